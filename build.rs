@@ -2,6 +2,27 @@ fn main() {
     tonic_build::configure()
         .compile(&["proto/auth.proto"], &["proto"])
         .unwrap();
+    // Compiled before account.proto since it imports it, and prost regenerates
+    // imported files without the account type attributes
+    tonic_build::configure()
+        .type_attribute(
+            "TransactionDesc",
+            "#[derive(serde::Serialize, serde::Deserialize)]",
+        )
+        .type_attribute(
+            "TransactionData",
+            "#[derive(serde::Serialize, serde::Deserialize)]",
+        )
+        .type_attribute(
+            "Transactions",
+            "#[derive(serde::Serialize, serde::Deserialize)]",
+        )
+        .type_attribute(
+            "TransactionStatus",
+            "#[derive(serde::Serialize, serde::Deserialize)]",
+        )
+        .compile(&["proto/transaction.proto"], &["proto"])
+        .unwrap();
     tonic_build::configure()
         .type_attribute(
             "CustomerData",
@@ -33,6 +54,14 @@ fn main() {
         )
         .type_attribute("Money", "#[derive(serde::Serialize, serde::Deserialize)]")
         .type_attribute("Source", "#[derive(serde::Serialize, serde::Deserialize)]")
+        .type_attribute(
+            "ProviderKind",
+            "#[derive(serde::Serialize, serde::Deserialize)] #[serde(rename_all = \"snake_case\")]",
+        )
+        .type_attribute(
+            "Providers",
+            "#[derive(serde::Serialize, serde::Deserialize)]",
+        )
         .type_attribute(
             "RoutingEntry",
             "#[derive(serde::Serialize, serde::Deserialize)]",
@@ -2,12 +2,15 @@ use std::str::FromStr;
 
 use bigdecimal::BigDecimal;
 use lunu::{
-    account::{self, PaymentMethod},
+    account::{self, FeeQuote, PartnerFeeSplit, PaymentMethod},
     diesel::{
-        insert_into, BoolExpressionMethods, ExpressionMethods, JoinOnDsl,
-        NullableExpressionMethods, QueryDsl,
+        delete, insert_into, BoolExpressionMethods, ExpressionMethods, JoinOnDsl,
+        NullableExpressionMethods, OptionalExtension, QueryDsl,
+    },
+    diesel_async::{
+        pooled_connection::bb8::Pool, scoped_futures::ScopedFutureExt, AsyncConnection,
+        AsyncPgConnection, RunQueryDsl,
     },
-    diesel_async::{pooled_connection::bb8::Pool, AsyncPgConnection, RunQueryDsl},
     schema,
 };
 use uuid::Uuid;
@@ -118,6 +121,8 @@ impl<'r> RetailerFees<'r> {
         })
     }
 
+    /// Upserts the fees of every entry. Fields missing from an entry keep their current
+    /// value, so they are only required when the payment method has no fees set yet.
     pub(crate) async fn set(
         &self,
         id: Uuid,
@@ -131,62 +136,304 @@ impl<'r> RetailerFees<'r> {
 
         use schema::retailer_fees::dsl as rf_dsl;
 
-        let mut parsed_fees = Vec::with_capacity(fees.len());
-        for fee in fees {
-            let payment_method_id = Uuid::from_str(&fee.payment_method_id)
-                .map_err(|_| AccountError::MalformedPaymentId)?;
-
-            let Some(retailer_fee) = fee.retailer_fee else {
-                continue;
-            };
-            let retailer_fee: BigDecimal = retailer_fee.into();
-
-            let Some(consumer_fee) = fee.consumer_fee else {
-                continue;
-            };
-            let consumer_fee: BigDecimal = consumer_fee.into();
-
-            let Some(exchange_spread) = fee.exchange_spread else {
-                continue;
-            };
-            let exchange_spread: BigDecimal = exchange_spread.into();
-
-            let Some(exchange_spread_stable_coin) = fee.exchange_spread_stable_coins else {
-                continue;
-            };
-            let exchange_spread_stable_coin: BigDecimal = exchange_spread_stable_coin.into();
-
-            let Some(min_transaction_fee) = fee.min_transaction_fee else {
-                continue;
-            };
-            let min_transaction_fee: BigDecimal = min_transaction_fee.into();
-
-            let Some(additional_fixed_fee) = fee.additional_fixed_fee else {
-                continue;
-            };
-            let (currency, amount): (String, BigDecimal) = additional_fixed_fee.into();
-
-            parsed_fees.push((
-                rf_dsl::payment_method_id.eq(payment_method_id),
-                rf_dsl::retailer_id.eq(id),
-                rf_dsl::retailer_fee.eq(retailer_fee),
-                rf_dsl::consumer_fee.eq(consumer_fee),
-                rf_dsl::exchange_spread.eq(exchange_spread),
-                rf_dsl::exchange_spread_stable_coin.eq(exchange_spread_stable_coin),
-                rf_dsl::min_transaction_fee.eq(min_transaction_fee),
-                rf_dsl::base_additional_fixed_fee_amount.eq(amount),
-                rf_dsl::base_additional_fixed_fee_currency.eq(currency),
-            ));
-        }
+        conn.transaction::<_, AccountError, _>(|conn| {
+            async move {
+                for fee in fees {
+                    let payment_method_id = Uuid::from_str(&fee.payment_method_id)
+                        .map_err(|_| AccountError::MalformedPaymentId)?;
+
+                    let current = rf_dsl::retailer_fees
+                        .filter(
+                            rf_dsl::retailer_id
+                                .eq(id)
+                                .and(rf_dsl::payment_method_id.eq(payment_method_id)),
+                        )
+                        .select((
+                            rf_dsl::retailer_fee,
+                            rf_dsl::consumer_fee,
+                            rf_dsl::exchange_spread,
+                            rf_dsl::exchange_spread_stable_coin,
+                            rf_dsl::min_transaction_fee,
+                            rf_dsl::base_additional_fixed_fee_amount,
+                            rf_dsl::base_additional_fixed_fee_currency,
+                        ))
+                        .for_update()
+                        .first::<(
+                            BigDecimal,
+                            BigDecimal,
+                            BigDecimal,
+                            BigDecimal,
+                            BigDecimal,
+                            BigDecimal,
+                            String,
+                        )>(conn)
+                        .await
+                        .optional()?;
+                    let (
+                        current_retailer_fee,
+                        current_consumer_fee,
+                        current_exchange_spread,
+                        current_exchange_spread_stable_coin,
+                        current_min_transaction_fee,
+                        current_fixed_fee,
+                    ) = match current {
+                        Some((
+                            retailer_fee,
+                            consumer_fee,
+                            exchange_spread,
+                            exchange_spread_stable_coin,
+                            min_transaction_fee,
+                            amount,
+                            currency,
+                        )) => (
+                            Some(retailer_fee),
+                            Some(consumer_fee),
+                            Some(exchange_spread),
+                            Some(exchange_spread_stable_coin),
+                            Some(min_transaction_fee),
+                            Some((currency, amount)),
+                        ),
+                        None => (None, None, None, None, None, None),
+                    };
+
+                    let retailer_fee: BigDecimal = fee
+                        .retailer_fee
+                        .map(|f| f.into())
+                        .or(current_retailer_fee)
+                        .ok_or(AccountError::MissingFee("retailer_fee"))?;
+                    let consumer_fee: BigDecimal = fee
+                        .consumer_fee
+                        .map(|f| f.into())
+                        .or(current_consumer_fee)
+                        .ok_or(AccountError::MissingFee("consumer_fee"))?;
+                    let exchange_spread: BigDecimal = fee
+                        .exchange_spread
+                        .map(|f| f.into())
+                        .or(current_exchange_spread)
+                        .ok_or(AccountError::MissingFee("exchange_spread"))?;
+                    let exchange_spread_stable_coin: BigDecimal = fee
+                        .exchange_spread_stable_coins
+                        .map(|f| f.into())
+                        .or(current_exchange_spread_stable_coin)
+                        .ok_or(AccountError::MissingFee("exchange_spread_stable_coins"))?;
+                    let min_transaction_fee: BigDecimal = fee
+                        .min_transaction_fee
+                        .map(|f| f.into())
+                        .or(current_min_transaction_fee)
+                        .ok_or(AccountError::MissingFee("min_transaction_fee"))?;
+                    let (currency, amount): (String, BigDecimal) = fee
+                        .additional_fixed_fee
+                        .map(|f| f.into())
+                        .or(current_fixed_fee)
+                        .ok_or(AccountError::MissingFee("additional_fixed_fee"))?;
+
+                    insert_into(rf_dsl::retailer_fees)
+                        .values((
+                            rf_dsl::payment_method_id.eq(payment_method_id),
+                            rf_dsl::retailer_id.eq(id),
+                            rf_dsl::retailer_fee.eq(&retailer_fee),
+                            rf_dsl::consumer_fee.eq(&consumer_fee),
+                            rf_dsl::exchange_spread.eq(&exchange_spread),
+                            rf_dsl::exchange_spread_stable_coin.eq(&exchange_spread_stable_coin),
+                            rf_dsl::min_transaction_fee.eq(&min_transaction_fee),
+                            rf_dsl::base_additional_fixed_fee_amount.eq(&amount),
+                            rf_dsl::base_additional_fixed_fee_currency.eq(&currency),
+                        ))
+                        .on_conflict((rf_dsl::payment_method_id, rf_dsl::retailer_id))
+                        .do_update()
+                        .set((
+                            rf_dsl::retailer_fee.eq(&retailer_fee),
+                            rf_dsl::consumer_fee.eq(&consumer_fee),
+                            rf_dsl::exchange_spread.eq(&exchange_spread),
+                            rf_dsl::exchange_spread_stable_coin.eq(&exchange_spread_stable_coin),
+                            rf_dsl::min_transaction_fee.eq(&min_transaction_fee),
+                            rf_dsl::base_additional_fixed_fee_amount.eq(&amount),
+                            rf_dsl::base_additional_fixed_fee_currency.eq(&currency),
+                        ))
+                        .execute(conn)
+                        .await?;
+                }
+
+                Ok(())
+            }
+            .scope_boxed()
+        })
+        .await
+    }
 
-        insert_into(rf_dsl::retailer_fees)
-            .values(parsed_fees)
+    pub(crate) async fn delete(
+        &self,
+        id: Uuid,
+        payment_method_id: Uuid,
+    ) -> Result<(), AccountError> {
+        let conn = &mut self
+            .0
+            .get()
+            .await
+            .map_err(|_| AccountError::PoolConnectionFailed)?;
+
+        use schema::retailer_fees::dsl as rf_dsl;
+
+        let deleted = delete(rf_dsl::retailer_fees)
+            .filter(
+                rf_dsl::retailer_id
+                    .eq(id)
+                    .and(rf_dsl::payment_method_id.eq(payment_method_id)),
+            )
             .execute(conn)
             .await
             .map_err(|e| AccountError::QueryFailed(e.to_string()))?;
 
+        if deleted == 0 {
+            return Err(AccountError::FeesNotFound);
+        }
+
         Ok(())
     }
+
+    /// Computes the fees for a customer paying `amount` through the payment method.
+    ///
+    /// The stored fees are percentages of the amount, except for the minimum transaction
+    /// fee which is the smallest total fee in the currency of the amount.
+    pub(crate) async fn quote(
+        &self,
+        id: Uuid,
+        payment_method_id: Uuid,
+        (currency, amount): (String, BigDecimal),
+        stable_coin: bool,
+    ) -> Result<FeeQuote, AccountError> {
+        let conn = &mut self
+            .0
+            .get()
+            .await
+            .map_err(|_| AccountError::PoolConnectionFailed)?;
+
+        use schema::retailer_fees::dsl as rf_dsl;
+
+        let (
+            retailer_fee,
+            consumer_fee,
+            exchange_spread,
+            exchange_spread_stable_coin,
+            min_transaction_fee,
+            fixed_fee_amount,
+            fixed_fee_currency,
+        ) = rf_dsl::retailer_fees
+            .filter(
+                rf_dsl::retailer_id
+                    .eq(id)
+                    .and(rf_dsl::payment_method_id.eq(payment_method_id)),
+            )
+            .select((
+                rf_dsl::retailer_fee,
+                rf_dsl::consumer_fee,
+                rf_dsl::exchange_spread,
+                rf_dsl::exchange_spread_stable_coin,
+                rf_dsl::min_transaction_fee,
+                rf_dsl::base_additional_fixed_fee_amount,
+                rf_dsl::base_additional_fixed_fee_currency,
+            ))
+            .load::<(
+                BigDecimal,
+                BigDecimal,
+                BigDecimal,
+                BigDecimal,
+                BigDecimal,
+                BigDecimal,
+                String,
+            )>(conn)
+            .await
+            .map_err(|e| AccountError::QueryFailed(e.to_string()))?
+            .pop()
+            .ok_or(AccountError::FeesNotFound)?;
+        if fixed_fee_currency != currency {
+            return Err(AccountError::FeeCurrencyMismatch(
+                fixed_fee_currency,
+                currency,
+            ));
+        }
+
+        use schema::partner_fees::dsl as pf_dsl;
+        use schema::retailer_partners::dsl as rp_dsl;
+
+        let partner_fees = rp_dsl::retailer_partners
+            .inner_join(pf_dsl::partner_fees.on(pf_dsl::partner_id.eq(rp_dsl::partner_id)))
+            .filter(
+                rp_dsl::retailer_id
+                    .eq(id)
+                    .and(pf_dsl::payment_method_id.eq(payment_method_id)),
+            )
+            .select((
+                pf_dsl::partner_id,
+                pf_dsl::referral_partner_fee,
+                pf_dsl::additional_fixed_fee_amount,
+                pf_dsl::additional_fixed_fee_currency,
+            ))
+            .load::<(Uuid, BigDecimal, BigDecimal, String)>(conn)
+            .await
+            .map_err(|e| AccountError::QueryFailed(e.to_string()))?;
+
+        let hundred = BigDecimal::from(100);
+        let consumer_fee = &amount * consumer_fee / &hundred;
+        let retailer_fee = &amount * retailer_fee / &hundred;
+        let exchange_spread = if stable_coin {
+            &amount * exchange_spread_stable_coin / &hundred
+        } else {
+            &amount * exchange_spread / &hundred
+        };
+
+        let mut total_fee = &consumer_fee + &exchange_spread + &fixed_fee_amount;
+        let min_fee_applied = total_fee < min_transaction_fee;
+        if min_fee_applied {
+            total_fee = min_transaction_fee.clone();
+        }
+        // The net amount would be negative
+        if total_fee > amount {
+            return Err(AccountError::AmountBelowFee(currency, total_fee));
+        }
+
+        let mut partner_splits = Vec::with_capacity(partner_fees.len());
+        let mut partner_total = BigDecimal::from(0);
+        for (partner_id, partner_fee, partner_fixed_amount, partner_fixed_currency) in partner_fees
+        {
+            if partner_fixed_currency != currency {
+                return Err(AccountError::FeeCurrencyMismatch(
+                    partner_fixed_currency,
+                    currency,
+                ));
+            }
+
+            let split = &amount * partner_fee / &hundred + partner_fixed_amount;
+            partner_total += &split;
+            partner_splits.push(PartnerFeeSplit {
+                partner_id: partner_id.to_string(),
+                amount: Some((currency.clone(), split).into()),
+            });
+        }
+        // The partners are paid out of the fee
+        if partner_total > total_fee {
+            return Err(AccountError::PartnerFeesExceedFee(
+                currency,
+                partner_total,
+                total_fee,
+            ));
+        }
+
+        let net_amount = &amount - &total_fee;
+
+        Ok(FeeQuote {
+            amount: Some((currency.clone(), amount).into()),
+            consumer_fee: Some((currency.clone(), consumer_fee).into()),
+            retailer_fee: Some((currency.clone(), retailer_fee).into()),
+            exchange_spread: Some((currency.clone(), exchange_spread).into()),
+            additional_fixed_fee: Some((currency.clone(), fixed_fee_amount).into()),
+            min_transaction_fee: Some((currency.clone(), min_transaction_fee).into()),
+            min_fee_applied,
+            total_fee: Some((currency.clone(), total_fee).into()),
+            partner_fees: partner_splits,
+            net_amount: Some((currency, net_amount).into()),
+        })
+    }
 }
 
 pub struct PartnerFees<'r>(pub &'r Pool<AsyncPgConnection>);
@@ -237,6 +484,8 @@ impl<'r> PartnerFees<'r> {
         })
     }
 
+    /// Upserts the fees of every entry. Fields missing from an entry keep their current
+    /// value, so they are only required when the payment method has no fees set yet.
     pub(crate) async fn set(
         &self,
         id: Uuid,
@@ -250,35 +499,98 @@ impl<'r> PartnerFees<'r> {
 
         use schema::partner_fees::dsl as pf_dsl;
 
-        let mut parsed_fees = Vec::with_capacity(fees.len());
-        for fee in fees {
-            let payment_method_id = Uuid::from_str(&fee.payment_method_id)
-                .map_err(|_| AccountError::MalformedPaymentId)?;
-
-            let Some(referral_partner_fee) = fee.partner_fee else {
-                continue;
-            };
-            let Some(additional_fixed_fee) = fee.additional_fixed_fee else {
-                continue;
-            };
-            let referral_partner_fee: BigDecimal = referral_partner_fee.into();
-            let (currency, amount): (String, BigDecimal) = additional_fixed_fee.into();
-
-            parsed_fees.push((
-                pf_dsl::payment_method_id.eq(payment_method_id),
-                pf_dsl::partner_id.eq(id),
-                pf_dsl::referral_partner_fee.eq(referral_partner_fee),
-                pf_dsl::additional_fixed_fee_amount.eq(amount),
-                pf_dsl::additional_fixed_fee_currency.eq(currency),
-            ));
-        }
+        conn.transaction::<_, AccountError, _>(|conn| {
+            async move {
+                for fee in fees {
+                    let payment_method_id = Uuid::from_str(&fee.payment_method_id)
+                        .map_err(|_| AccountError::MalformedPaymentId)?;
+
+                    let current = pf_dsl::partner_fees
+                        .filter(
+                            pf_dsl::partner_id
+                                .eq(id)
+                                .and(pf_dsl::payment_method_id.eq(payment_method_id)),
+                        )
+                        .select((
+                            pf_dsl::referral_partner_fee,
+                            pf_dsl::additional_fixed_fee_currency,
+                            pf_dsl::additional_fixed_fee_amount,
+                        ))
+                        .for_update()
+                        .first::<(BigDecimal, String, BigDecimal)>(conn)
+                        .await
+                        .optional()?;
+                    let (current_partner_fee, current_fixed_fee) = match current {
+                        Some((partner_fee, currency, amount)) => {
+                            (Some(partner_fee), Some((currency, amount)))
+                        }
+                        None => (None, None),
+                    };
+
+                    let referral_partner_fee: BigDecimal = fee
+                        .partner_fee
+                        .map(|f| f.into())
+                        .or(current_partner_fee)
+                        .ok_or(AccountError::MissingFee("partner_fee"))?;
+                    let (currency, amount): (String, BigDecimal) = fee
+                        .additional_fixed_fee
+                        .map(|f| f.into())
+                        .or(current_fixed_fee)
+                        .ok_or(AccountError::MissingFee("additional_fixed_fee"))?;
+
+                    insert_into(pf_dsl::partner_fees)
+                        .values((
+                            pf_dsl::payment_method_id.eq(payment_method_id),
+                            pf_dsl::partner_id.eq(id),
+                            pf_dsl::referral_partner_fee.eq(&referral_partner_fee),
+                            pf_dsl::additional_fixed_fee_amount.eq(&amount),
+                            pf_dsl::additional_fixed_fee_currency.eq(&currency),
+                        ))
+                        .on_conflict((pf_dsl::payment_method_id, pf_dsl::partner_id))
+                        .do_update()
+                        .set((
+                            pf_dsl::referral_partner_fee.eq(&referral_partner_fee),
+                            pf_dsl::additional_fixed_fee_amount.eq(&amount),
+                            pf_dsl::additional_fixed_fee_currency.eq(&currency),
+                        ))
+                        .execute(conn)
+                        .await?;
+                }
+
+                Ok(())
+            }
+            .scope_boxed()
+        })
+        .await
+    }
 
-        insert_into(pf_dsl::partner_fees)
-            .values(parsed_fees)
+    pub(crate) async fn delete(
+        &self,
+        id: Uuid,
+        payment_method_id: Uuid,
+    ) -> Result<(), AccountError> {
+        let conn = &mut self
+            .0
+            .get()
+            .await
+            .map_err(|_| AccountError::PoolConnectionFailed)?;
+
+        use schema::partner_fees::dsl as pf_dsl;
+
+        let deleted = delete(pf_dsl::partner_fees)
+            .filter(
+                pf_dsl::partner_id
+                    .eq(id)
+                    .and(pf_dsl::payment_method_id.eq(payment_method_id)),
+            )
             .execute(conn)
             .await
             .map_err(|e| AccountError::QueryFailed(e.to_string()))?;
 
+        if deleted == 0 {
+            return Err(AccountError::FeesNotFound);
+        }
+
         Ok(())
     }
 }
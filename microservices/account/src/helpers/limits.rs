@@ -0,0 +1,44 @@
+use std::ops::DerefMut;
+
+use bigdecimal::BigDecimal;
+use lunu::{
+    account::LimitCheck,
+    diesel_async::{pooled_connection::bb8::Pool, AsyncPgConnection},
+    limits::{self, LimitError},
+};
+use uuid::Uuid;
+
+use crate::AccountError;
+
+pub struct Limits<'l>(pub &'l Pool<AsyncPgConnection>);
+
+impl<'l> Limits<'l> {
+    /// Checks if the customer can transact `amount` without going over any of its limits,
+    /// see `lunu::limits::check`.
+    pub(crate) async fn check(
+        &self,
+        customer_id: Uuid,
+        retailer_id: Option<Uuid>,
+        amount: (String, BigDecimal),
+    ) -> Result<LimitCheck, AccountError> {
+        let mut conn = self
+            .0
+            .get()
+            .await
+            .map_err(|_| AccountError::PoolConnectionFailed)?;
+
+        Ok(limits::check(conn.deref_mut(), customer_id, retailer_id, amount).await?)
+    }
+}
+
+impl From<LimitError> for AccountError {
+    fn from(value: LimitError) -> Self {
+        match value {
+            LimitError::CustomerNotFound => AccountError::CustomerNotFound,
+            LimitError::CurrencyMismatch(limit, amount) => {
+                AccountError::LimitCurrencyMismatch(limit, amount)
+            }
+            LimitError::QueryFailed(s) => AccountError::QueryFailed(s),
+        }
+    }
+}
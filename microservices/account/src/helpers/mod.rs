@@ -1,2 +1,4 @@
 pub mod fees;
+pub mod limits;
+pub mod providers;
 pub mod routing;
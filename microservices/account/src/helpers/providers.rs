@@ -0,0 +1,228 @@
+use lunu::{
+    account::{ProviderKind, Source},
+    diesel::{delete, insert_into, select, update, ExpressionMethods, QueryDsl},
+    diesel_async::{
+        pooled_connection::bb8::Pool, scoped_futures::ScopedFutureExt, AsyncConnection,
+        AsyncPgConnection, RunQueryDsl,
+    },
+    schema,
+};
+use uuid::Uuid;
+
+use crate::AccountError;
+
+/// Runs `$body` with `$table` bound to the schema module of the catalogue for `$kind`.
+macro_rules! with_catalogue {
+    ($kind:expr, $table:ident => $body:expr) => {
+        match $kind {
+            ProviderKind::PaymentGateways => {
+                use schema::payment_gateways as $table;
+                $body
+            }
+            ProviderKind::CustodyProviders => {
+                use schema::custody_providers as $table;
+                $body
+            }
+            ProviderKind::ExchangeProviders => {
+                use schema::exchange_providers as $table;
+                $body
+            }
+            ProviderKind::PaymentMethods => {
+                use schema::payment_methods as $table;
+                $body
+            }
+        }
+    };
+}
+
+pub struct Providers<'p>(pub &'p Pool<AsyncPgConnection>);
+
+impl<'p> Providers<'p> {
+    pub(crate) async fn create(
+        &self,
+        kind: ProviderKind,
+        name: String,
+    ) -> Result<Uuid, AccountError> {
+        let conn = &mut self
+            .0
+            .get()
+            .await
+            .map_err(|_| AccountError::PoolConnectionFailed)?;
+
+        let id = Uuid::new_v4();
+        with_catalogue!(kind, c => insert_into(c::table)
+            .values((c::id.eq(id), c::name.eq(name)))
+            .execute(conn)
+            .await)
+        .map_err(|e| AccountError::QueryFailed(e.to_string()))?;
+
+        Ok(id)
+    }
+
+    pub(crate) async fn list(&self, kind: ProviderKind) -> Result<Vec<Source>, AccountError> {
+        let conn = &mut self
+            .0
+            .get()
+            .await
+            .map_err(|_| AccountError::PoolConnectionFailed)?;
+
+        let providers = with_catalogue!(kind, c => c::table
+            .select((c::id, c::name))
+            .order(c::name)
+            .load::<(Uuid, String)>(conn)
+            .await)
+        .map_err(|e| AccountError::QueryFailed(e.to_string()))?;
+
+        Ok(providers
+            .into_iter()
+            .map(|provider| provider.into())
+            .collect())
+    }
+
+    pub(crate) async fn rename(
+        &self,
+        kind: ProviderKind,
+        id: Uuid,
+        name: String,
+    ) -> Result<(), AccountError> {
+        let conn = &mut self
+            .0
+            .get()
+            .await
+            .map_err(|_| AccountError::PoolConnectionFailed)?;
+
+        let updated = with_catalogue!(kind, c => update(c::table)
+            .filter(c::id.eq(id))
+            .set(c::name.eq(name))
+            .execute(conn)
+            .await)
+        .map_err(|e| AccountError::QueryFailed(e.to_string()))?;
+
+        if updated == 0 {
+            return Err(AccountError::ProviderNotFound);
+        }
+
+        Ok(())
+    }
+
+    /// Deletes the provider, refusing to do so while any routing or fee row still uses it.
+    pub(crate) async fn delete(&self, kind: ProviderKind, id: Uuid) -> Result<(), AccountError> {
+        let conn = &mut self
+            .0
+            .get()
+            .await
+            .map_err(|_| AccountError::PoolConnectionFailed)?;
+
+        conn.transaction::<_, AccountError, _>(|conn| {
+            async move {
+                if is_referenced(conn, kind, id).await? {
+                    return Err(AccountError::ProviderInUse);
+                }
+
+                let deleted = with_catalogue!(kind, c => delete(c::table)
+                    .filter(c::id.eq(id))
+                    .execute(conn)
+                    .await)?;
+
+                if deleted == 0 {
+                    return Err(AccountError::ProviderNotFound);
+                }
+
+                Ok(())
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+}
+
+async fn is_referenced(
+    conn: &mut AsyncPgConnection,
+    kind: ProviderKind,
+    id: Uuid,
+) -> Result<bool, AccountError> {
+    use lunu::diesel::dsl::exists;
+
+    let referenced = match kind {
+        ProviderKind::PaymentGateways => {
+            use schema::customer_payment_gateway_routing::dsl as cpgr_dsl;
+            use schema::global_payment_gateway_routing::dsl as gpgr_dsl;
+            use schema::retailer_payment_gateway_routing::dsl as rpgr_dsl;
+
+            select(exists(
+                cpgr_dsl::customer_payment_gateway_routing.filter(cpgr_dsl::selected.eq(id)),
+            ))
+            .get_result::<bool>(conn)
+            .await?
+                || select(exists(
+                    rpgr_dsl::retailer_payment_gateway_routing.filter(rpgr_dsl::selected.eq(id)),
+                ))
+                .get_result::<bool>(conn)
+                .await?
+                || select(exists(
+                    gpgr_dsl::global_payment_gateway_routing.filter(gpgr_dsl::selected.eq(id)),
+                ))
+                .get_result::<bool>(conn)
+                .await?
+        }
+        ProviderKind::CustodyProviders => {
+            use schema::customer_custody_provider_routing::dsl as ccpr_dsl;
+            use schema::global_custody_provider_routing::dsl as gcpr_dsl;
+            use schema::retailer_custody_provider_routing::dsl as rcpr_dsl;
+
+            select(exists(
+                ccpr_dsl::customer_custody_provider_routing.filter(ccpr_dsl::selected.eq(id)),
+            ))
+            .get_result::<bool>(conn)
+            .await?
+                || select(exists(
+                    rcpr_dsl::retailer_custody_provider_routing.filter(rcpr_dsl::selected.eq(id)),
+                ))
+                .get_result::<bool>(conn)
+                .await?
+                || select(exists(
+                    gcpr_dsl::global_custody_provider_routing.filter(gcpr_dsl::selected.eq(id)),
+                ))
+                .get_result::<bool>(conn)
+                .await?
+        }
+        ProviderKind::ExchangeProviders => {
+            use schema::customer_exchange_provider_routing::dsl as cepr_dsl;
+            use schema::global_exchange_provider_routing::dsl as gepr_dsl;
+            use schema::retailer_exchange_provider_routing::dsl as repr_dsl;
+
+            select(exists(
+                cepr_dsl::customer_exchange_provider_routing.filter(cepr_dsl::selected.eq(id)),
+            ))
+            .get_result::<bool>(conn)
+            .await?
+                || select(exists(
+                    repr_dsl::retailer_exchange_provider_routing.filter(repr_dsl::selected.eq(id)),
+                ))
+                .get_result::<bool>(conn)
+                .await?
+                || select(exists(
+                    gepr_dsl::global_exchange_provider_routing.filter(gepr_dsl::selected.eq(id)),
+                ))
+                .get_result::<bool>(conn)
+                .await?
+        }
+        ProviderKind::PaymentMethods => {
+            use schema::partner_fees::dsl as pf_dsl;
+            use schema::retailer_fees::dsl as rf_dsl;
+
+            select(exists(
+                rf_dsl::retailer_fees.filter(rf_dsl::payment_method_id.eq(id)),
+            ))
+            .get_result::<bool>(conn)
+            .await?
+                || select(exists(
+                    pf_dsl::partner_fees.filter(pf_dsl::payment_method_id.eq(id)),
+                ))
+                .get_result::<bool>(conn)
+                .await?
+        }
+    };
+
+    Ok(referenced)
+}
@@ -2,9 +2,15 @@ use std::str::FromStr;
 
 use bigdecimal::BigDecimal;
 use lunu::{
-    account::{Routing, RoutingEntry},
-    diesel::{insert_into, ExpressionMethods, JoinOnDsl, NullableExpressionMethods, QueryDsl},
-    diesel_async::{pooled_connection::bb8::Pool, AsyncPgConnection, RunQueryDsl},
+    account::{ResolvedRoute, ResolvedRouting, Routing, RoutingEntry, RoutingLevel},
+    diesel::{
+        delete, insert_into, update, upsert::excluded, BoolExpressionMethods, ExpressionMethods,
+        JoinOnDsl, NullableExpressionMethods, QueryDsl,
+    },
+    diesel_async::{
+        pooled_connection::bb8::Pool, scoped_futures::ScopedFutureExt, AsyncConnection,
+        AsyncPgConnection, RunQueryDsl,
+    },
     models, schema,
 };
 use uuid::Uuid;
@@ -42,6 +48,7 @@ impl RoutingTable for CustomerRouting {
         let payment_gateways = cpgr_dsl::customer_payment_gateway_routing
             .inner_join(pg_dsl::payment_gateways.on(pg_dsl::id.eq(cpgr_dsl::selected)))
             .filter(cpgr_dsl::customer_id.eq(id))
+            .order(cpgr_dsl::idx)
             .select((
                 pg_dsl::id,
                 pg_dsl::name,
@@ -64,6 +71,7 @@ impl RoutingTable for CustomerRouting {
         let custody_providers = ccpr_dsl::customer_custody_provider_routing
             .inner_join(cp_dsl::custody_providers.on(cp_dsl::id.eq(ccpr_dsl::selected)))
             .filter(ccpr_dsl::customer_id.eq(id))
+            .order(ccpr_dsl::idx)
             .select((
                 cp_dsl::id,
                 cp_dsl::name,
@@ -86,6 +94,7 @@ impl RoutingTable for CustomerRouting {
         let exchange_providers = cepr_dsl::customer_exchange_provider_routing
             .inner_join(ep_dsl::exchange_providers.on(ep_dsl::id.eq(cepr_dsl::selected)))
             .filter(cepr_dsl::customer_id.eq(id))
+            .order(cepr_dsl::idx)
             .select((
                 ep_dsl::id,
                 ep_dsl::name,
@@ -123,100 +132,162 @@ impl RoutingTable for CustomerRouting {
             .await
             .map_err(|_| AccountError::PoolConnectionFailed)?;
 
-        use schema::customer_payment_gateway_routing::dsl as cpgr_dsl;
-
-        let mut parsed_payment_gateways = Vec::with_capacity(payment_gateways.len());
-
-        for (idx, entry) in payment_gateways.into_iter().enumerate() {
-            let Some(source) = entry.source else {
-                continue;
-            };
-            let Some(amount) = entry.amount else {
-                continue;
-            };
-            let selected_id = Uuid::from_str(&source.id)
-                .map_err(|_| AccountError::MalformedRoutingSourceToken)?;
-            let (currency, amount): (String, BigDecimal) = amount.into();
-
-            parsed_payment_gateways.push((
-                cpgr_dsl::idx.eq(models::ProfileIndex::try_from(idx)
-                    .map_err(|_| AccountError::TooManyRouteEntries)?),
-                cpgr_dsl::customer_id.eq(id),
-                cpgr_dsl::selected.eq(selected_id),
-                cpgr_dsl::amount.eq(amount),
-                cpgr_dsl::currency.eq(currency),
-            ));
-        }
-
-        insert_into(cpgr_dsl::customer_payment_gateway_routing)
-            .values(&parsed_payment_gateways)
-            .execute(conn)
-            .await
-            .map_err(|e| AccountError::QueryFailed(e.to_string()))?;
-
-        use schema::customer_custody_provider_routing::dsl as ccpr_dsl;
-
-        let mut parsed_custody_providers = Vec::with_capacity(custody_providers.len());
-
-        for (idx, entry) in custody_providers.into_iter().enumerate() {
-            let Some(source) = entry.source else {
-                    continue;
-                };
-            let Some(amount) = entry.amount else {
-                    continue;
-                };
-            let selected_id = Uuid::from_str(&source.id)
-                .map_err(|_| AccountError::MalformedRoutingSourceToken)?;
-            let (currency, amount): (String, BigDecimal) = amount.into();
-
-            parsed_custody_providers.push((
-                ccpr_dsl::idx.eq(models::ProfileIndex::try_from(idx)
-                    .map_err(|_| AccountError::TooManyRouteEntries)?),
-                ccpr_dsl::customer_id.eq(id),
-                ccpr_dsl::selected.eq(selected_id),
-                ccpr_dsl::amount.eq(amount),
-                ccpr_dsl::currency.eq(currency),
-            ));
-        }
-
-        insert_into(ccpr_dsl::customer_custody_provider_routing)
-            .values(&parsed_custody_providers)
-            .execute(conn)
-            .await
-            .map_err(|e| AccountError::QueryFailed(e.to_string()))?;
-
-        use schema::customer_exchange_provider_routing::dsl as cepr_dsl;
+        conn.transaction::<_, AccountError, _>(|conn| {
+            async move {
+                use schema::customer_payment_gateway_routing::dsl as cpgr_dsl;
 
-        let mut parsed_exchange_providers = Vec::with_capacity(exchange_providers.len());
+                let mut parsed_payment_gateways = Vec::with_capacity(payment_gateways.len());
+                let mut payment_gateway_idxs = Vec::with_capacity(payment_gateways.len());
 
-        for (idx, entry) in exchange_providers.into_iter().enumerate() {
-            let Some(source) = entry.source else {
+                for (idx, entry) in payment_gateways.into_iter().enumerate() {
+                    let Some(source) = entry.source else {
                         continue;
                     };
-            let Some(amount) = entry.amount else {
+                    let Some(amount) = entry.amount else {
                         continue;
                     };
-            let selected_id = Uuid::from_str(&source.id)
-                .map_err(|_| AccountError::MalformedRoutingSourceToken)?;
-            let (currency, amount): (String, BigDecimal) = amount.into();
-
-            parsed_exchange_providers.push((
-                cepr_dsl::idx.eq(models::ProfileIndex::try_from(idx)
-                    .map_err(|_| AccountError::TooManyRouteEntries)?),
-                cepr_dsl::customer_id.eq(id),
-                cepr_dsl::selected.eq(selected_id),
-                cepr_dsl::amount.eq(amount),
-                cepr_dsl::currency.eq(currency),
-            ));
-        }
-
-        insert_into(cepr_dsl::customer_exchange_provider_routing)
-            .values(&parsed_exchange_providers)
-            .execute(conn)
-            .await
-            .map_err(|e| AccountError::QueryFailed(e.to_string()))?;
-
-        Ok(())
+                    let idx = models::ProfileIndex::try_from(idx)
+                        .map_err(|_| AccountError::TooManyRouteEntries)?;
+                    let selected_id = Uuid::from_str(&source.id)
+                        .map_err(|_| AccountError::MalformedRoutingSourceToken)?;
+                    let (currency, amount): (String, BigDecimal) = amount.into();
+
+                    payment_gateway_idxs.push(idx);
+                    parsed_payment_gateways.push((
+                        cpgr_dsl::idx.eq(idx),
+                        cpgr_dsl::customer_id.eq(id),
+                        cpgr_dsl::selected.eq(selected_id),
+                        cpgr_dsl::amount.eq(amount),
+                        cpgr_dsl::currency.eq(currency),
+                    ));
+                }
+
+                delete(cpgr_dsl::customer_payment_gateway_routing)
+                    .filter(
+                        cpgr_dsl::customer_id
+                            .eq(id)
+                            .and(cpgr_dsl::idx.ne_all(payment_gateway_idxs)),
+                    )
+                    .execute(conn)
+                    .await?;
+                if !parsed_payment_gateways.is_empty() {
+                    insert_into(cpgr_dsl::customer_payment_gateway_routing)
+                        .values(&parsed_payment_gateways)
+                        .on_conflict((cpgr_dsl::idx, cpgr_dsl::customer_id))
+                        .do_update()
+                        .set((
+                            cpgr_dsl::selected.eq(excluded(cpgr_dsl::selected)),
+                            cpgr_dsl::amount.eq(excluded(cpgr_dsl::amount)),
+                            cpgr_dsl::currency.eq(excluded(cpgr_dsl::currency)),
+                        ))
+                        .execute(conn)
+                        .await?;
+                }
+
+                use schema::customer_custody_provider_routing::dsl as ccpr_dsl;
+
+                let mut parsed_custody_providers = Vec::with_capacity(custody_providers.len());
+                let mut custody_provider_idxs = Vec::with_capacity(custody_providers.len());
+
+                for (idx, entry) in custody_providers.into_iter().enumerate() {
+                    let Some(source) = entry.source else {
+                        continue;
+                    };
+                    let Some(amount) = entry.amount else {
+                        continue;
+                    };
+                    let idx = models::ProfileIndex::try_from(idx)
+                        .map_err(|_| AccountError::TooManyRouteEntries)?;
+                    let selected_id = Uuid::from_str(&source.id)
+                        .map_err(|_| AccountError::MalformedRoutingSourceToken)?;
+                    let (currency, amount): (String, BigDecimal) = amount.into();
+
+                    custody_provider_idxs.push(idx);
+                    parsed_custody_providers.push((
+                        ccpr_dsl::idx.eq(idx),
+                        ccpr_dsl::customer_id.eq(id),
+                        ccpr_dsl::selected.eq(selected_id),
+                        ccpr_dsl::amount.eq(amount),
+                        ccpr_dsl::currency.eq(currency),
+                    ));
+                }
+
+                delete(ccpr_dsl::customer_custody_provider_routing)
+                    .filter(
+                        ccpr_dsl::customer_id
+                            .eq(id)
+                            .and(ccpr_dsl::idx.ne_all(custody_provider_idxs)),
+                    )
+                    .execute(conn)
+                    .await?;
+                if !parsed_custody_providers.is_empty() {
+                    insert_into(ccpr_dsl::customer_custody_provider_routing)
+                        .values(&parsed_custody_providers)
+                        .on_conflict((ccpr_dsl::idx, ccpr_dsl::customer_id))
+                        .do_update()
+                        .set((
+                            ccpr_dsl::selected.eq(excluded(ccpr_dsl::selected)),
+                            ccpr_dsl::amount.eq(excluded(ccpr_dsl::amount)),
+                            ccpr_dsl::currency.eq(excluded(ccpr_dsl::currency)),
+                        ))
+                        .execute(conn)
+                        .await?;
+                }
+
+                use schema::customer_exchange_provider_routing::dsl as cepr_dsl;
+
+                let mut parsed_exchange_providers = Vec::with_capacity(exchange_providers.len());
+                let mut exchange_provider_idxs = Vec::with_capacity(exchange_providers.len());
+
+                for (idx, entry) in exchange_providers.into_iter().enumerate() {
+                    let Some(source) = entry.source else {
+                        continue;
+                    };
+                    let Some(amount) = entry.amount else {
+                        continue;
+                    };
+                    let idx = models::ProfileIndex::try_from(idx)
+                        .map_err(|_| AccountError::TooManyRouteEntries)?;
+                    let selected_id = Uuid::from_str(&source.id)
+                        .map_err(|_| AccountError::MalformedRoutingSourceToken)?;
+                    let (currency, amount): (String, BigDecimal) = amount.into();
+
+                    exchange_provider_idxs.push(idx);
+                    parsed_exchange_providers.push((
+                        cepr_dsl::idx.eq(idx),
+                        cepr_dsl::customer_id.eq(id),
+                        cepr_dsl::selected.eq(selected_id),
+                        cepr_dsl::amount.eq(amount),
+                        cepr_dsl::currency.eq(currency),
+                    ));
+                }
+
+                delete(cepr_dsl::customer_exchange_provider_routing)
+                    .filter(
+                        cepr_dsl::customer_id
+                            .eq(id)
+                            .and(cepr_dsl::idx.ne_all(exchange_provider_idxs)),
+                    )
+                    .execute(conn)
+                    .await?;
+                if !parsed_exchange_providers.is_empty() {
+                    insert_into(cepr_dsl::customer_exchange_provider_routing)
+                        .values(&parsed_exchange_providers)
+                        .on_conflict((cepr_dsl::idx, cepr_dsl::customer_id))
+                        .do_update()
+                        .set((
+                            cepr_dsl::selected.eq(excluded(cepr_dsl::selected)),
+                            cepr_dsl::amount.eq(excluded(cepr_dsl::amount)),
+                            cepr_dsl::currency.eq(excluded(cepr_dsl::currency)),
+                        ))
+                        .execute(conn)
+                        .await?;
+                }
+                Ok(())
+            }
+            .scope_boxed()
+        })
+        .await
     }
 }
 
@@ -238,6 +309,7 @@ impl RoutingTable for RetailerRouting {
         let payment_gateways = rpgr_dsl::retailer_payment_gateway_routing
             .inner_join(pg_dsl::payment_gateways.on(pg_dsl::id.eq(rpgr_dsl::selected)))
             .filter(rpgr_dsl::retailer_id.eq(id))
+            .order(rpgr_dsl::idx)
             .select((
                 pg_dsl::id,
                 pg_dsl::name,
@@ -260,6 +332,7 @@ impl RoutingTable for RetailerRouting {
         let custody_providers = rcpr_dsl::retailer_custody_provider_routing
             .inner_join(cp_dsl::custody_providers.on(cp_dsl::id.eq(rcpr_dsl::selected)))
             .filter(rcpr_dsl::retailer_id.eq(id))
+            .order(rcpr_dsl::idx)
             .select((
                 cp_dsl::id,
                 cp_dsl::name,
@@ -282,6 +355,7 @@ impl RoutingTable for RetailerRouting {
         let exchange_providers = repr_dsl::retailer_exchange_provider_routing
             .inner_join(ep_dsl::exchange_providers.on(ep_dsl::id.eq(repr_dsl::selected)))
             .filter(repr_dsl::retailer_id.eq(id))
+            .order(repr_dsl::idx)
             .select((
                 ep_dsl::id,
                 ep_dsl::name,
@@ -319,100 +393,162 @@ impl RoutingTable for RetailerRouting {
             .await
             .map_err(|_| AccountError::PoolConnectionFailed)?;
 
-        use schema::retailer_payment_gateway_routing::dsl as rpgr_dsl;
+        conn.transaction::<_, AccountError, _>(|conn| {
+            async move {
+                use schema::retailer_payment_gateway_routing::dsl as rpgr_dsl;
 
-        let mut parsed_payment_gateways = Vec::with_capacity(payment_gateways.len());
-
-        for (idx, entry) in payment_gateways.into_iter().enumerate() {
-            let Some(source) = entry.source else {
-                continue;
-            };
-            let Some(amount) = entry.amount else {
-                continue;
-            };
-            let selected_id = Uuid::from_str(&source.id)
-                .map_err(|_| AccountError::MalformedRoutingSourceToken)?;
-            let (currency, amount): (String, BigDecimal) = amount.into();
-
-            parsed_payment_gateways.push((
-                rpgr_dsl::idx.eq(models::ProfileIndex::try_from(idx)
-                    .map_err(|_| AccountError::TooManyRouteEntries)?),
-                rpgr_dsl::retailer_id.eq(id),
-                rpgr_dsl::selected.eq(selected_id),
-                rpgr_dsl::amount.eq(amount),
-                rpgr_dsl::currency.eq(currency),
-            ));
-        }
-
-        insert_into(rpgr_dsl::retailer_payment_gateway_routing)
-            .values(&parsed_payment_gateways)
-            .execute(conn)
-            .await
-            .map_err(|e| AccountError::QueryFailed(e.to_string()))?;
-
-        use schema::retailer_custody_provider_routing::dsl as rcpr_dsl;
-
-        let mut parsed_custody_providers = Vec::with_capacity(custody_providers.len());
-
-        for (idx, entry) in custody_providers.into_iter().enumerate() {
-            let Some(source) = entry.source else {
-                    continue;
-                };
-            let Some(amount) = entry.amount else {
-                    continue;
-                };
-            let selected_id = Uuid::from_str(&source.id)
-                .map_err(|_| AccountError::MalformedRoutingSourceToken)?;
-            let (currency, amount): (String, BigDecimal) = amount.into();
-
-            parsed_custody_providers.push((
-                rcpr_dsl::idx.eq(models::ProfileIndex::try_from(idx)
-                    .map_err(|_| AccountError::TooManyRouteEntries)?),
-                rcpr_dsl::retailer_id.eq(id),
-                rcpr_dsl::selected.eq(selected_id),
-                rcpr_dsl::amount.eq(amount),
-                rcpr_dsl::currency.eq(currency),
-            ));
-        }
-
-        insert_into(rcpr_dsl::retailer_custody_provider_routing)
-            .values(&parsed_custody_providers)
-            .execute(conn)
-            .await
-            .map_err(|e| AccountError::QueryFailed(e.to_string()))?;
+                let mut parsed_payment_gateways = Vec::with_capacity(payment_gateways.len());
+                let mut payment_gateway_idxs = Vec::with_capacity(payment_gateways.len());
 
-        use schema::retailer_exchange_provider_routing::dsl as repr_dsl;
-
-        let mut parsed_exchange_providers = Vec::with_capacity(exchange_providers.len());
-
-        for (idx, entry) in exchange_providers.into_iter().enumerate() {
-            let Some(source) = entry.source else {
+                for (idx, entry) in payment_gateways.into_iter().enumerate() {
+                    let Some(source) = entry.source else {
                         continue;
                     };
-            let Some(amount) = entry.amount else {
+                    let Some(amount) = entry.amount else {
                         continue;
                     };
-            let selected_id = Uuid::from_str(&source.id)
-                .map_err(|_| AccountError::MalformedRoutingSourceToken)?;
-            let (currency, amount): (String, BigDecimal) = amount.into();
-
-            parsed_exchange_providers.push((
-                repr_dsl::idx.eq(models::ProfileIndex::try_from(idx)
-                    .map_err(|_| AccountError::TooManyRouteEntries)?),
-                repr_dsl::retailer_id.eq(id),
-                repr_dsl::selected.eq(selected_id),
-                repr_dsl::amount.eq(amount),
-                repr_dsl::currency.eq(currency),
-            ));
-        }
-
-        insert_into(repr_dsl::retailer_exchange_provider_routing)
-            .values(&parsed_exchange_providers)
-            .execute(conn)
-            .await
-            .map_err(|e| AccountError::QueryFailed(e.to_string()))?;
-
-        Ok(())
+                    let idx = models::ProfileIndex::try_from(idx)
+                        .map_err(|_| AccountError::TooManyRouteEntries)?;
+                    let selected_id = Uuid::from_str(&source.id)
+                        .map_err(|_| AccountError::MalformedRoutingSourceToken)?;
+                    let (currency, amount): (String, BigDecimal) = amount.into();
+
+                    payment_gateway_idxs.push(idx);
+                    parsed_payment_gateways.push((
+                        rpgr_dsl::idx.eq(idx),
+                        rpgr_dsl::retailer_id.eq(id),
+                        rpgr_dsl::selected.eq(selected_id),
+                        rpgr_dsl::amount.eq(amount),
+                        rpgr_dsl::currency.eq(currency),
+                    ));
+                }
+
+                delete(rpgr_dsl::retailer_payment_gateway_routing)
+                    .filter(
+                        rpgr_dsl::retailer_id
+                            .eq(id)
+                            .and(rpgr_dsl::idx.ne_all(payment_gateway_idxs)),
+                    )
+                    .execute(conn)
+                    .await?;
+                if !parsed_payment_gateways.is_empty() {
+                    insert_into(rpgr_dsl::retailer_payment_gateway_routing)
+                        .values(&parsed_payment_gateways)
+                        .on_conflict((rpgr_dsl::idx, rpgr_dsl::retailer_id))
+                        .do_update()
+                        .set((
+                            rpgr_dsl::selected.eq(excluded(rpgr_dsl::selected)),
+                            rpgr_dsl::amount.eq(excluded(rpgr_dsl::amount)),
+                            rpgr_dsl::currency.eq(excluded(rpgr_dsl::currency)),
+                        ))
+                        .execute(conn)
+                        .await?;
+                }
+
+                use schema::retailer_custody_provider_routing::dsl as rcpr_dsl;
+
+                let mut parsed_custody_providers = Vec::with_capacity(custody_providers.len());
+                let mut custody_provider_idxs = Vec::with_capacity(custody_providers.len());
+
+                for (idx, entry) in custody_providers.into_iter().enumerate() {
+                    let Some(source) = entry.source else {
+                        continue;
+                    };
+                    let Some(amount) = entry.amount else {
+                        continue;
+                    };
+                    let idx = models::ProfileIndex::try_from(idx)
+                        .map_err(|_| AccountError::TooManyRouteEntries)?;
+                    let selected_id = Uuid::from_str(&source.id)
+                        .map_err(|_| AccountError::MalformedRoutingSourceToken)?;
+                    let (currency, amount): (String, BigDecimal) = amount.into();
+
+                    custody_provider_idxs.push(idx);
+                    parsed_custody_providers.push((
+                        rcpr_dsl::idx.eq(idx),
+                        rcpr_dsl::retailer_id.eq(id),
+                        rcpr_dsl::selected.eq(selected_id),
+                        rcpr_dsl::amount.eq(amount),
+                        rcpr_dsl::currency.eq(currency),
+                    ));
+                }
+
+                delete(rcpr_dsl::retailer_custody_provider_routing)
+                    .filter(
+                        rcpr_dsl::retailer_id
+                            .eq(id)
+                            .and(rcpr_dsl::idx.ne_all(custody_provider_idxs)),
+                    )
+                    .execute(conn)
+                    .await?;
+                if !parsed_custody_providers.is_empty() {
+                    insert_into(rcpr_dsl::retailer_custody_provider_routing)
+                        .values(&parsed_custody_providers)
+                        .on_conflict((rcpr_dsl::idx, rcpr_dsl::retailer_id))
+                        .do_update()
+                        .set((
+                            rcpr_dsl::selected.eq(excluded(rcpr_dsl::selected)),
+                            rcpr_dsl::amount.eq(excluded(rcpr_dsl::amount)),
+                            rcpr_dsl::currency.eq(excluded(rcpr_dsl::currency)),
+                        ))
+                        .execute(conn)
+                        .await?;
+                }
+
+                use schema::retailer_exchange_provider_routing::dsl as repr_dsl;
+
+                let mut parsed_exchange_providers = Vec::with_capacity(exchange_providers.len());
+                let mut exchange_provider_idxs = Vec::with_capacity(exchange_providers.len());
+
+                for (idx, entry) in exchange_providers.into_iter().enumerate() {
+                    let Some(source) = entry.source else {
+                        continue;
+                    };
+                    let Some(amount) = entry.amount else {
+                        continue;
+                    };
+                    let idx = models::ProfileIndex::try_from(idx)
+                        .map_err(|_| AccountError::TooManyRouteEntries)?;
+                    let selected_id = Uuid::from_str(&source.id)
+                        .map_err(|_| AccountError::MalformedRoutingSourceToken)?;
+                    let (currency, amount): (String, BigDecimal) = amount.into();
+
+                    exchange_provider_idxs.push(idx);
+                    parsed_exchange_providers.push((
+                        repr_dsl::idx.eq(idx),
+                        repr_dsl::retailer_id.eq(id),
+                        repr_dsl::selected.eq(selected_id),
+                        repr_dsl::amount.eq(amount),
+                        repr_dsl::currency.eq(currency),
+                    ));
+                }
+
+                delete(repr_dsl::retailer_exchange_provider_routing)
+                    .filter(
+                        repr_dsl::retailer_id
+                            .eq(id)
+                            .and(repr_dsl::idx.ne_all(exchange_provider_idxs)),
+                    )
+                    .execute(conn)
+                    .await?;
+                if !parsed_exchange_providers.is_empty() {
+                    insert_into(repr_dsl::retailer_exchange_provider_routing)
+                        .values(&parsed_exchange_providers)
+                        .on_conflict((repr_dsl::idx, repr_dsl::retailer_id))
+                        .do_update()
+                        .set((
+                            repr_dsl::selected.eq(excluded(repr_dsl::selected)),
+                            repr_dsl::amount.eq(excluded(repr_dsl::amount)),
+                            repr_dsl::currency.eq(excluded(repr_dsl::currency)),
+                        ))
+                        .execute(conn)
+                        .await?;
+                }
+                Ok(())
+            }
+            .scope_boxed()
+        })
+        .await
     }
 }
 
@@ -433,6 +569,7 @@ impl RoutingTable for GlobalRouting {
 
         let payment_gateways = gpgr_dsl::global_payment_gateway_routing
             .left_join(pg_dsl::payment_gateways.on(gpgr_dsl::selected.eq(pg_dsl::id.nullable())))
+            .order(gpgr_dsl::idx)
             .select((
                 pg_dsl::id.nullable(),
                 pg_dsl::name.nullable(),
@@ -461,6 +598,7 @@ impl RoutingTable for GlobalRouting {
 
         let custody_providers = gcpr_dsl::global_custody_provider_routing
             .inner_join(cp_dsl::custody_providers.on(cp_dsl::id.nullable().eq(gcpr_dsl::selected)))
+            .order(gcpr_dsl::idx)
             .select((
                 cp_dsl::id.nullable(),
                 cp_dsl::name.nullable(),
@@ -489,6 +627,7 @@ impl RoutingTable for GlobalRouting {
 
         let exchange_providers = gepr_dsl::global_exchange_provider_routing
             .inner_join(ep_dsl::exchange_providers.on(ep_dsl::id.nullable().eq(gepr_dsl::selected)))
+            .order(gepr_dsl::idx)
             .select((
                 ep_dsl::id.nullable(),
                 ep_dsl::name.nullable(),
@@ -533,96 +672,241 @@ impl RoutingTable for GlobalRouting {
             .await
             .map_err(|_| AccountError::PoolConnectionFailed)?;
 
-        use schema::global_payment_gateway_routing::dsl as gpgr_dsl;
+        conn.transaction::<_, AccountError, _>(|conn| {
+            async move {
+                use schema::global_payment_gateway_routing::dsl as gpgr_dsl;
 
-        let mut parsed_payment_gateways = Vec::with_capacity(payment_gateways.len());
-
-        for (idx, entry) in payment_gateways.into_iter().enumerate() {
-            let Some(source) = entry.source else {
-            continue;
-        };
-            let Some(amount) = entry.amount else {
-            continue;
-        };
-            let selected_id = Uuid::from_str(&source.id)
-                .map_err(|_| AccountError::MalformedRoutingSourceToken)?;
-            let (currency, amount): (String, BigDecimal) = amount.into();
-
-            parsed_payment_gateways.push((
-                gpgr_dsl::idx.eq(models::ProfileIndex::try_from(idx)
-                    .map_err(|_| AccountError::TooManyRouteEntries)?),
-                gpgr_dsl::selected.eq(selected_id),
-                gpgr_dsl::amount.eq(amount),
-                gpgr_dsl::currency.eq(currency),
-            ));
-        }
-
-        insert_into(gpgr_dsl::global_payment_gateway_routing)
-            .values(&parsed_payment_gateways)
-            .execute(conn)
-            .await
-            .map_err(|e| AccountError::QueryFailed(e.to_string()))?;
+                let mut parsed_payment_gateways = Vec::with_capacity(payment_gateways.len());
+                let mut payment_gateway_idxs = Vec::with_capacity(payment_gateways.len());
 
-        use schema::global_custody_provider_routing::dsl as gcpr_dsl;
-
-        let mut parsed_custody_providers = Vec::with_capacity(custody_providers.len());
-
-        for (idx, entry) in custody_providers.into_iter().enumerate() {
-            let Some(source) = entry.source else {
-                continue;
-            };
-            let Some(amount) = entry.amount else {
-                continue;
-            };
-            let selected_id = Uuid::from_str(&source.id)
-                .map_err(|_| AccountError::MalformedRoutingSourceToken)?;
-            let (currency, amount): (String, BigDecimal) = amount.into();
-
-            parsed_custody_providers.push((
-                gcpr_dsl::idx.eq(models::ProfileIndex::try_from(idx)
-                    .map_err(|_| AccountError::TooManyRouteEntries)?),
-                gcpr_dsl::selected.eq(selected_id),
-                gcpr_dsl::amount.eq(amount),
-                gcpr_dsl::currency.eq(currency),
-            ));
-        }
-
-        insert_into(gcpr_dsl::global_custody_provider_routing)
-            .values(&parsed_custody_providers)
-            .execute(conn)
-            .await
-            .map_err(|e| AccountError::QueryFailed(e.to_string()))?;
-
-        use schema::global_exchange_provider_routing::dsl as gepr_dsl;
-
-        let mut parsed_exchange_providers = Vec::with_capacity(exchange_providers.len());
-
-        for (idx, entry) in exchange_providers.into_iter().enumerate() {
-            let Some(source) = entry.source else {
-                    continue;
-                };
-            let Some(amount) = entry.amount else {
-                    continue;
-                };
-            let selected_id = Uuid::from_str(&source.id)
-                .map_err(|_| AccountError::MalformedRoutingSourceToken)?;
-            let (currency, amount): (String, BigDecimal) = amount.into();
-
-            parsed_exchange_providers.push((
-                gepr_dsl::idx.eq(models::ProfileIndex::try_from(idx)
-                    .map_err(|_| AccountError::TooManyRouteEntries)?),
-                gepr_dsl::selected.eq(selected_id),
-                gepr_dsl::amount.eq(amount),
-                gepr_dsl::currency.eq(currency),
-            ));
-        }
-
-        insert_into(gepr_dsl::global_exchange_provider_routing)
-            .values(&parsed_exchange_providers)
-            .execute(conn)
-            .await
-            .map_err(|e| AccountError::QueryFailed(e.to_string()))?;
+                for (idx, entry) in payment_gateways.into_iter().enumerate() {
+                    let Some(source) = entry.source else {
+                        continue;
+                    };
+                    let Some(amount) = entry.amount else {
+                        continue;
+                    };
+                    let idx = models::ProfileIndex::try_from(idx)
+                        .map_err(|_| AccountError::TooManyRouteEntries)?;
+                    let selected_id = Uuid::from_str(&source.id)
+                        .map_err(|_| AccountError::MalformedRoutingSourceToken)?;
+                    let (currency, amount): (String, BigDecimal) = amount.into();
+
+                    payment_gateway_idxs.push(idx);
+                    parsed_payment_gateways.push((
+                        gpgr_dsl::idx.eq(idx),
+                        gpgr_dsl::selected.eq(selected_id),
+                        gpgr_dsl::amount.eq(amount),
+                        gpgr_dsl::currency.eq(currency),
+                    ));
+                }
+
+                // The global tables keep a row for every profile index, so unused ones are cleared
+                update(gpgr_dsl::global_payment_gateway_routing)
+                    .filter(gpgr_dsl::idx.ne_all(payment_gateway_idxs))
+                    .set((
+                        gpgr_dsl::selected.eq(None::<Uuid>),
+                        gpgr_dsl::amount.eq(None::<BigDecimal>),
+                        gpgr_dsl::currency.eq(None::<String>),
+                    ))
+                    .execute(conn)
+                    .await?;
+                if !parsed_payment_gateways.is_empty() {
+                    insert_into(gpgr_dsl::global_payment_gateway_routing)
+                        .values(&parsed_payment_gateways)
+                        .on_conflict(gpgr_dsl::idx)
+                        .do_update()
+                        .set((
+                            gpgr_dsl::selected.eq(excluded(gpgr_dsl::selected)),
+                            gpgr_dsl::amount.eq(excluded(gpgr_dsl::amount)),
+                            gpgr_dsl::currency.eq(excluded(gpgr_dsl::currency)),
+                        ))
+                        .execute(conn)
+                        .await?;
+                }
+
+                use schema::global_custody_provider_routing::dsl as gcpr_dsl;
+
+                let mut parsed_custody_providers = Vec::with_capacity(custody_providers.len());
+                let mut custody_provider_idxs = Vec::with_capacity(custody_providers.len());
+
+                for (idx, entry) in custody_providers.into_iter().enumerate() {
+                    let Some(source) = entry.source else {
+                        continue;
+                    };
+                    let Some(amount) = entry.amount else {
+                        continue;
+                    };
+                    let idx = models::ProfileIndex::try_from(idx)
+                        .map_err(|_| AccountError::TooManyRouteEntries)?;
+                    let selected_id = Uuid::from_str(&source.id)
+                        .map_err(|_| AccountError::MalformedRoutingSourceToken)?;
+                    let (currency, amount): (String, BigDecimal) = amount.into();
+
+                    custody_provider_idxs.push(idx);
+                    parsed_custody_providers.push((
+                        gcpr_dsl::idx.eq(idx),
+                        gcpr_dsl::selected.eq(selected_id),
+                        gcpr_dsl::amount.eq(amount),
+                        gcpr_dsl::currency.eq(currency),
+                    ));
+                }
+
+                // The global tables keep a row for every profile index, so unused ones are cleared
+                update(gcpr_dsl::global_custody_provider_routing)
+                    .filter(gcpr_dsl::idx.ne_all(custody_provider_idxs))
+                    .set((
+                        gcpr_dsl::selected.eq(None::<Uuid>),
+                        gcpr_dsl::amount.eq(None::<BigDecimal>),
+                        gcpr_dsl::currency.eq(None::<String>),
+                    ))
+                    .execute(conn)
+                    .await?;
+                if !parsed_custody_providers.is_empty() {
+                    insert_into(gcpr_dsl::global_custody_provider_routing)
+                        .values(&parsed_custody_providers)
+                        .on_conflict(gcpr_dsl::idx)
+                        .do_update()
+                        .set((
+                            gcpr_dsl::selected.eq(excluded(gcpr_dsl::selected)),
+                            gcpr_dsl::amount.eq(excluded(gcpr_dsl::amount)),
+                            gcpr_dsl::currency.eq(excluded(gcpr_dsl::currency)),
+                        ))
+                        .execute(conn)
+                        .await?;
+                }
+
+                use schema::global_exchange_provider_routing::dsl as gepr_dsl;
+
+                let mut parsed_exchange_providers = Vec::with_capacity(exchange_providers.len());
+                let mut exchange_provider_idxs = Vec::with_capacity(exchange_providers.len());
+
+                for (idx, entry) in exchange_providers.into_iter().enumerate() {
+                    let Some(source) = entry.source else {
+                        continue;
+                    };
+                    let Some(amount) = entry.amount else {
+                        continue;
+                    };
+                    let idx = models::ProfileIndex::try_from(idx)
+                        .map_err(|_| AccountError::TooManyRouteEntries)?;
+                    let selected_id = Uuid::from_str(&source.id)
+                        .map_err(|_| AccountError::MalformedRoutingSourceToken)?;
+                    let (currency, amount): (String, BigDecimal) = amount.into();
+
+                    exchange_provider_idxs.push(idx);
+                    parsed_exchange_providers.push((
+                        gepr_dsl::idx.eq(idx),
+                        gepr_dsl::selected.eq(selected_id),
+                        gepr_dsl::amount.eq(amount),
+                        gepr_dsl::currency.eq(currency),
+                    ));
+                }
+
+                // The global tables keep a row for every profile index, so unused ones are cleared
+                update(gepr_dsl::global_exchange_provider_routing)
+                    .filter(gepr_dsl::idx.ne_all(exchange_provider_idxs))
+                    .set((
+                        gepr_dsl::selected.eq(None::<Uuid>),
+                        gepr_dsl::amount.eq(None::<BigDecimal>),
+                        gepr_dsl::currency.eq(None::<String>),
+                    ))
+                    .execute(conn)
+                    .await?;
+                if !parsed_exchange_providers.is_empty() {
+                    insert_into(gepr_dsl::global_exchange_provider_routing)
+                        .values(&parsed_exchange_providers)
+                        .on_conflict(gepr_dsl::idx)
+                        .do_update()
+                        .set((
+                            gepr_dsl::selected.eq(excluded(gepr_dsl::selected)),
+                            gepr_dsl::amount.eq(excluded(gepr_dsl::amount)),
+                            gepr_dsl::currency.eq(excluded(gepr_dsl::currency)),
+                        ))
+                        .execute(conn)
+                        .await?;
+                }
+                Ok(())
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+}
 
-        Ok(())
+/// Picks the payment gateway, custody provider and exchange provider to use for `amount`.
+///
+/// Each of them is picked from the first level, in the order customer, retailer and global,
+/// that has entries in the currency of the amount. Inside of that level the first entry
+/// whose amount is at least the requested amount is used.
+pub(crate) async fn resolve(
+    pool: &Pool<AsyncPgConnection>,
+    customer_id: Uuid,
+    retailer_id: Option<Uuid>,
+    amount: (String, BigDecimal),
+) -> Result<ResolvedRouting, AccountError> {
+    let mut levels = vec![(
+        RoutingLevel::CustomerLevel,
+        CustomerRouting::get(pool, customer_id).await?,
+    )];
+    if let Some(retailer_id) = retailer_id {
+        levels.push((
+            RoutingLevel::RetailerLevel,
+            RetailerRouting::get(pool, retailer_id).await?,
+        ));
     }
+    levels.push((
+        RoutingLevel::GlobalLevel,
+        GlobalRouting::get(pool, ()).await?,
+    ));
+
+    let payment_gateway = resolve_entries(
+        levels
+            .iter()
+            .map(|(level, routing)| (*level, &routing.payment_gateways)),
+        &amount,
+    )
+    .ok_or(AccountError::NoRouteFound("payment gateway"))?;
+    let custody_provider = resolve_entries(
+        levels
+            .iter()
+            .map(|(level, routing)| (*level, &routing.custody_providers)),
+        &amount,
+    )
+    .ok_or(AccountError::NoRouteFound("custody provider"))?;
+    let exchange_provider = resolve_entries(
+        levels
+            .iter()
+            .map(|(level, routing)| (*level, &routing.exchange_providers)),
+        &amount,
+    )
+    .ok_or(AccountError::NoRouteFound("exchange provider"))?;
+
+    Ok(ResolvedRouting {
+        payment_gateway: Some(payment_gateway),
+        custody_provider: Some(custody_provider),
+        exchange_provider: Some(exchange_provider),
+    })
+}
+
+fn resolve_entries<'e>(
+    levels: impl Iterator<Item = (RoutingLevel, &'e Vec<RoutingEntry>)>,
+    (currency, amount): &(String, BigDecimal),
+) -> Option<ResolvedRoute> {
+    // A level without an entry covering the amount falls through to the next one
+    levels.into_iter().find_map(|(level, entries)| {
+        entries
+            .iter()
+            .filter_map(|entry| {
+                let source = entry.source.clone()?;
+                let (entry_currency, max_amount): (String, BigDecimal) =
+                    entry.amount.clone()?.into();
+                (entry_currency == *currency).then_some((source, max_amount))
+            })
+            .find(|(_, max_amount)| max_amount >= amount)
+            .map(|(source, _)| ResolvedRoute {
+                source: Some(source),
+                level: level as i32,
+            })
+    })
 }
@@ -6,15 +6,20 @@ use bigdecimal::{num_bigint::BigInt, BigDecimal};
 use helpers::routing::RoutingTable;
 use lunu::{
     account::{
-        account_server::AccountServer, Approval, CustomerData, CustomerDesc, GetApproval, Id,
-        InnerLimits, KycLevel, Limits, Money, PartnerData, PartnerDesc, PartnerFees,
-        PutPartnerFees, PutRetailerFees, RetailerData, RetailerDesc, RetailerFees, RetailerPartner,
-        Routing, SetApproval, SetLimit, SetLimitGlobal, SetMinPurchase, SetRouting,
+        account_server::AccountServer, Approval, CheckLimit, CustomerData, CustomerDesc, FeeQuote,
+        GetApproval, Id, InnerLimits, KycLevel, LimitCheck, Limits, ListProvider, Money,
+        NewProvider, PartnerData, PartnerDesc, PartnerFees, PaymentMethodFee, ProviderId,
+        Providers, PutPartnerFees, PutRetailerFees, QuoteFee, ResolveRoute, ResolvedRouting,
+        RetailerData, RetailerDesc, RetailerFees, RetailerPartner, Routing, SetApproval, SetLimit,
+        SetLimitGlobal, SetMinPurchase, SetProviderName, SetRouting,
+    },
+    diesel::{
+        self, delete, insert_into, update, BoolExpressionMethods, ExpressionMethods, QueryDsl,
     },
-    diesel::{delete, insert_into, update, BoolExpressionMethods, ExpressionMethods, QueryDsl},
     diesel_async::{
         pooled_connection::{bb8::Pool, AsyncDieselConnectionManager},
-        AsyncPgConnection, RunQueryDsl,
+        scoped_futures::ScopedFutureExt,
+        AsyncConnection, AsyncPgConnection, RunQueryDsl,
     },
     dotenvy::dotenv,
     models, schema, Microservice, MICROSERVICE_ADDRS,
@@ -50,16 +55,35 @@ impl lunu::account::account_server::Account for Account {
         use schema::customers::dsl as c_dsl;
 
         let id = Uuid::new_v4();
-        insert_into(c_dsl::customers)
-            .values(models::Customer {
-                id,
-                first_name,
-                last_name,
-                account_id,
-            })
-            .execute(conn)
-            .await
-            .map_err(|e| AccountError::QueryFailed(e.to_string()))?;
+        // The scope is granted along with the customer so the account can use it right away
+        conn.transaction::<_, AccountError, _>(|conn| {
+            async move {
+                insert_into(c_dsl::customers)
+                    .values(models::Customer {
+                        id,
+                        first_name,
+                        last_name,
+                        account_id,
+                    })
+                    .execute(conn)
+                    .await?;
+
+                use schema::scopes::dsl as s_dsl;
+
+                insert_into(s_dsl::scopes)
+                    .values(models::Scope {
+                        account_id,
+                        scope: models::ScopeKind::Customer,
+                    })
+                    .on_conflict_do_nothing()
+                    .execute(conn)
+                    .await?;
+
+                Ok(())
+            }
+            .scope_boxed()
+        })
+        .await?;
 
         Ok(tonic::Response::new(Id { id: id.to_string() }))
     }
@@ -130,11 +154,30 @@ impl lunu::account::account_server::Account for Account {
         use schema::retailers::dsl as r_dsl;
 
         let id = Uuid::new_v4();
-        insert_into(r_dsl::retailers)
-            .values(models::Retailer { id, account_id })
-            .execute(conn)
-            .await
-            .map_err(|e| AccountError::QueryFailed(e.to_string()))?;
+        // The scope is granted along with the retailer so the account can use it right away
+        conn.transaction::<_, AccountError, _>(|conn| {
+            async move {
+                insert_into(r_dsl::retailers)
+                    .values(models::Retailer { id, account_id })
+                    .execute(conn)
+                    .await?;
+
+                use schema::scopes::dsl as s_dsl;
+
+                insert_into(s_dsl::scopes)
+                    .values(models::Scope {
+                        account_id,
+                        scope: models::ScopeKind::Retailer,
+                    })
+                    .on_conflict_do_nothing()
+                    .execute(conn)
+                    .await?;
+
+                Ok(())
+            }
+            .scope_boxed()
+        })
+        .await?;
 
         Ok(tonic::Response::new(Id { id: id.to_string() }))
     }
@@ -212,11 +255,30 @@ impl lunu::account::account_server::Account for Account {
         use schema::partners::dsl as p_dsl;
 
         let id = Uuid::new_v4();
-        insert_into(p_dsl::partners)
-            .values(models::Partner { id, account_id })
-            .execute(conn)
-            .await
-            .map_err(|e| AccountError::QueryFailed(e.to_string()))?;
+        // The scope is granted along with the partner so the account can use it right away
+        conn.transaction::<_, AccountError, _>(|conn| {
+            async move {
+                insert_into(p_dsl::partners)
+                    .values(models::Partner { id, account_id })
+                    .execute(conn)
+                    .await?;
+
+                use schema::scopes::dsl as s_dsl;
+
+                insert_into(s_dsl::scopes)
+                    .values(models::Scope {
+                        account_id,
+                        scope: models::ScopeKind::Partner,
+                    })
+                    .on_conflict_do_nothing()
+                    .execute(conn)
+                    .await?;
+
+                Ok(())
+            }
+            .scope_boxed()
+        })
+        .await?;
 
         Ok(tonic::Response::new(Id { id: id.to_string() }))
     }
@@ -691,6 +753,29 @@ impl lunu::account::account_server::Account for Account {
         Ok(tonic::Response::new(()))
     }
 
+    async fn check_limits(
+        &self,
+        request: tonic::Request<CheckLimit>,
+    ) -> Result<tonic::Response<LimitCheck>, tonic::Status> {
+        let CheckLimit {
+            customer_id,
+            retailer_id,
+            amount,
+        } = request.into_inner();
+        let customer_id =
+            Uuid::from_str(&customer_id).map_err(|_| AccountError::MalformedAccountToken)?;
+        let retailer_id = retailer_id
+            .map(|id| Uuid::from_str(&id))
+            .transpose()
+            .map_err(|_| AccountError::MalformedAccountToken)?;
+        let amount = amount.ok_or(AccountError::MissingAmount)?.into();
+        let limits = helpers::limits::Limits(&self.pool);
+
+        Ok(tonic::Response::new(
+            limits.check(customer_id, retailer_id, amount).await?,
+        ))
+    }
+
     async fn get_min_purchase_value(
         &self,
         request: tonic::Request<Id>,
@@ -824,6 +909,82 @@ impl lunu::account::account_server::Account for Account {
         ))
     }
 
+    async fn resolve_routing(
+        &self,
+        request: tonic::Request<ResolveRoute>,
+    ) -> Result<tonic::Response<ResolvedRouting>, tonic::Status> {
+        let ResolveRoute {
+            customer_id,
+            retailer_id,
+            amount,
+        } = request.into_inner();
+        let customer_id =
+            Uuid::from_str(&customer_id).map_err(|_| AccountError::MalformedAccountToken)?;
+        let retailer_id = retailer_id
+            .map(|id| Uuid::from_str(&id))
+            .transpose()
+            .map_err(|_| AccountError::MalformedAccountToken)?;
+        let amount = amount.ok_or(AccountError::MissingAmount)?.into();
+
+        Ok(tonic::Response::new(
+            helpers::routing::resolve(&self.pool, customer_id, retailer_id, amount).await?,
+        ))
+    }
+
+    async fn create_provider(
+        &self,
+        request: tonic::Request<NewProvider>,
+    ) -> Result<tonic::Response<Id>, tonic::Status> {
+        let new_provider = request.into_inner();
+        let providers = helpers::providers::Providers(&self.pool);
+
+        let id = providers
+            .create(new_provider.kind(), new_provider.name)
+            .await?;
+
+        Ok(tonic::Response::new(Id { id: id.to_string() }))
+    }
+
+    async fn list_providers(
+        &self,
+        request: tonic::Request<ListProvider>,
+    ) -> Result<tonic::Response<Providers>, tonic::Status> {
+        let kind = request.into_inner().kind();
+        let providers = helpers::providers::Providers(&self.pool);
+
+        Ok(tonic::Response::new(Providers {
+            providers: providers.list(kind).await?,
+        }))
+    }
+
+    async fn rename_provider(
+        &self,
+        request: tonic::Request<SetProviderName>,
+    ) -> Result<tonic::Response<()>, tonic::Status> {
+        let rename = request.into_inner();
+        let kind = rename.kind();
+        let id = Uuid::from_str(&rename.id).map_err(|_| AccountError::MalformedProviderId)?;
+        let providers = helpers::providers::Providers(&self.pool);
+
+        providers.rename(kind, id, rename.name).await?;
+
+        Ok(tonic::Response::new(()))
+    }
+
+    async fn delete_provider(
+        &self,
+        request: tonic::Request<ProviderId>,
+    ) -> Result<tonic::Response<()>, tonic::Status> {
+        let provider = request.into_inner();
+        let kind = provider.kind();
+        let id = Uuid::from_str(&provider.id).map_err(|_| AccountError::MalformedProviderId)?;
+        let providers = helpers::providers::Providers(&self.pool);
+
+        providers.delete(kind, id).await?;
+
+        Ok(tonic::Response::new(()))
+    }
+
     async fn get_retailer_fees(
         &self,
         request: tonic::Request<Id>,
@@ -851,6 +1012,47 @@ impl lunu::account::account_server::Account for Account {
         Ok(tonic::Response::new(()))
     }
 
+    async fn delete_retailer_fee(
+        &self,
+        request: tonic::Request<PaymentMethodFee>,
+    ) -> Result<tonic::Response<()>, tonic::Status> {
+        let PaymentMethodFee {
+            id,
+            payment_method_id,
+        } = request.into_inner();
+        let id = Uuid::from_str(&id).map_err(|_| AccountError::MalformedAccountToken)?;
+        let payment_method_id =
+            Uuid::from_str(&payment_method_id).map_err(|_| AccountError::MalformedPaymentId)?;
+        let fees = helpers::fees::RetailerFees(&self.pool);
+
+        fees.delete(id, payment_method_id).await?;
+
+        Ok(tonic::Response::new(()))
+    }
+
+    async fn quote_fees(
+        &self,
+        request: tonic::Request<QuoteFee>,
+    ) -> Result<tonic::Response<FeeQuote>, tonic::Status> {
+        let QuoteFee {
+            retailer_id,
+            payment_method_id,
+            amount,
+            stable_coin,
+        } = request.into_inner();
+        let retailer_id =
+            Uuid::from_str(&retailer_id).map_err(|_| AccountError::MalformedAccountToken)?;
+        let payment_method_id =
+            Uuid::from_str(&payment_method_id).map_err(|_| AccountError::MalformedPaymentId)?;
+        let amount = amount.ok_or(AccountError::MissingAmount)?.into();
+        let fees = helpers::fees::RetailerFees(&self.pool);
+
+        Ok(tonic::Response::new(
+            fees.quote(retailer_id, payment_method_id, amount, stable_coin)
+                .await?,
+        ))
+    }
+
     async fn get_partner_fees(
         &self,
         request: tonic::Request<Id>,
@@ -877,6 +1079,24 @@ impl lunu::account::account_server::Account for Account {
 
         Ok(tonic::Response::new(()))
     }
+
+    async fn delete_partner_fee(
+        &self,
+        request: tonic::Request<PaymentMethodFee>,
+    ) -> Result<tonic::Response<()>, tonic::Status> {
+        let PaymentMethodFee {
+            id,
+            payment_method_id,
+        } = request.into_inner();
+        let id = Uuid::from_str(&id).map_err(|_| AccountError::MalformedAccountToken)?;
+        let payment_method_id =
+            Uuid::from_str(&payment_method_id).map_err(|_| AccountError::MalformedPaymentId)?;
+        let fees = helpers::fees::PartnerFees(&self.pool);
+
+        fees.delete(id, payment_method_id).await?;
+
+        Ok(tonic::Response::new(()))
+    }
 }
 
 enum AccountError {
@@ -890,6 +1110,22 @@ enum AccountError {
     RetailerNotFound,
     TooManyRouteEntries,
     MissingRoutingData,
+    LimitCurrencyMismatch(String, String),
+    NoRouteFound(&'static str),
+    FeesNotFound,
+    FeeCurrencyMismatch(String, String),
+    MalformedProviderId,
+    ProviderNotFound,
+    ProviderInUse,
+    MissingFee(&'static str),
+    AmountBelowFee(String, BigDecimal),
+    PartnerFeesExceedFee(String, BigDecimal, BigDecimal),
+}
+
+impl From<diesel::result::Error> for AccountError {
+    fn from(value: diesel::result::Error) -> Self {
+        AccountError::QueryFailed(value.to_string())
+    }
 }
 
 impl From<AccountError> for tonic::Status {
@@ -923,6 +1159,38 @@ impl From<AccountError> for tonic::Status {
             AccountError::MissingRoutingData => {
                 tonic::Status::invalid_argument("Routing data was missing")
             }
+            AccountError::LimitCurrencyMismatch(limit, amount) => tonic::Status::invalid_argument(
+                format!("The limit is set in {limit} but the amount is in {amount}"),
+            ),
+            AccountError::NoRouteFound(kind) => {
+                tonic::Status::failed_precondition(format!("No {kind} is routed for the amount"))
+            }
+            AccountError::FeesNotFound => tonic::Status::not_found(
+                "The retailer has no fees set for the supplied payment method",
+            ),
+            AccountError::FeeCurrencyMismatch(fee, amount) => tonic::Status::invalid_argument(
+                format!("The fee is set in {fee} but the amount is in {amount}"),
+            ),
+            AccountError::MalformedProviderId => {
+                tonic::Status::invalid_argument("Malformed provider id")
+            }
+            AccountError::ProviderNotFound => {
+                tonic::Status::not_found("Provider with the supplied id was not found")
+            }
+            AccountError::ProviderInUse => tonic::Status::failed_precondition(
+                "Provider is still referenced by a routing or fee entry",
+            ),
+            AccountError::MissingFee(field) => tonic::Status::invalid_argument(format!(
+                "Missing {field} for a payment method that has no fees set yet"
+            )),
+            AccountError::AmountBelowFee(currency, fee) => tonic::Status::invalid_argument(
+                format!("The amount does not cover the fee of {fee} {currency}"),
+            ),
+            AccountError::PartnerFeesExceedFee(currency, partner_fees, fee) => {
+                tonic::Status::failed_precondition(format!(
+                    "The partner fees of {partner_fees} {currency} exceed the fee of {fee} {currency}"
+                ))
+            }
         }
     }
 }
@@ -1,22 +1,44 @@
-use std::{cell::RefCell, env, ops::DerefMut, str::FromStr};
+mod password_policy;
+
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    env,
+    ops::DerefMut,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
-use argon2::{password_hash::SaltString, Argon2, PasswordHasher};
+use argon2::{
+    password_hash::SaltString, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier,
+    Version,
+};
+use jsonwebtoken::{EncodingKey, Header};
 use lunu::{
     auth::{
-        auth_server::AuthServer, Account, AccountEmail, EmailLoginIntent, EmailLoginParams,
-        NewPassLoginParams, OptionalAccount, PasswordParams, SessionToken,
+        auth_server::AuthServer, login_result, AccessClaims, Account, AccountEmail, AccountId,
+        AccountScopes, ApiKey, ApiKeyDesc, ApiKeyId, ApiKeyToken, ApiKeys, BlockAccountParams,
+        ClientInfo, EmailChange, EmailChangeCode, EmailLoginIntent, EmailLoginParams, LoginResult,
+        NewApiKey, NewPassLoginIntentInfo, NewPassLoginParams, NewPassLoginToken, OptionalAccount,
+        PasswordParams, PasswordViolations, RecoveryCodes, Revocations, RevokedAccount, Scope,
+        ScopeGrant, SessionDesc, SessionId, SessionToken, SessionTokens, Sessions, TotpChallenge,
+        TotpCode, TotpEnrollment, TotpLoginParams,
     },
     diesel::{
-        delete, insert_into, pg::Pg, ExpressionMethods, JoinOnDsl, OptionalExtension, QueryDsl,
+        self, delete, dsl::exists, insert_into, pg::Pg, select, update, BoolExpressionMethods,
+        ExpressionMethods, JoinOnDsl, NullableExpressionMethods, OptionalExtension, QueryDsl,
     },
     diesel_async::{
         pooled_connection::{bb8::Pool, AsyncDieselConnectionManager},
+        scoped_futures::ScopedFutureExt,
         AsyncConnection, AsyncPgConnection, RunQueryDsl,
     },
     dotenvy::dotenv,
     email::Email,
     models, register_tonic_clients, schema, Microservice, MICROSERVICE_ADDRS,
 };
+use password_policy::PasswordPolicy;
+use prost::Message;
 use rand::{
     distributions::Alphanumeric,
     rngs::{OsRng, ThreadRng},
@@ -24,6 +46,7 @@ use rand::{
 };
 use time::{Duration, OffsetDateTime};
 use tonic::transport::{Channel, Server};
+use totp_rs::{Algorithm, Secret, TOTP};
 use uuid::Uuid;
 
 thread_local! {
@@ -34,8 +57,49 @@ register_tonic_clients! {
     (MAIL_CLIENT, lunu::email::mail_client::MailClient<Channel>, lunu::Microservice::Email, "email"),
 }
 
+// Setting the number of times an email is handed to the mail service before it is dropped
+const MAIL_SEND_ATTEMPTS: u32 = 5;
+// Setting the first retry to 1 second, it doubles with every further attempt
+const MAIL_RETRY_BASE: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Sends the email without waiting for the mail service, so the time an intent takes to
+/// create doesn't tell whether an email was sent for it. The mail service is retried while
+/// it is unavailable, the intent stays usable and can be asked for again in the meantime.
+fn send_email(email: Email) {
+    let mut client = MAIL_CLIENT
+        .get()
+        .expect("MAIL_CLIENT used before it was initalized")
+        .clone();
+
+    tokio::spawn(async move {
+        let mut retry_in = MAIL_RETRY_BASE;
+        for attempt in 1..=MAIL_SEND_ATTEMPTS {
+            let Err(status) = client.send(email.clone()).await else {
+                return;
+            };
+            if status.code() != tonic::Code::Unavailable || attempt == MAIL_SEND_ATTEMPTS {
+                eprintln!(
+                    "Failed to send the {} email: {}",
+                    email.template,
+                    status.message()
+                );
+                return;
+            }
+
+            tokio::time::sleep(retry_in).await;
+            retry_in *= 2;
+        }
+    });
+}
+
 struct Auth {
     pool: Pool<AsyncPgConnection>,
+    argon: Argon2<'static>,
+    password_policy: PasswordPolicy,
+    jwt_key: EncodingKey,
+    frontend_url: String,
+    // Verified when a login has no password to check, so it takes as long as one that has
+    dummy_hash: String,
 }
 
 impl Auth {
@@ -47,12 +111,73 @@ impl Auth {
     const SESSION_DURATION: Duration = Duration::WEEK;
     // Setting the session token length
     const SESSION_TOKEN_LEN: usize = 128;
+    // Setting the access token duration to 15 minutes, which is also how long revocations
+    // are kept for the gateway
+    const ACCESS_TOKEN_DURATION: Duration = Duration::minutes(15);
+    // Setting a shorter duration for admin tokens, in case the gateway misses a revocation
+    const ADMIN_ACCESS_TOKEN_DURATION: Duration = Duration::minutes(5);
+    // Setting the email change duration to 1 hour, the code has to be looked up in another inbox
+    const EMAIL_CHANGE_DURATION: Duration = Duration::HOUR;
+    // Setting how long an account that was signed up for can stay unverified before it is deleted
+    const UNVERIFIED_ACCOUNT_DURATION: Duration = Duration::DAY;
     // Setting the new password login token length
     const NEW_PASS_LOGIN_TOKEN_LEN: usize = 64;
+    // Setting the frontend page the new password link points to
+    const NEW_PASS_LOGIN_PATH: &'static str = "/new-password";
+    // Setting the prefix every api key starts with
+    const API_KEY_PREFIX: &'static str = "lunu_";
+    // Setting the length of the secret part of an api key
+    const API_KEY_SECRET_LEN: usize = 48;
+    // Setting the totp challenge token length
+    const TOTP_CHALLENGE_TOKEN_LEN: usize = 64;
+    // Setting the issuer shown in authenticator apps
+    const TOTP_ISSUER: &'static str = "lunu";
+    // Setting the totp parameters to the ones authenticator apps expect (RFC 6238)
+    const TOTP_DIGITS: usize = 6;
+    const TOTP_STEP: u64 = 30;
+    // Setting the number of steps a code is still accepted for, to allow for clock drift
+    const TOTP_SKEW: u8 = 1;
+    // Setting the totp secret length to 160 bits
+    const TOTP_SECRET_LEN: usize = 20;
+    // Setting the number and length of the recovery codes
+    const RECOVERY_CODE_COUNT: usize = 10;
+    const RECOVERY_CODE_LEN: usize = 10;
+    // Setting the number of wrong codes after which an intent or totp challenge is invalidated
+    const MAX_CODE_ATTEMPTS: i32 = 5;
+    // Setting the number of failed logins an account gets before it has to back off
+    const FREE_LOGIN_ATTEMPTS: i32 = 3;
+    // Setting the first backoff to 2 seconds, it doubles with every further failed login
+    const LOGIN_BACKOFF_BASE: Duration = Duration::seconds(2);
+    // Setting the longest backoff to 1 hour
+    const LOGIN_BACKOFF_MAX: Duration = Duration::HOUR;
+
+    fn hash_password(&self, password: &str) -> Result<String, AuthError> {
+        let salt = SaltString::generate(&mut OsRng);
+
+        Ok(self
+            .argon
+            .hash_password(password.as_bytes(), &salt)
+            .map_err(AuthError::PasswordHashingError)?
+            .to_string())
+    }
+
+    // A hash made with other argon2 parameters than the configured ones should be replaced
+    fn needs_rehash(&self, hash: &PasswordHash) -> bool {
+        let Ok(params) = Params::try_from(hash) else {
+            return true;
+        };
+        let configured = self.argon.params();
+
+        hash.algorithm != argon2::Algorithm::Argon2id.ident()
+            || hash.version != Some(Version::V0x13.into())
+            || params.m_cost() != configured.m_cost()
+            || params.t_cost() != configured.t_cost()
+            || params.p_cost() != configured.p_cost()
+    }
 
     async fn get_account(
         &self,
-        conn: &mut (impl AsyncConnection<Backend = Pg> + Send),
+        conn: &mut impl AsyncConnection<Backend = Pg>,
         email: &str,
     ) -> Result<Option<Uuid>, tonic::Status> {
         use schema::accounts::dsl as a_dsl;
@@ -71,53 +196,123 @@ impl Auth {
         }
     }
 
-    async fn create_account(
+    /// Finds the account a login code should be sent to. Signing up creates the account
+    /// when it doesn't exist, but it stays unverified until the first code sent to it is used.
+    ///
+    /// Returns `None` when no code should be sent, which the caller must not reveal.
+    async fn intent_account(
         &self,
-        conn: &mut (impl AsyncConnection<Backend = Pg> + Send),
+        conn: &mut impl AsyncConnection<Backend = Pg>,
         email: &str,
-    ) -> Result<Uuid, tonic::Status> {
+        sign_up: bool,
+    ) -> Result<Option<Uuid>, tonic::Status> {
         use schema::accounts::dsl as a_dsl;
 
-        let id = Uuid::new_v4();
-        insert_into(a_dsl::accounts)
-            .values(models::Account { id, email })
-            .execute(conn)
+        let account = a_dsl::accounts
+            .select((a_dsl::id, a_dsl::verified, a_dsl::blocked))
+            .filter(a_dsl::email.eq(email))
+            .first::<(Uuid, bool, bool)>(conn)
             .await
-            .map_err(|_| AuthError::AccountForEmailAreadyExists)?;
+            .optional()
+            .map_err(|e| AuthError::QueryFailed(e.to_string()))?;
 
-        Ok(id)
+        match account {
+            Some((_, _, true)) => Ok(None),
+            Some((id, true, _)) => Ok(Some(id)),
+            // An unverified account only gets codes through signing up again
+            Some((id, false, _)) if sign_up => Ok(Some(id)),
+            Some(_) => Ok(None),
+            None if sign_up => {
+                // Someone signing up with the same email at the same time gets no code
+                Ok(insert_into(a_dsl::accounts)
+                    .values(models::Account {
+                        id: Uuid::new_v4(),
+                        email,
+                        verified: false,
+                    })
+                    .on_conflict_do_nothing()
+                    .returning(a_dsl::id)
+                    .get_result::<Uuid>(conn)
+                    .await
+                    .optional()
+                    .map_err(|e| AuthError::QueryFailed(e.to_string()))?)
+            }
+            None => Ok(None),
+        }
     }
 
-    async fn get_or_create_account(
+    /// Marks the account as verified once a code sent to its email was used.
+    async fn verify_account(
         &self,
-        conn: &mut (impl AsyncConnection<Backend = Pg> + Send),
-        email: &str,
-    ) -> Result<Uuid, tonic::Status> {
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+        account_id: Uuid,
+    ) -> Result<(), tonic::Status> {
         use schema::accounts::dsl as a_dsl;
 
-        let account_id = if let Some(account) = self.get_account(conn, email).await? {
-            account
-        } else {
-            let id = Uuid::new_v4();
+        update(a_dsl::accounts)
+            .filter(a_dsl::id.eq(account_id))
+            .filter(a_dsl::verified.eq(false))
+            .set(a_dsl::verified.eq(true))
+            .execute(conn)
+            .await
+            .map_err(|e| AuthError::QueryFailed(e.to_string()))?;
 
-            insert_into(a_dsl::accounts)
-                .values(models::Account { id, email })
-                .execute(conn)
-                .await
-                .map_err(|e| AuthError::QueryFailed(e.to_string()))?;
+        Ok(())
+    }
 
-            id
-        };
+    /// Creates an email login intent and sends its code, or a decoy intent without an account.
+    /// The password hash is stored for the account once the code is used.
+    async fn insert_email_login_intent(
+        &self,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+        account_id: Option<Uuid>,
+        email: String,
+        password_hash: Option<&str>,
+    ) -> Result<Uuid, AuthError> {
+        use schema::email_login_intents::dsl as eli_dsl;
+
+        let pass_key: String = RNG.with(|rng| {
+            let mut rng = rng.borrow_mut();
+            (0..Self::SESSION_INTENT_CODE_LEN)
+                .map(|_| rng.sample(Alphanumeric) as char)
+                .collect()
+        });
+        let expires_at = OffsetDateTime::now_utc().saturating_add(Self::SESSION_INTENT_DURATION);
+        let id = Uuid::new_v4();
+        insert_into(eli_dsl::email_login_intents)
+            .values(models::EmailLoginIntent {
+                id,
+                account_id,
+                pass_key: pass_key.as_str(),
+                expires_at,
+                attempts: 0,
+                password_hash,
+            })
+            .execute(conn)
+            .await
+            .map_err(|e| AuthError::QueryFailed(e.to_string()))?;
+
+        if account_id.is_some() {
+            send_email(Email {
+                email,
+                template: "login_code".into(),
+                params: HashMap::from([("code".into(), pass_key)]),
+                locale: None,
+                ..Default::default()
+            });
+        }
 
-        Ok(account_id)
+        Ok(id)
     }
 
     async fn create_session(
         &self,
-        conn: &mut (impl AsyncConnection<Backend = Pg> + Send),
+        conn: &mut impl AsyncConnection<Backend = Pg>,
         account_id: Uuid,
         password_login: bool,
-    ) -> Result<SessionToken, tonic::Status> {
+        two_factor: bool,
+        client: &ClientInfo,
+    ) -> Result<SessionTokens, tonic::Status> {
         let token: String = RNG.with(|rng| {
             let mut rng = rng.borrow_mut();
             (0..Self::SESSION_TOKEN_LEN)
@@ -127,18 +322,483 @@ impl Auth {
 
         use schema::sessions::dsl as s_dsl;
 
+        let now = OffsetDateTime::now_utc();
+        let id = Uuid::new_v4();
         insert_into(s_dsl::sessions)
             .values(models::Session {
                 token: token.as_ref(),
                 account_id,
                 password_login,
-                expires_at: OffsetDateTime::now_utc().saturating_add(Self::SESSION_DURATION),
+                expires_at: now.saturating_add(Self::SESSION_DURATION),
+                two_factor,
+                id,
+                created_at: now,
+                last_used_at: now,
+                user_agent: client.user_agent.as_deref(),
+                ip: client.ip.as_deref(),
             })
             .execute(conn)
             .await
             .map_err(|e| AuthError::QueryFailed(e.to_string()))?;
 
-        Ok(SessionToken { token })
+        use schema::login_attempts::dsl as la_dsl;
+
+        // A successful login resets the failed attempts of the account
+        delete(la_dsl::login_attempts)
+            .filter(la_dsl::account_id.eq(account_id))
+            .execute(conn)
+            .await
+            .map_err(|e| AuthError::QueryFailed(e.to_string()))?;
+
+        let access_token = self
+            .access_token(conn, account_id, id, password_login, two_factor)
+            .await?;
+
+        Ok(SessionTokens {
+            access_token,
+            refresh_token: token,
+        })
+    }
+
+    /// Makes the gateway reject the access tokens issued for the sessions. The sessions
+    /// themselves are expected to be deleted already.
+    async fn revoke_sessions(
+        &self,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+        session_ids: &[Uuid],
+    ) -> Result<(), AuthError> {
+        use schema::revoked_sessions::dsl as rs_dsl;
+
+        insert_into(rs_dsl::revoked_sessions)
+            .values(
+                session_ids
+                    .iter()
+                    .map(|id| rs_dsl::session_id.eq(id))
+                    .collect::<Vec<_>>(),
+            )
+            .on_conflict_do_nothing()
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Deletes the session and revokes its access tokens.
+    async fn end_session(
+        &self,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+        session_id: Uuid,
+    ) -> Result<(), AuthError> {
+        use schema::sessions::dsl as s_dsl;
+
+        delete(s_dsl::sessions)
+            .filter(s_dsl::id.eq(session_id))
+            .execute(conn)
+            .await?;
+        self.revoke_sessions(conn, &[session_id]).await
+    }
+
+    /// Makes the gateway reject every access token of the account issued so far, while its
+    /// sessions can still be refreshed into new ones.
+    async fn revoke_access_tokens(
+        &self,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+        account_id: Uuid,
+    ) -> Result<(), AuthError> {
+        use schema::accounts::dsl as a_dsl;
+
+        update(a_dsl::accounts)
+            .filter(a_dsl::id.eq(account_id))
+            .set((
+                a_dsl::token_generation.eq(a_dsl::token_generation + 1),
+                a_dsl::tokens_revoked_at.eq(OffsetDateTime::now_utc()),
+            ))
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Issues a signed access token with everything the gateway needs to know about the
+    /// account, so it can authenticate requests without asking the auth microservice.
+    async fn access_token(
+        &self,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+        account_id: Uuid,
+        session_id: Uuid,
+        password_login: bool,
+        two_factor: bool,
+    ) -> Result<String, tonic::Status> {
+        use schema::scopes::dsl as sc_dsl;
+
+        let scopes = sc_dsl::scopes
+            .select(sc_dsl::scope)
+            .filter(sc_dsl::account_id.eq(account_id))
+            .load::<models::ScopeKind>(conn)
+            .await
+            .map_err(|e| AuthError::QueryFailed(e.to_string()))?;
+        let duration = if scopes.contains(&models::ScopeKind::Admin) {
+            Self::ADMIN_ACCESS_TOKEN_DURATION
+        } else {
+            Self::ACCESS_TOKEN_DURATION
+        };
+        let scopes = scopes
+            .into_iter()
+            .map(|kind| Scope::from(kind).as_str_name().to_string())
+            .collect();
+
+        use schema::accounts::dsl as a_dsl;
+
+        let token_generation = a_dsl::accounts
+            .select(a_dsl::token_generation)
+            .filter(a_dsl::id.eq(account_id))
+            .first::<i32>(conn)
+            .await
+            .map_err(|e| AuthError::QueryFailed(e.to_string()))?;
+
+        let account = self.describe_account(conn, account_id, vec![]).await?;
+
+        let now = OffsetDateTime::now_utc();
+        let claims = AccessClaims {
+            sub: account.id,
+            sid: session_id.to_string(),
+            scopes,
+            customer_id: account.customer_id,
+            retailer_id: account.retailer_id,
+            partner_id: account.partner_id,
+            password_login,
+            two_factor,
+            gen: token_generation,
+            iat: now.unix_timestamp(),
+            exp: (now + duration).unix_timestamp(),
+        };
+
+        Ok(
+            jsonwebtoken::encode(&Header::default(), &claims, &self.jwt_key)
+                .map_err(|e| AuthError::TokenSigningFailed(e.to_string()))?,
+        )
+    }
+
+    async fn check_not_blocked(
+        &self,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+        account_id: Uuid,
+    ) -> Result<(), tonic::Status> {
+        use schema::accounts::dsl as a_dsl;
+
+        let blocked = a_dsl::accounts
+            .select(a_dsl::blocked)
+            .filter(a_dsl::id.eq(account_id))
+            .first::<bool>(conn)
+            .await
+            .optional()
+            .map_err(|e| AuthError::QueryFailed(e.to_string()))?
+            .ok_or(AuthError::AccountNotFound)?;
+
+        if blocked {
+            Err(AuthError::AccountBlocked.into())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Refuses to log into the account while it is backing off from failed attempts.
+    /// Every failed attempt past `FREE_LOGIN_ATTEMPTS` doubles the time to wait.
+    ///
+    /// The attempt is counted as failed before it is checked, so concurrent attempts can't
+    /// all get past the backoff. A session resets the count, `release_login_attempt` takes
+    /// the attempt back when it succeeded without creating one.
+    async fn claim_login_attempt(
+        &self,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+        account_id: Uuid,
+    ) -> Result<(), AuthError> {
+        use schema::login_attempts::dsl as la_dsl;
+
+        loop {
+            let now = OffsetDateTime::now_utc();
+            let attempts = la_dsl::login_attempts
+                .select((la_dsl::failed_attempts, la_dsl::last_failed_at))
+                .filter(la_dsl::account_id.eq(account_id))
+                .first::<(i32, OffsetDateTime)>(conn)
+                .await
+                .optional()
+                .map_err(|e| AuthError::QueryFailed(e.to_string()))?;
+
+            let claimed = match attempts {
+                None => insert_into(la_dsl::login_attempts)
+                    .values((
+                        la_dsl::account_id.eq(account_id),
+                        la_dsl::failed_attempts.eq(1),
+                        la_dsl::last_failed_at.eq(now),
+                    ))
+                    .on_conflict_do_nothing()
+                    .execute(conn)
+                    .await
+                    .map_err(|e| AuthError::QueryFailed(e.to_string()))?,
+                Some((failed_attempts, last_failed_at)) => {
+                    if failed_attempts >= Self::FREE_LOGIN_ATTEMPTS {
+                        // Capping the exponent keeps the multiplication from overflowing
+                        let exponent = (failed_attempts - Self::FREE_LOGIN_ATTEMPTS).min(16) as u32;
+                        let backoff = (Self::LOGIN_BACKOFF_BASE * 2u32.pow(exponent))
+                            .min(Self::LOGIN_BACKOFF_MAX);
+                        let retry_after = last_failed_at + backoff - now;
+
+                        if retry_after.is_positive() {
+                            return Err(AuthError::TooManyAttempts(Some(retry_after)));
+                        }
+                    }
+
+                    update(la_dsl::login_attempts)
+                        .filter(la_dsl::account_id.eq(account_id))
+                        .filter(la_dsl::failed_attempts.eq(failed_attempts))
+                        .set((
+                            la_dsl::failed_attempts.eq(failed_attempts + 1),
+                            la_dsl::last_failed_at.eq(now),
+                        ))
+                        .execute(conn)
+                        .await
+                        .map_err(|e| AuthError::QueryFailed(e.to_string()))?
+                }
+            };
+
+            // Another attempt changed the count first, so the backoff is checked again
+            if claimed == 1 {
+                return Ok(());
+            }
+        }
+    }
+
+    async fn release_login_attempt(
+        &self,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+        account_id: Uuid,
+    ) -> Result<(), AuthError> {
+        use schema::login_attempts::dsl as la_dsl;
+
+        update(la_dsl::login_attempts)
+            .filter(la_dsl::account_id.eq(account_id))
+            .filter(la_dsl::failed_attempts.gt(0))
+            .set(la_dsl::failed_attempts.eq(la_dsl::failed_attempts - 1))
+            .execute(conn)
+            .await
+            .map_err(|e| AuthError::QueryFailed(e.to_string()))?;
+
+        Ok(())
+    }
+
+    /// Creates a session for the account, or a totp challenge that has to be solved
+    /// with `login_with_totp` first when the account has two factor authentication enabled.
+    async fn login(
+        &self,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+        account_id: Uuid,
+        password_login: bool,
+        client: &ClientInfo,
+    ) -> Result<LoginResult, tonic::Status> {
+        use schema::totp::dsl as t_dsl;
+
+        let totp_enabled = select(exists(
+            t_dsl::totp
+                .filter(t_dsl::account_id.eq(account_id))
+                .filter(t_dsl::confirmed.eq(true)),
+        ))
+        .get_result::<bool>(conn)
+        .await
+        .map_err(|e| AuthError::QueryFailed(e.to_string()))?;
+
+        let result = if totp_enabled {
+            let token: String = RNG.with(|rng| {
+                let mut rng = rng.borrow_mut();
+                (0..Self::TOTP_CHALLENGE_TOKEN_LEN)
+                    .map(|_| rng.sample(Alphanumeric) as char)
+                    .collect()
+            });
+
+            use schema::totp_challenges::dsl as tc_dsl;
+
+            insert_into(tc_dsl::totp_challenges)
+                .values(models::TotpChallenge {
+                    token: token.as_ref(),
+                    account_id,
+                    password_login,
+                    expires_at: OffsetDateTime::now_utc()
+                        .saturating_add(Self::SESSION_INTENT_DURATION),
+                    attempts: 0,
+                })
+                .execute(conn)
+                .await
+                .map_err(|e| AuthError::QueryFailed(e.to_string()))?;
+
+            login_result::Result::Challenge(TotpChallenge { token })
+        } else {
+            login_result::Result::Session(
+                self.create_session(conn, account_id, password_login, false, client)
+                    .await?,
+            )
+        };
+
+        Ok(LoginResult {
+            result: Some(result),
+        })
+    }
+
+    fn totp(secret: &str, email: String) -> Result<TOTP, AuthError> {
+        let secret = Secret::Encoded(secret.to_string())
+            .to_bytes()
+            .map_err(|e| AuthError::TotpError(e.to_string()))?;
+
+        TOTP::new(
+            Algorithm::SHA1,
+            Self::TOTP_DIGITS,
+            Self::TOTP_SKEW,
+            Self::TOTP_STEP,
+            secret,
+            Some(Self::TOTP_ISSUER.to_string()),
+            email,
+        )
+        .map_err(|e| AuthError::TotpError(e.to_string()))
+    }
+
+    /// Returns the totp secret of the account, if it has one, along with whether it was
+    /// confirmed and the email of the account.
+    async fn get_totp(
+        &self,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+        account_id: Uuid,
+    ) -> Result<Option<(String, bool, String)>, tonic::Status> {
+        use schema::accounts::dsl as a_dsl;
+        use schema::totp::dsl as t_dsl;
+
+        Ok(t_dsl::totp
+            .inner_join(a_dsl::accounts)
+            .select((t_dsl::secret, t_dsl::confirmed, a_dsl::email))
+            .filter(t_dsl::account_id.eq(account_id))
+            .first::<(String, bool, String)>(conn)
+            .await
+            .optional()
+            .map_err(|e| AuthError::QueryFailed(e.to_string()))?)
+    }
+
+    /// Checks the code against the current totp code, falling back to the recovery codes
+    /// of the account. A matching recovery code is used up.
+    /// Returns the step the code was generated for, allowing for `TOTP_SKEW` steps of
+    /// clock drift.
+    fn totp_step(totp: &TOTP, code: &str) -> Result<Option<i64>, AuthError> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|e| AuthError::TotpError(e.to_string()))?
+            .as_secs();
+        let current = now / Self::TOTP_STEP;
+        let skew = Self::TOTP_SKEW as u64;
+
+        // Checking every step on its own tells which one matched
+        let exact = TOTP {
+            skew: 0,
+            ..totp.clone()
+        };
+        Ok((current.saturating_sub(skew)..=current + skew)
+            .find(|step| exact.check(code, step * Self::TOTP_STEP))
+            .map(|step| step as i64))
+    }
+
+    async fn check_second_factor(
+        &self,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+        account_id: Uuid,
+        totp: &TOTP,
+        code: &str,
+    ) -> Result<bool, tonic::Status> {
+        if let Some(step) = Self::totp_step(totp, code)? {
+            use schema::totp::dsl as t_dsl;
+
+            // A code is only accepted once, so one that was seen can't be used again
+            let accepted = update(t_dsl::totp)
+                .filter(t_dsl::account_id.eq(account_id))
+                .filter(t_dsl::last_step.is_null().or(t_dsl::last_step.lt(step)))
+                .set(t_dsl::last_step.eq(step))
+                .execute(conn)
+                .await
+                .map_err(|e| AuthError::QueryFailed(e.to_string()))?;
+
+            return Ok(accepted == 1);
+        }
+
+        // Every recovery code is a slow hash to check, so only codes that look like one are
+        if code.len() != Self::RECOVERY_CODE_LEN || !code.chars().all(|c| c.is_ascii_alphanumeric())
+        {
+            return Ok(false);
+        }
+
+        use schema::totp_recovery_codes::dsl as trc_dsl;
+
+        let recovery_codes = trc_dsl::totp_recovery_codes
+            .select((trc_dsl::id, trc_dsl::hash))
+            .filter(trc_dsl::account_id.eq(account_id))
+            .load::<(Uuid, String)>(conn)
+            .await
+            .map_err(|e| AuthError::QueryFailed(e.to_string()))?;
+
+        for (id, hash) in recovery_codes {
+            let hash = PasswordHash::new(&hash).map_err(AuthError::PasswordHashingError)?;
+            if self.argon.verify_password(code.as_bytes(), &hash).is_ok() {
+                // The code was used by a concurrent attempt when it is already gone
+                let deleted = delete(trc_dsl::totp_recovery_codes)
+                    .filter(trc_dsl::id.eq(id))
+                    .execute(conn)
+                    .await
+                    .map_err(|e| AuthError::QueryFailed(e.to_string()))?;
+
+                return Ok(deleted == 1);
+            }
+        }
+
+        Ok(false)
+    }
+
+    async fn describe_account(
+        &self,
+        conn: &mut impl AsyncConnection<Backend = Pg>,
+        id: Uuid,
+        scopes: Vec<i32>,
+    ) -> Result<Account, tonic::Status> {
+        use schema::customers::dsl as c_dsl;
+
+        let customer_id = c_dsl::customers
+            .select(c_dsl::id)
+            .filter(c_dsl::account_id.eq(id))
+            .load::<Uuid>(conn)
+            .await
+            .map_err(|e| AuthError::QueryFailed(e.to_string()))?
+            .pop();
+
+        use schema::retailers::dsl as r_dsl;
+
+        let retailer_id = r_dsl::retailers
+            .select(r_dsl::id)
+            .filter(r_dsl::account_id.eq(id))
+            .load::<Uuid>(conn)
+            .await
+            .map_err(|e| AuthError::QueryFailed(e.to_string()))?
+            .pop();
+
+        use schema::partners::dsl as p_dsl;
+
+        let partner_id = p_dsl::partners
+            .select(p_dsl::id)
+            .filter(p_dsl::account_id.eq(id))
+            .load::<Uuid>(conn)
+            .await
+            .map_err(|e| AuthError::QueryFailed(e.to_string()))?
+            .pop();
+
+        Ok(Account {
+            id: id.to_string(),
+            customer_id: customer_id.map(|id| id.to_string()),
+            retailer_id: retailer_id.map(|id| id.to_string()),
+            partner_id: partner_id.map(|id| id.to_string()),
+            scopes,
+        })
     }
 }
 
@@ -157,14 +817,16 @@ impl lunu::auth::auth_server::Auth for Auth {
         use schema::scopes;
         use schema::sessions;
 
-        let (id, password_login, time) = sessions::dsl::sessions
+        let (id, password_login, two_factor, time, session_id) = sessions::dsl::sessions
             .select((
                 sessions::dsl::account_id,
                 sessions::dsl::password_login,
+                sessions::dsl::two_factor,
                 sessions::dsl::expires_at,
+                sessions::dsl::id,
             ))
             .filter(sessions::dsl::token.eq(&request.get_ref().token))
-            .first::<(Uuid, bool, OffsetDateTime)>(conn)
+            .first::<(Uuid, bool, bool, OffsetDateTime, Uuid)>(conn)
             .await
             .map_err(|e| AuthError::QueryFailed(e.to_string()))?;
 
@@ -180,8 +842,19 @@ impl lunu::auth::auth_server::Auth for Auth {
             Ok(tonic::Response::new(OptionalAccount {
                 account: None,
                 password_login: false,
+                two_factor: false,
+                session_id: None,
             }))
         } else {
+            self.check_not_blocked(conn.deref_mut(), id).await?;
+
+            update(sessions::dsl::sessions)
+                .filter(sessions::dsl::id.eq(session_id))
+                .set(sessions::dsl::last_used_at.eq(OffsetDateTime::now_utc()))
+                .execute(conn)
+                .await
+                .map_err(|e| AuthError::QueryFailed(e.to_string()))?;
+
             let scopes = sessions::dsl::sessions
                 .inner_join(
                     scopes::dsl::scopes.on(scopes::dsl::account_id.eq(sessions::dsl::account_id)),
@@ -195,45 +868,11 @@ impl lunu::auth::auth_server::Auth for Auth {
                 .map(|kind| kind as i32)
                 .collect();
 
-            use schema::customers::dsl as c_dsl;
-
-            let customer_id = c_dsl::customers
-                .select(c_dsl::id)
-                .filter(c_dsl::account_id.eq(id))
-                .load::<Uuid>(conn)
-                .await
-                .map_err(|e| AuthError::QueryFailed(e.to_string()))?
-                .pop();
-
-            use schema::retailers::dsl as r_dsl;
-
-            let retailer_id = r_dsl::retailers
-                .select(r_dsl::id)
-                .filter(r_dsl::account_id.eq(id))
-                .load::<Uuid>(conn)
-                .await
-                .map_err(|e| AuthError::QueryFailed(e.to_string()))?
-                .pop();
-
-            use schema::partners::dsl as p_dsl;
-
-            let partner_id = p_dsl::partners
-                .select(p_dsl::id)
-                .filter(p_dsl::account_id.eq(id))
-                .load::<Uuid>(conn)
-                .await
-                .map_err(|e| AuthError::QueryFailed(e.to_string()))?
-                .pop();
-
             Ok(tonic::Response::new(OptionalAccount {
-                account: Some(Account {
-                    id: id.to_string(),
-                    customer_id: customer_id.map(|id| id.to_string()),
-                    retailer_id: retailer_id.map(|id| id.to_string()),
-                    partner_id: partner_id.map(|id| id.to_string()),
-                    scopes,
-                }),
+                account: Some(self.describe_account(conn.deref_mut(), id, scopes).await?),
                 password_login,
+                two_factor,
+                session_id: Some(session_id.to_string()),
             }))
         }
     }
@@ -247,41 +886,16 @@ impl lunu::auth::auth_server::Auth for Auth {
             .get()
             .await
             .map_err(|_| AuthError::PoolConnectionFailed)?;
-        let email = request.into_inner().email;
-
-        let account_id = self.get_or_create_account(conn.deref_mut(), &email).await?;
-
-        use schema::email_login_intents::dsl as eli_dsl;
+        let AccountEmail { email, sign_up } = request.into_inner();
 
-        let pass_key: String = RNG.with(|rng| {
-            let mut rng = rng.borrow_mut();
-            (0..Self::SESSION_INTENT_CODE_LEN)
-                .map(|_| rng.sample(Alphanumeric) as char)
-                .collect()
-        });
-        let expires_at = OffsetDateTime::now_utc().saturating_add(Self::SESSION_INTENT_DURATION);
-        let id = Uuid::new_v4();
-        insert_into(eli_dsl::email_login_intents)
-            .values(models::EmailLoginIntent {
-                id,
-                account_id,
-                pass_key: pass_key.as_str(),
-                expires_at,
-            })
-            .execute(&mut conn)
-            .await
-            .map_err(|e| AuthError::QueryFailed(e.to_string()))?;
+        // Without an account a decoy intent is created, which no code is sent for and
+        // which no code logs into, so the response doesn't tell whether the account exists
+        let account_id = self
+            .intent_account(conn.deref_mut(), &email, sign_up)
+            .await?;
 
-        let mut client = MAIL_CLIENT
-            .get()
-            .expect("MAIL_CLIENT used before it was initalized")
-            .clone();
-        client
-            .send(Email {
-                email,
-                subject: "Pass key for lunu login".into(),
-                body_html: format!("<h1>{pass_key}</h1>"),
-            })
+        let id = self
+            .insert_email_login_intent(conn.deref_mut(), account_id, email, None)
             .await?;
 
         Ok(tonic::Response::new(EmailLoginIntent {
@@ -292,7 +906,7 @@ impl lunu::auth::auth_server::Auth for Auth {
     async fn login_with_email_login(
         &self,
         request: tonic::Request<EmailLoginParams>,
-    ) -> Result<tonic::Response<SessionToken>, tonic::Status> {
+    ) -> Result<tonic::Response<LoginResult>, tonic::Status> {
         let conn = &mut self
             .pool
             .get()
@@ -301,40 +915,102 @@ impl lunu::auth::auth_server::Auth for Auth {
         let resp = request.into_inner();
         let token = resp.token;
         let code = resp.code;
+        let client = resp.client.unwrap_or_default();
 
         use schema::email_login_intents::dsl as eli_dsl;
 
         let uuid = Uuid::from_str(&token).map_err(|_| AuthError::MalformedSessionToken)?;
         let session = eli_dsl::email_login_intents
-            .select((eli_dsl::account_id, eli_dsl::pass_key, eli_dsl::expires_at))
+            .select((
+                eli_dsl::account_id,
+                eli_dsl::pass_key,
+                eli_dsl::expires_at,
+                eli_dsl::password_hash,
+            ))
             .filter(eli_dsl::id.eq(uuid))
-            .load::<(Uuid, String, OffsetDateTime)>(conn)
+            .load::<(Option<Uuid>, String, OffsetDateTime, Option<String>)>(conn)
             .await
             .map_err(|e| AuthError::QueryFailed(e.to_string()))?
             .pop();
 
-        if let Some((account_id, pass_key, expires_at)) = session {
-            if expires_at < OffsetDateTime::now_utc() {
-                Err(AuthError::SessionIntentTimeout.into())
-            } else {
-                if pass_key == code {
-                    let token = self
-                        .create_session(conn.deref_mut(), account_id, false)
-                        .await?;
+        let Some((account_id, pass_key, expires_at, password_hash)) = session else {
+            return Err(AuthError::BadSessionToken.into());
+        };
+        if expires_at < OffsetDateTime::now_utc() {
+            return Err(AuthError::SessionIntentTimeout.into());
+        }
 
-                    delete(eli_dsl::email_login_intents)
-                        .filter(eli_dsl::id.eq(uuid))
-                        .execute(conn)
-                        .await
-                        .map_err(|e| AuthError::QueryFailed(e.to_string()))?;
+        // Only the intent counts the attempts, the account backoff would tell a decoy apart
+        // and let anyone lock an account out by asking for codes. The attempt is counted
+        // before the code is compared, so concurrent guesses can't get past `MAX_CODE_ATTEMPTS`
+        let Some(attempts) = update(eli_dsl::email_login_intents)
+            .filter(eli_dsl::id.eq(uuid))
+            .filter(eli_dsl::attempts.lt(Self::MAX_CODE_ATTEMPTS))
+            .set(eli_dsl::attempts.eq(eli_dsl::attempts + 1))
+            .returning(eli_dsl::attempts)
+            .get_result::<i32>(conn)
+            .await
+            .optional()
+            .map_err(|e| AuthError::QueryFailed(e.to_string()))?
+        else {
+            return Err(AuthError::TooManyAttempts(None).into());
+        };
 
-                    Ok(tonic::Response::new(token))
-                } else {
-                    Err(AuthError::PasscodeDoesNotMatch.into())
-                }
+        // A decoy intent counts the wrong codes like any other intent, but never matches
+        if let (Some(account_id), true) = (account_id, pass_key == code) {
+            self.check_not_blocked(conn.deref_mut(), account_id).await?;
+
+            // Only the attempt that deletes the intent gets to log in
+            let deleted = delete(eli_dsl::email_login_intents)
+                .filter(eli_dsl::id.eq(uuid))
+                .execute(conn)
+                .await
+                .map_err(|e| AuthError::QueryFailed(e.to_string()))?;
+            if deleted != 1 {
+                return Err(AuthError::BadSessionToken.into());
+            }
+
+            self.verify_account(conn.deref_mut(), account_id).await?;
+
+            // The account was signed up for with a password, which is kept now that
+            // the email is verified
+            if let Some(password_hash) = password_hash {
+                use schema::password_login::dsl as pl_dsl;
+
+                delete(pl_dsl::password_login)
+                    .filter(pl_dsl::account_id.eq(account_id))
+                    .execute(conn)
+                    .await
+                    .map_err(|e| AuthError::QueryFailed(e.to_string()))?;
+
+                insert_into(pl_dsl::password_login)
+                    .values(models::PasswordLogin {
+                        account_id,
+                        hash: &password_hash,
+                        created_at: OffsetDateTime::now_utc(),
+                    })
+                    .execute(conn)
+                    .await
+                    .map_err(|e| AuthError::QueryFailed(e.to_string()))?;
             }
+
+            let result = self
+                .login(conn.deref_mut(), account_id, false, &client)
+                .await?;
+
+            return Ok(tonic::Response::new(result));
+        }
+
+        if attempts >= Self::MAX_CODE_ATTEMPTS {
+            delete(eli_dsl::email_login_intents)
+                .filter(eli_dsl::id.eq(uuid))
+                .execute(conn)
+                .await
+                .map_err(|e| AuthError::QueryFailed(e.to_string()))?;
+
+            Err(AuthError::TooManyAttempts(None).into())
         } else {
-            Err(AuthError::BadSessionToken.into())
+            Err(AuthError::PasscodeDoesNotMatch.into())
         }
     }
 
@@ -342,14 +1018,20 @@ impl lunu::auth::auth_server::Auth for Auth {
         &self,
         request: tonic::Request<AccountEmail>,
     ) -> Result<tonic::Response<()>, tonic::Status> {
-        let AccountEmail { email } = request.into_inner();
+        let AccountEmail { email, sign_up } = request.into_inner();
         let conn = &mut self
             .pool
             .get()
             .await
             .map_err(|_| AuthError::PoolConnectionFailed)?;
 
-        let account_id = self.get_or_create_account(conn.deref_mut(), &email).await?;
+        // The response is the same whether or not a link was sent
+        let Some(account_id) = self
+            .intent_account(conn.deref_mut(), &email, sign_up)
+            .await?
+        else {
+            return Ok(tonic::Response::new(()));
+        };
 
         use schema::new_pass_login_intents::dsl as fpli_dsl;
 
@@ -370,26 +1052,68 @@ impl lunu::auth::auth_server::Auth for Auth {
             .await
             .map_err(|e| AuthError::QueryFailed(e.to_string()))?;
 
-        let mut client = MAIL_CLIENT
-            .get()
-            .expect("MAIL_CLIENT used before it was initalized")
-            .clone();
-        client
-            .send(Email {
-                email,
-                subject: "Login url token for lunu login".into(),
-                body_html: format!("<h1>{id}</h1>"),
-            })
-            .await?;
+        // The token is alphanumeric, so it needs no escaping in the query
+        let link = format!(
+            "{}{}?token={id}",
+            self.frontend_url,
+            Self::NEW_PASS_LOGIN_PATH
+        );
+        send_email(Email {
+            email,
+            template: "new_password".into(),
+            params: HashMap::from([("link".into(), link)]),
+            locale: None,
+            ..Default::default()
+        });
 
         Ok(tonic::Response::new(()))
     }
 
+    async fn check_new_pass_login_intent(
+        &self,
+        request: tonic::Request<NewPassLoginToken>,
+    ) -> Result<tonic::Response<NewPassLoginIntentInfo>, tonic::Status> {
+        let NewPassLoginToken { token } = request.into_inner();
+        let conn = &mut self
+            .pool
+            .get()
+            .await
+            .map_err(|_| AuthError::PoolConnectionFailed)?;
+
+        use schema::new_pass_login_intents::dsl as fpli_dsl;
+        let session = fpli_dsl::new_pass_login_intents
+            .select((fpli_dsl::account_id, fpli_dsl::expires_at))
+            .filter(fpli_dsl::id.eq(&token))
+            .load::<(Uuid, OffsetDateTime)>(conn)
+            .await
+            .map_err(|e| AuthError::QueryFailed(e.to_string()))?
+            .pop();
+
+        // Expired intents are left for the login or the cleanup to delete
+        let Some((account_id, expires_at)) = session else {
+            return Err(AuthError::BadSessionToken.into());
+        };
+        if expires_at < OffsetDateTime::now_utc() {
+            return Err(AuthError::BadSessionToken.into());
+        }
+        self.check_not_blocked(conn.deref_mut(), account_id).await?;
+
+        Ok(tonic::Response::new(NewPassLoginIntentInfo {
+            expires_at: expires_at.to_string(),
+        }))
+    }
+
     async fn login_with_new_pass_login(
         &self,
         request: tonic::Request<NewPassLoginParams>,
-    ) -> Result<tonic::Response<SessionToken>, tonic::Status> {
-        let NewPassLoginParams { token, password } = request.into_inner();
+    ) -> Result<tonic::Response<LoginResult>, tonic::Status> {
+        let NewPassLoginParams {
+            token,
+            password,
+            client,
+        } = request.into_inner();
+        let client = client.unwrap_or_default();
+        self.password_policy.check(&password)?;
         let conn = &mut self
             .pool
             .get()
@@ -408,6 +1132,7 @@ impl lunu::auth::auth_server::Auth for Auth {
         let Some((account_id, expires_at)) = session else {
             return Err(AuthError::BadSessionToken.into());
         };
+        self.check_not_blocked(conn.deref_mut(), account_id).await?;
 
         let now = OffsetDateTime::now_utc();
         if expires_at < now {
@@ -419,16 +1144,23 @@ impl lunu::auth::auth_server::Auth for Auth {
 
             Err(AuthError::BadSessionToken.into())
         } else {
-            let salt = SaltString::generate(&mut OsRng);
-            let argon = Argon2::default();
-
-            let password_hash = argon
-                .hash_password(password.as_bytes(), &salt)
-                .map_err(|err| AuthError::PasswordHashingError(err))?
-                .to_string();
+            let password_hash = self.hash_password(&password)?;
 
             use schema::password_login::dsl as pl_dsl;
 
+            use schema::sessions::dsl as s_dsl;
+
+            // Changing the password logs out every other session
+            let session_ids = delete(s_dsl::sessions)
+                .filter(s_dsl::account_id.eq(account_id))
+                .returning(s_dsl::id)
+                .get_results::<Uuid>(conn)
+                .await
+                .map_err(|e| AuthError::QueryFailed(e.to_string()))?;
+            self.revoke_sessions(conn.deref_mut(), &session_ids).await?;
+
+            self.verify_account(conn.deref_mut(), account_id).await?;
+
             // Delete an old password if it exists
             delete(pl_dsl::password_login)
                 .filter(pl_dsl::account_id.eq(account_id))
@@ -440,7 +1172,6 @@ impl lunu::auth::auth_server::Auth for Auth {
                 .values(models::PasswordLogin {
                     account_id,
                     hash: &password_hash,
-                    salt: salt.as_str(),
                     created_at: now,
                 })
                 .execute(conn)
@@ -453,163 +1184,1521 @@ impl lunu::auth::auth_server::Auth for Auth {
                 .await
                 .map_err(|e| AuthError::QueryFailed(e.to_string()))?;
 
-            let token = self
-                .create_session(conn.deref_mut(), account_id, true)
+            let result = self
+                .login(conn.deref_mut(), account_id, true, &client)
                 .await?;
 
-            Ok(tonic::Response::new(token))
+            Ok(tonic::Response::new(result))
         }
     }
 
     async fn create_with_password(
         &self,
         request: tonic::Request<PasswordParams>,
-    ) -> Result<tonic::Response<SessionToken>, tonic::Status> {
-        let PasswordParams { email, password } = request.into_inner();
+    ) -> Result<tonic::Response<EmailLoginIntent>, tonic::Status> {
+        let PasswordParams {
+            email, password, ..
+        } = request.into_inner();
+        self.password_policy.check(&password)?;
         let conn = &mut self
             .pool
             .get()
             .await
             .map_err(|_| AuthError::PoolConnectionFailed)?;
 
-        let account_id = self.create_account(conn.deref_mut(), &email).await?;
-
-        let salt = SaltString::generate(&mut OsRng);
-        let argon = Argon2::default();
-
-        let password_hash = argon
-            .hash_password(password.as_bytes(), &salt)
-            .map_err(|err| AuthError::PasswordHashingError(err))?
-            .to_string();
-
-        use schema::password_login::dsl as pl_dsl;
+        use schema::accounts::dsl as a_dsl;
 
-        insert_into(pl_dsl::password_login)
-            .values(models::PasswordLogin {
-                account_id,
-                hash: &password_hash,
-                salt: salt.as_str(),
-                created_at: OffsetDateTime::now_utc(),
-            })
-            .execute(conn)
+        let verified = a_dsl::accounts
+            .select(a_dsl::verified)
+            .filter(a_dsl::email.eq(&email))
+            .first::<bool>(conn)
             .await
+            .optional()
             .map_err(|e| AuthError::QueryFailed(e.to_string()))?;
+        if verified == Some(true) {
+            return Err(AuthError::AccountForEmailAreadyExists.into());
+        }
 
-        let token = self
-            .create_session(conn.deref_mut(), account_id, true)
+        // The account stays unverified and without a password until the code sent to
+        // its email is used with `login_with_email_login`
+        let account_id = self.intent_account(conn.deref_mut(), &email, true).await?;
+        let password_hash = self.hash_password(&password)?;
+        let id = self
+            .insert_email_login_intent(conn.deref_mut(), account_id, email, Some(&password_hash))
             .await?;
 
-        Ok(tonic::Response::new(token))
+        Ok(tonic::Response::new(EmailLoginIntent {
+            token: id.to_string(),
+        }))
     }
 
     async fn login_with_password(
         &self,
         request: tonic::Request<PasswordParams>,
-    ) -> Result<tonic::Response<SessionToken>, tonic::Status> {
-        let PasswordParams { email, password } = request.into_inner();
+    ) -> Result<tonic::Response<LoginResult>, tonic::Status> {
+        let PasswordParams {
+            email,
+            password,
+            client,
+        } = request.into_inner();
+        let client = client.unwrap_or_default();
         let conn = &mut self
             .pool
             .get()
             .await
             .map_err(|_| AuthError::PoolConnectionFailed)?;
 
-        let account_id = self
-            .get_account(conn.deref_mut(), &email)
-            .await?
-            .ok_or(AuthError::NoAccountForEmail(email))?;
-
+        use schema::accounts::dsl as a_dsl;
         use schema::password_login::dsl as pl_dsl;
-        let (hash, salt) = pl_dsl::password_login
-            .select((pl_dsl::hash, pl_dsl::salt))
-            .filter(pl_dsl::account_id.eq(account_id))
-            .first::<(String, String)>(conn)
+
+        let login = a_dsl::accounts
+            .left_join(pl_dsl::password_login)
+            .select((
+                a_dsl::id,
+                a_dsl::verified,
+                a_dsl::blocked,
+                pl_dsl::hash.nullable(),
+            ))
+            .filter(a_dsl::email.eq(&email))
+            .first::<(Uuid, bool, bool, Option<String>)>(conn)
             .await
             .optional()
-            .map_err(|e| AuthError::QueryFailed(e.to_string()))?
-            .ok_or(AuthError::AccountHasNoPasswordLogin)?;
+            .map_err(|e| AuthError::QueryFailed(e.to_string()))?;
 
-        let salt = SaltString::from_b64(&salt).expect("Failed to parse the stored salt");
-        let argon = Argon2::default();
+        // Without an account or a password the dummy hash is verified instead, so every
+        // failed login takes as long and fails the same way
+        let (account, hash) = match login {
+            Some((account_id, verified, blocked, Some(hash))) => {
+                (Some((account_id, verified, blocked)), hash)
+            }
+            _ => (None, self.dummy_hash.clone()),
+        };
+        let hash = PasswordHash::new(&hash).map_err(AuthError::PasswordHashingError)?;
 
-        if hash
-            == argon
-                .hash_password(password.as_bytes(), &salt)
-                .map_err(|err| AuthError::PasswordHashingError(err))?
-                .to_string()
-        {
-            let token = self
-                .create_session(conn.deref_mut(), account_id, true)
+        if let Some((account_id, ..)) = account {
+            self.claim_login_attempt(conn.deref_mut(), account_id)
                 .await?;
+        }
 
-            Ok(tonic::Response::new(token))
-        } else {
-            Err(AuthError::WrongPassword.into())
+        // The verification is done with the parameters stored in the hash
+        let matches = self
+            .argon
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok();
+        let Some((account_id, verified, blocked)) = account.filter(|_| matches) else {
+            return Err(AuthError::WrongPassword.into());
+        };
+
+        // The state of the account is only told to someone who knows the password
+        if blocked {
+            return Err(AuthError::AccountBlocked.into());
+        }
+        // A password only logs in once the email of the account was verified
+        if !verified {
+            return Err(AuthError::AccountNotVerified.into());
+        }
+
+        // Upgrading the hash while the plain password is at hand
+        if self.needs_rehash(&hash) {
+            let password_hash = self.hash_password(&password)?;
+
+            update(pl_dsl::password_login)
+                .filter(pl_dsl::account_id.eq(account_id))
+                .set(pl_dsl::hash.eq(&password_hash))
+                .execute(conn)
+                .await
+                .map_err(|e| AuthError::QueryFailed(e.to_string()))?;
         }
+
+        let result = self
+            .login(conn.deref_mut(), account_id, true, &client)
+            .await?;
+        self.release_login_attempt(conn.deref_mut(), account_id)
+            .await?;
+
+        Ok(tonic::Response::new(result))
     }
 
-    async fn cleanup_db(
+    async fn login_with_totp(
         &self,
-        _request: tonic::Request<()>,
-    ) -> Result<tonic::Response<()>, tonic::Status> {
+        request: tonic::Request<TotpLoginParams>,
+    ) -> Result<tonic::Response<SessionTokens>, tonic::Status> {
+        let TotpLoginParams {
+            token,
+            code,
+            client,
+        } = request.into_inner();
+        let client = client.unwrap_or_default();
         let conn = &mut self
             .pool
             .get()
             .await
             .map_err(|_| AuthError::PoolConnectionFailed)?;
-        let now = OffsetDateTime::now_utc();
 
-        use schema::new_pass_login_intents::dsl as fpli_dsl;
-        delete(fpli_dsl::new_pass_login_intents)
-            .filter(fpli_dsl::expires_at.lt(now))
-            .execute(conn)
+        use schema::totp_challenges::dsl as tc_dsl;
+
+        let Some((account_id, password_login, expires_at)) = tc_dsl::totp_challenges
+            .select((
+                tc_dsl::account_id,
+                tc_dsl::password_login,
+                tc_dsl::expires_at,
+            ))
+            .filter(tc_dsl::token.eq(&token))
+            .first::<(Uuid, bool, OffsetDateTime)>(conn)
             .await
-            .map_err(|e| AuthError::QueryFailed(e.to_string()))?;
+            .optional()
+            .map_err(|e| AuthError::QueryFailed(e.to_string()))?
+        else {
+            return Err(AuthError::BadSessionToken.into());
+        };
 
-        use schema::email_login_intents::dsl as eli_dsl;
-        delete(eli_dsl::email_login_intents)
-            .filter(eli_dsl::expires_at.lt(now))
+        if expires_at < OffsetDateTime::now_utc() {
+            delete(tc_dsl::totp_challenges)
+                .filter(tc_dsl::token.eq(&token))
+                .execute(conn)
+                .await
+                .map_err(|e| AuthError::QueryFailed(e.to_string()))?;
+
+            return Err(AuthError::SessionIntentTimeout.into());
+        }
+
+        self.check_not_blocked(conn.deref_mut(), account_id).await?;
+
+        let Some((secret, true, email)) = self.get_totp(conn.deref_mut(), account_id).await? else {
+            return Err(AuthError::TotpNotEnabled.into());
+        };
+        let totp = Self::totp(&secret, email)?;
+
+        self.claim_login_attempt(conn.deref_mut(), account_id)
+            .await?;
+
+        // The attempt is counted before the code is checked, so concurrent guesses can't
+        // get past `MAX_CODE_ATTEMPTS`
+        let Some(attempts) = update(tc_dsl::totp_challenges)
+            .filter(tc_dsl::token.eq(&token))
+            .filter(tc_dsl::attempts.lt(Self::MAX_CODE_ATTEMPTS))
+            .set(tc_dsl::attempts.eq(tc_dsl::attempts + 1))
+            .returning(tc_dsl::attempts)
+            .get_result::<i32>(conn)
+            .await
+            .optional()
+            .map_err(|e| AuthError::QueryFailed(e.to_string()))?
+        else {
+            return Err(AuthError::TooManyAttempts(None).into());
+        };
+
+        if !self
+            .check_second_factor(conn.deref_mut(), account_id, &totp, &code)
+            .await?
+        {
+            if attempts >= Self::MAX_CODE_ATTEMPTS {
+                delete(tc_dsl::totp_challenges)
+                    .filter(tc_dsl::token.eq(&token))
+                    .execute(conn)
+                    .await
+                    .map_err(|e| AuthError::QueryFailed(e.to_string()))?;
+
+                return Err(AuthError::TooManyAttempts(None).into());
+            }
+
+            return Err(AuthError::WrongTotpCode.into());
+        }
+
+        // Only the attempt that deletes the challenge gets a session
+        let deleted = delete(tc_dsl::totp_challenges)
+            .filter(tc_dsl::token.eq(&token))
             .execute(conn)
             .await
             .map_err(|e| AuthError::QueryFailed(e.to_string()))?;
+        if deleted != 1 {
+            return Err(AuthError::BadSessionToken.into());
+        }
 
-        use schema::sessions::dsl as s_dsl;
-        delete(s_dsl::sessions)
-            .filter(s_dsl::expires_at.lt(now))
+        let token = self
+            .create_session(conn.deref_mut(), account_id, password_login, true, &client)
+            .await?;
+
+        Ok(tonic::Response::new(token))
+    }
+
+    async fn enroll_totp(
+        &self,
+        request: tonic::Request<AccountId>,
+    ) -> Result<tonic::Response<TotpEnrollment>, tonic::Status> {
+        let account_id =
+            Uuid::from_str(&request.get_ref().id).map_err(|_| AuthError::MalformedAccountId)?;
+        let conn = &mut self
+            .pool
+            .get()
+            .await
+            .map_err(|_| AuthError::PoolConnectionFailed)?;
+
+        if let Some((_, true, _)) = self.get_totp(conn.deref_mut(), account_id).await? {
+            return Err(AuthError::TotpAlreadyEnabled.into());
+        }
+
+        use schema::accounts::dsl as a_dsl;
+
+        let email = a_dsl::accounts
+            .select(a_dsl::email)
+            .filter(a_dsl::id.eq(account_id))
+            .first::<String>(conn)
+            .await
+            .optional()
+            .map_err(|e| AuthError::QueryFailed(e.to_string()))?
+            .ok_or(AuthError::AccountNotFound)?;
+
+        let mut secret = [0; Self::TOTP_SECRET_LEN];
+        OsRng.fill(&mut secret);
+        let secret = Secret::Raw(secret.to_vec()).to_encoded().to_string();
+        let otpauth_url = Self::totp(&secret, email)?.get_url();
+
+        use schema::totp::dsl as t_dsl;
+
+        // Enrolling again before confirming replaces the unconfirmed secret
+        insert_into(t_dsl::totp)
+            .values(models::Totp {
+                account_id,
+                secret: &secret,
+                confirmed: false,
+                created_at: OffsetDateTime::now_utc(),
+            })
+            .on_conflict(t_dsl::account_id)
+            .do_update()
+            .set((
+                t_dsl::secret.eq(&secret),
+                t_dsl::created_at.eq(OffsetDateTime::now_utc()),
+                t_dsl::last_step.eq(None::<i64>),
+            ))
             .execute(conn)
             .await
             .map_err(|e| AuthError::QueryFailed(e.to_string()))?;
 
-        Ok(tonic::Response::new(()))
+        Ok(tonic::Response::new(TotpEnrollment {
+            secret,
+            otpauth_url,
+        }))
     }
-}
 
-enum AuthError {
-    BadSessionToken,
-    MalformedSessionToken,
-    SessionIntentTimeout,
-    PasscodeDoesNotMatch,
-    QueryFailed(String),
-    PasswordHashingError(argon2::password_hash::Error),
-    PoolConnectionFailed,
-    AccountForEmailAreadyExists,
-    NoAccountForEmail(String),
-    AccountHasNoPasswordLogin,
-    WrongPassword,
-}
+    async fn confirm_totp(
+        &self,
+        request: tonic::Request<TotpCode>,
+    ) -> Result<tonic::Response<RecoveryCodes>, tonic::Status> {
+        let TotpCode { account_id, code } = request.into_inner();
+        let account_id = Uuid::from_str(&account_id).map_err(|_| AuthError::MalformedAccountId)?;
+        let conn = &mut self
+            .pool
+            .get()
+            .await
+            .map_err(|_| AuthError::PoolConnectionFailed)?;
 
-impl From<AuthError> for tonic::Status {
-    fn from(value: AuthError) -> Self {
-        match value {
-            AuthError::BadSessionToken => tonic::Status::invalid_argument(
-                "Session token does not correspond to a session intent",
-            ),
-            AuthError::MalformedSessionToken => {
-                tonic::Status::invalid_argument("Malformed session token")
+        let (secret, confirmed, email) = self
+            .get_totp(conn.deref_mut(), account_id)
+            .await?
+            .ok_or(AuthError::TotpNotEnrolled)?;
+        if confirmed {
+            return Err(AuthError::TotpAlreadyEnabled.into());
+        }
+
+        // Guesses back off like logins, a session alone mustn't be enough to find the code
+        self.claim_login_attempt(conn.deref_mut(), account_id)
+            .await?;
+        let Some(step) = Self::totp_step(&Self::totp(&secret, email)?, &code)? else {
+            return Err(AuthError::WrongTotpCode.into());
+        };
+        self.release_login_attempt(conn.deref_mut(), account_id)
+            .await?;
+
+        let codes: Vec<String> = RNG.with(|rng| {
+            let mut rng = rng.borrow_mut();
+            (0..Self::RECOVERY_CODE_COUNT)
+                .map(|_| {
+                    (0..Self::RECOVERY_CODE_LEN)
+                        .map(|_| rng.sample(Alphanumeric) as char)
+                        .collect()
+                })
+                .collect()
+        });
+        let hashes = codes
+            .iter()
+            .map(|code| self.hash_password(code))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        conn.transaction::<_, AuthError, _>(|conn| {
+            async move {
+                use schema::totp::dsl as t_dsl;
+                use schema::totp_recovery_codes::dsl as trc_dsl;
+
+                update(t_dsl::totp)
+                    .filter(t_dsl::account_id.eq(account_id))
+                    .set((t_dsl::confirmed.eq(true), t_dsl::last_step.eq(step)))
+                    .execute(conn)
+                    .await?;
+
+                delete(trc_dsl::totp_recovery_codes)
+                    .filter(trc_dsl::account_id.eq(account_id))
+                    .execute(conn)
+                    .await?;
+
+                insert_into(trc_dsl::totp_recovery_codes)
+                    .values(
+                        hashes
+                            .iter()
+                            .map(|hash| models::TotpRecoveryCode {
+                                id: Uuid::new_v4(),
+                                account_id,
+                                hash,
+                            })
+                            .collect::<Vec<_>>(),
+                    )
+                    .execute(conn)
+                    .await?;
+
+                Ok(())
             }
-            AuthError::SessionIntentTimeout => {
-                tonic::Status::resource_exhausted("This session token has already timed out")
+            .scope_boxed()
+        })
+        .await?;
+
+        Ok(tonic::Response::new(RecoveryCodes { codes }))
+    }
+
+    async fn disable_totp(
+        &self,
+        request: tonic::Request<TotpCode>,
+    ) -> Result<tonic::Response<()>, tonic::Status> {
+        let TotpCode { account_id, code } = request.into_inner();
+        let account_id = Uuid::from_str(&account_id).map_err(|_| AuthError::MalformedAccountId)?;
+        let conn = &mut self
+            .pool
+            .get()
+            .await
+            .map_err(|_| AuthError::PoolConnectionFailed)?;
+
+        let Some((secret, true, email)) = self.get_totp(conn.deref_mut(), account_id).await? else {
+            return Err(AuthError::TotpNotEnabled.into());
+        };
+        let totp = Self::totp(&secret, email)?;
+
+        // Guesses back off like logins, a session alone mustn't be enough to turn off the
+        // second factor, and every guess may hash the recovery codes
+        self.claim_login_attempt(conn.deref_mut(), account_id)
+            .await?;
+        if !self
+            .check_second_factor(conn.deref_mut(), account_id, &totp, &code)
+            .await?
+        {
+            return Err(AuthError::WrongTotpCode.into());
+        }
+        self.release_login_attempt(conn.deref_mut(), account_id)
+            .await?;
+
+        conn.transaction::<_, AuthError, _>(|conn| {
+            async move {
+                use schema::totp::dsl as t_dsl;
+                use schema::totp_recovery_codes::dsl as trc_dsl;
+
+                delete(trc_dsl::totp_recovery_codes)
+                    .filter(trc_dsl::account_id.eq(account_id))
+                    .execute(conn)
+                    .await?;
+
+                delete(t_dsl::totp)
+                    .filter(t_dsl::account_id.eq(account_id))
+                    .execute(conn)
+                    .await?;
+
+                Ok(())
             }
-            AuthError::PasscodeDoesNotMatch => {
+            .scope_boxed()
+        })
+        .await?;
+
+        Ok(tonic::Response::new(()))
+    }
+
+    async fn create_api_key(
+        &self,
+        request: tonic::Request<NewApiKey>,
+    ) -> Result<tonic::Response<ApiKey>, tonic::Status> {
+        let NewApiKey {
+            account_id,
+            name,
+            scopes,
+        } = request.into_inner();
+        let account_id = Uuid::from_str(&account_id).map_err(|_| AuthError::MalformedAccountId)?;
+        let conn = &mut self
+            .pool
+            .get()
+            .await
+            .map_err(|_| AuthError::PoolConnectionFailed)?;
+
+        use schema::scopes::dsl as sc_dsl;
+
+        let account_scopes = sc_dsl::scopes
+            .select(sc_dsl::scope)
+            .filter(sc_dsl::account_id.eq(account_id))
+            .load::<models::ScopeKind>(conn)
+            .await
+            .map_err(|e| AuthError::QueryFailed(e.to_string()))?;
+
+        // An api key can only be given a subset of the scopes of its account
+        let scopes = scopes
+            .into_iter()
+            .map(|scope| {
+                let scope = Scope::from_i32(scope)
+                    .map(models::ScopeKind::from)
+                    .ok_or(AuthError::UnknownScope(scope))?;
+                if account_scopes.contains(&scope) {
+                    Ok(scope)
+                } else {
+                    Err(AuthError::ScopeNotGranted(scope))
+                }
+            })
+            .collect::<Result<HashSet<_>, _>>()?;
+
+        let secret: String = RNG.with(|rng| {
+            let mut rng = rng.borrow_mut();
+            (0..Self::API_KEY_SECRET_LEN)
+                .map(|_| rng.sample(Alphanumeric) as char)
+                .collect()
+        });
+        let hash = self.hash_password(&secret)?;
+
+        let id = Uuid::new_v4();
+        conn.transaction::<_, AuthError, _>(|conn| {
+            async move {
+                use schema::api_key_scopes::dsl as aks_dsl;
+                use schema::api_keys::dsl as ak_dsl;
+
+                insert_into(ak_dsl::api_keys)
+                    .values(models::ApiKey {
+                        id,
+                        account_id,
+                        name: &name,
+                        hash: &hash,
+                        created_at: OffsetDateTime::now_utc(),
+                        last_used_at: None,
+                    })
+                    .execute(conn)
+                    .await
+                    .map_err(|e| AuthError::QueryFailed(e.to_string()))?;
+
+                if !scopes.is_empty() {
+                    insert_into(aks_dsl::api_key_scopes)
+                        .values(
+                            scopes
+                                .into_iter()
+                                .map(|scope| models::ApiKeyScope {
+                                    api_key_id: id,
+                                    scope,
+                                })
+                                .collect::<Vec<_>>(),
+                        )
+                        .execute(conn)
+                        .await
+                        .map_err(|e| AuthError::QueryFailed(e.to_string()))?;
+                }
+
+                Ok(())
+            }
+            .scope_boxed()
+        })
+        .await?;
+
+        Ok(tonic::Response::new(ApiKey {
+            id: id.to_string(),
+            key: format!("{}{}_{secret}", Self::API_KEY_PREFIX, id.simple()),
+        }))
+    }
+
+    async fn list_api_keys(
+        &self,
+        request: tonic::Request<AccountId>,
+    ) -> Result<tonic::Response<ApiKeys>, tonic::Status> {
+        let account_id =
+            Uuid::from_str(&request.get_ref().id).map_err(|_| AuthError::MalformedAccountId)?;
+        let conn = &mut self
+            .pool
+            .get()
+            .await
+            .map_err(|_| AuthError::PoolConnectionFailed)?;
+
+        use schema::api_key_scopes::dsl as aks_dsl;
+        use schema::api_keys::dsl as ak_dsl;
+
+        let mut scopes = HashMap::<Uuid, Vec<i32>>::new();
+        for (api_key_id, scope) in aks_dsl::api_key_scopes
+            .inner_join(ak_dsl::api_keys)
+            .select((aks_dsl::api_key_id, aks_dsl::scope))
+            .filter(ak_dsl::account_id.eq(account_id))
+            .load::<(Uuid, models::ScopeKind)>(conn)
+            .await
+            .map_err(|e| AuthError::QueryFailed(e.to_string()))?
+        {
+            scopes.entry(api_key_id).or_default().push(scope as i32);
+        }
+
+        let api_keys = ak_dsl::api_keys
+            .select((
+                ak_dsl::id,
+                ak_dsl::name,
+                ak_dsl::created_at,
+                ak_dsl::last_used_at,
+            ))
+            .filter(ak_dsl::account_id.eq(account_id))
+            .order(ak_dsl::created_at)
+            .load::<(Uuid, String, OffsetDateTime, Option<OffsetDateTime>)>(conn)
+            .await
+            .map_err(|e| AuthError::QueryFailed(e.to_string()))?
+            .into_iter()
+            .map(|(id, name, created_at, last_used_at)| ApiKeyDesc {
+                id: id.to_string(),
+                name,
+                scopes: scopes.remove(&id).unwrap_or_default(),
+                created_at: created_at.to_string(),
+                last_used_at: last_used_at.map(|time| time.to_string()),
+            })
+            .collect();
+
+        Ok(tonic::Response::new(ApiKeys { api_keys }))
+    }
+
+    async fn revoke_api_key(
+        &self,
+        request: tonic::Request<ApiKeyId>,
+    ) -> Result<tonic::Response<()>, tonic::Status> {
+        let ApiKeyId { account_id, id } = request.into_inner();
+        let account_id = Uuid::from_str(&account_id).map_err(|_| AuthError::MalformedAccountId)?;
+        let id = Uuid::from_str(&id).map_err(|_| AuthError::MalformedApiKeyId)?;
+        let conn = &mut self
+            .pool
+            .get()
+            .await
+            .map_err(|_| AuthError::PoolConnectionFailed)?;
+
+        use schema::api_keys::dsl as ak_dsl;
+
+        let deleted = delete(ak_dsl::api_keys)
+            .filter(ak_dsl::id.eq(id))
+            .filter(ak_dsl::account_id.eq(account_id))
+            .execute(conn)
+            .await
+            .map_err(|e| AuthError::QueryFailed(e.to_string()))?;
+
+        if deleted == 0 {
+            return Err(AuthError::ApiKeyNotFound.into());
+        }
+
+        Ok(tonic::Response::new(()))
+    }
+
+    async fn fetch_api_key_account(
+        &self,
+        request: tonic::Request<ApiKeyToken>,
+    ) -> Result<tonic::Response<OptionalAccount>, tonic::Status> {
+        let no_account = OptionalAccount {
+            account: None,
+            password_login: false,
+            two_factor: false,
+            session_id: None,
+        };
+
+        let Some((id, secret)) = request
+            .get_ref()
+            .key
+            .strip_prefix(Self::API_KEY_PREFIX)
+            .and_then(|key| key.split_once('_'))
+        else {
+            return Ok(tonic::Response::new(no_account));
+        };
+        let Ok(id) = Uuid::from_str(id) else {
+            return Ok(tonic::Response::new(no_account));
+        };
+
+        let conn = &mut self
+            .pool
+            .get()
+            .await
+            .map_err(|_| AuthError::PoolConnectionFailed)?;
+
+        use schema::api_keys::dsl as ak_dsl;
+
+        let Some((account_id, hash)) = ak_dsl::api_keys
+            .select((ak_dsl::account_id, ak_dsl::hash))
+            .filter(ak_dsl::id.eq(id))
+            .first::<(Uuid, String)>(conn)
+            .await
+            .optional()
+            .map_err(|e| AuthError::QueryFailed(e.to_string()))?
+        else {
+            return Ok(tonic::Response::new(no_account));
+        };
+
+        let hash = PasswordHash::new(&hash).map_err(AuthError::PasswordHashingError)?;
+        if self
+            .argon
+            .verify_password(secret.as_bytes(), &hash)
+            .is_err()
+        {
+            return Ok(tonic::Response::new(no_account));
+        }
+
+        self.check_not_blocked(conn.deref_mut(), account_id).await?;
+
+        update(ak_dsl::api_keys)
+            .filter(ak_dsl::id.eq(id))
+            .set(ak_dsl::last_used_at.eq(OffsetDateTime::now_utc()))
+            .execute(conn)
+            .await
+            .map_err(|e| AuthError::QueryFailed(e.to_string()))?;
+
+        use schema::api_key_scopes::dsl as aks_dsl;
+        use schema::scopes::dsl as sc_dsl;
+
+        // Scopes revoked from the account since the key was created are dropped from it too
+        let account_scopes = sc_dsl::scopes
+            .select(sc_dsl::scope)
+            .filter(sc_dsl::account_id.eq(account_id))
+            .load::<models::ScopeKind>(conn)
+            .await
+            .map_err(|e| AuthError::QueryFailed(e.to_string()))?;
+        let scopes = aks_dsl::api_key_scopes
+            .select(aks_dsl::scope)
+            .filter(aks_dsl::api_key_id.eq(id))
+            .load::<models::ScopeKind>(conn)
+            .await
+            .map_err(|e| AuthError::QueryFailed(e.to_string()))?
+            .into_iter()
+            .filter(|scope| account_scopes.contains(scope))
+            .map(|kind| kind as i32)
+            .collect();
+
+        Ok(tonic::Response::new(OptionalAccount {
+            account: Some(
+                self.describe_account(conn.deref_mut(), account_id, scopes)
+                    .await?,
+            ),
+            password_login: false,
+            two_factor: false,
+            session_id: None,
+        }))
+    }
+
+    async fn block_account(
+        &self,
+        request: tonic::Request<BlockAccountParams>,
+    ) -> Result<tonic::Response<()>, tonic::Status> {
+        let BlockAccountParams { account_id, reason } = request.into_inner();
+        let account_id = Uuid::from_str(&account_id).map_err(|_| AuthError::MalformedAccountId)?;
+        let conn = &mut self
+            .pool
+            .get()
+            .await
+            .map_err(|_| AuthError::PoolConnectionFailed)?;
+
+        // Blocking also ends every session and pending login of the account, so it can't
+        // be used any more from the moment it is blocked
+        conn.transaction::<_, AuthError, _>(|conn| {
+            async move {
+                use schema::accounts::dsl as a_dsl;
+
+                let updated = update(a_dsl::accounts)
+                    .filter(a_dsl::id.eq(account_id))
+                    .set((
+                        a_dsl::blocked.eq(true),
+                        a_dsl::blocked_reason.eq(reason),
+                        a_dsl::blocked_at.eq(OffsetDateTime::now_utc()),
+                        // Revokes the access tokens that are still valid
+                        a_dsl::token_generation.eq(a_dsl::token_generation + 1),
+                        a_dsl::tokens_revoked_at.eq(OffsetDateTime::now_utc()),
+                    ))
+                    .execute(conn)
+                    .await?;
+
+                if updated == 0 {
+                    return Err(AuthError::AccountNotFound);
+                }
+
+                use schema::sessions::dsl as s_dsl;
+                delete(s_dsl::sessions)
+                    .filter(s_dsl::account_id.eq(account_id))
+                    .execute(conn)
+                    .await?;
+
+                use schema::email_login_intents::dsl as eli_dsl;
+                delete(eli_dsl::email_login_intents)
+                    .filter(eli_dsl::account_id.eq(account_id))
+                    .execute(conn)
+                    .await?;
+
+                use schema::new_pass_login_intents::dsl as fpli_dsl;
+                delete(fpli_dsl::new_pass_login_intents)
+                    .filter(fpli_dsl::account_id.eq(account_id))
+                    .execute(conn)
+                    .await?;
+
+                use schema::totp_challenges::dsl as tc_dsl;
+                delete(tc_dsl::totp_challenges)
+                    .filter(tc_dsl::account_id.eq(account_id))
+                    .execute(conn)
+                    .await?;
+
+                Ok(())
+            }
+            .scope_boxed()
+        })
+        .await?;
+
+        Ok(tonic::Response::new(()))
+    }
+
+    async fn unblock_account(
+        &self,
+        request: tonic::Request<AccountId>,
+    ) -> Result<tonic::Response<()>, tonic::Status> {
+        let account_id =
+            Uuid::from_str(&request.get_ref().id).map_err(|_| AuthError::MalformedAccountId)?;
+        let conn = &mut self
+            .pool
+            .get()
+            .await
+            .map_err(|_| AuthError::PoolConnectionFailed)?;
+
+        use schema::accounts::dsl as a_dsl;
+
+        let updated = update(a_dsl::accounts)
+            .filter(a_dsl::id.eq(account_id))
+            .set((
+                a_dsl::blocked.eq(false),
+                a_dsl::blocked_reason.eq(None::<String>),
+                a_dsl::blocked_at.eq(None::<OffsetDateTime>),
+            ))
+            .execute(conn)
+            .await
+            .map_err(|e| AuthError::QueryFailed(e.to_string()))?;
+
+        if updated == 0 {
+            return Err(AuthError::AccountNotFound.into());
+        }
+
+        Ok(tonic::Response::new(()))
+    }
+
+    async fn refresh_session(
+        &self,
+        request: tonic::Request<SessionToken>,
+    ) -> Result<tonic::Response<SessionTokens>, tonic::Status> {
+        let conn = &mut self
+            .pool
+            .get()
+            .await
+            .map_err(|_| AuthError::PoolConnectionFailed)?;
+
+        let old_token = request.into_inner().token;
+
+        use schema::sessions::dsl as s_dsl;
+
+        let Some((id, account_id, password_login, two_factor, expires_at)) = s_dsl::sessions
+            .select((
+                s_dsl::id,
+                s_dsl::account_id,
+                s_dsl::password_login,
+                s_dsl::two_factor,
+                s_dsl::expires_at,
+            ))
+            .filter(s_dsl::token.eq(&old_token))
+            .first::<(Uuid, Uuid, bool, bool, OffsetDateTime)>(conn)
+            .await
+            .optional()
+            .map_err(|e| AuthError::QueryFailed(e.to_string()))?
+        else {
+            use schema::retired_session_tokens::dsl as rst_dsl;
+
+            // A refresh token that was rotated out is only presented again when it was
+            // stolen, so the session is ended for the thief and the owner alike
+            let retired = rst_dsl::retired_session_tokens
+                .select(rst_dsl::session_id)
+                .filter(rst_dsl::token.eq(&old_token))
+                .first::<Uuid>(conn)
+                .await
+                .optional()
+                .map_err(|e| AuthError::QueryFailed(e.to_string()))?;
+            if let Some(session_id) = retired {
+                self.end_session(conn.deref_mut(), session_id).await?;
+                return Err(AuthError::RefreshTokenReused.into());
+            }
+
+            return Err(AuthError::BadSessionToken.into());
+        };
+
+        let now = OffsetDateTime::now_utc();
+        if expires_at < now {
+            delete(s_dsl::sessions)
+                .filter(s_dsl::id.eq(id))
+                .execute(conn)
+                .await
+                .map_err(|e| AuthError::QueryFailed(e.to_string()))?;
+
+            return Err(AuthError::SessionIntentTimeout.into());
+        }
+        self.check_not_blocked(conn.deref_mut(), account_id).await?;
+
+        // The refresh token is rotated, so a leaked one stops working once it has been used
+        let token: String = RNG.with(|rng| {
+            let mut rng = rng.borrow_mut();
+            (0..Self::SESSION_TOKEN_LEN)
+                .map(|_| rng.sample(Alphanumeric) as char)
+                .collect()
+        });
+
+        let new_token = token.clone();
+        let rotated = conn
+            .transaction::<_, AuthError, _>(move |conn| {
+                async move {
+                    // Only one of several refreshes with the same token can swap it
+                    let updated = update(s_dsl::sessions)
+                        .filter(s_dsl::id.eq(id))
+                        .filter(s_dsl::token.eq(&old_token))
+                        .set((
+                            s_dsl::token.eq(&new_token),
+                            s_dsl::last_used_at.eq(now),
+                            s_dsl::expires_at.eq(now.saturating_add(Self::SESSION_DURATION)),
+                        ))
+                        .execute(conn)
+                        .await?;
+                    if updated != 1 {
+                        return Ok(false);
+                    }
+
+                    use schema::retired_session_tokens::dsl as rst_dsl;
+                    insert_into(rst_dsl::retired_session_tokens)
+                        .values((rst_dsl::token.eq(&old_token), rst_dsl::session_id.eq(id)))
+                        .execute(conn)
+                        .await?;
+
+                    Ok(true)
+                }
+                .scope_boxed()
+            })
+            .await?;
+
+        // Losing the race means the same refresh token was used twice
+        if !rotated {
+            self.end_session(conn.deref_mut(), id).await?;
+            return Err(AuthError::RefreshTokenReused.into());
+        }
+
+        let access_token = self
+            .access_token(conn.deref_mut(), account_id, id, password_login, two_factor)
+            .await?;
+
+        Ok(tonic::Response::new(SessionTokens {
+            access_token,
+            refresh_token: token,
+        }))
+    }
+
+    async fn logout(
+        &self,
+        request: tonic::Request<SessionToken>,
+    ) -> Result<tonic::Response<()>, tonic::Status> {
+        let conn = &mut self
+            .pool
+            .get()
+            .await
+            .map_err(|_| AuthError::PoolConnectionFailed)?;
+
+        use schema::sessions::dsl as s_dsl;
+
+        let session_ids = delete(s_dsl::sessions)
+            .filter(s_dsl::token.eq(&request.get_ref().token))
+            .returning(s_dsl::id)
+            .get_results::<Uuid>(conn)
+            .await
+            .map_err(|e| AuthError::QueryFailed(e.to_string()))?;
+        self.revoke_sessions(conn.deref_mut(), &session_ids).await?;
+
+        Ok(tonic::Response::new(()))
+    }
+
+    async fn list_sessions(
+        &self,
+        request: tonic::Request<AccountId>,
+    ) -> Result<tonic::Response<Sessions>, tonic::Status> {
+        let account_id =
+            Uuid::from_str(&request.get_ref().id).map_err(|_| AuthError::MalformedAccountId)?;
+        let conn = &mut self
+            .pool
+            .get()
+            .await
+            .map_err(|_| AuthError::PoolConnectionFailed)?;
+
+        use schema::sessions::dsl as s_dsl;
+
+        let sessions = s_dsl::sessions
+            .select((
+                s_dsl::id,
+                s_dsl::created_at,
+                s_dsl::last_used_at,
+                s_dsl::expires_at,
+                s_dsl::user_agent,
+                s_dsl::ip,
+                s_dsl::password_login,
+                s_dsl::two_factor,
+            ))
+            .filter(s_dsl::account_id.eq(account_id))
+            .filter(s_dsl::expires_at.gt(OffsetDateTime::now_utc()))
+            .order(s_dsl::last_used_at.desc())
+            .load::<(
+                Uuid,
+                OffsetDateTime,
+                OffsetDateTime,
+                OffsetDateTime,
+                Option<String>,
+                Option<String>,
+                bool,
+                bool,
+            )>(conn)
+            .await
+            .map_err(|e| AuthError::QueryFailed(e.to_string()))?
+            .into_iter()
+            .map(
+                |(
+                    id,
+                    created_at,
+                    last_used_at,
+                    expires_at,
+                    user_agent,
+                    ip,
+                    password_login,
+                    two_factor,
+                )| SessionDesc {
+                    id: id.to_string(),
+                    created_at: created_at.to_string(),
+                    last_used_at: last_used_at.to_string(),
+                    expires_at: expires_at.to_string(),
+                    user_agent,
+                    ip,
+                    password_login,
+                    two_factor,
+                },
+            )
+            .collect();
+
+        Ok(tonic::Response::new(Sessions { sessions }))
+    }
+
+    async fn revoke_session(
+        &self,
+        request: tonic::Request<SessionId>,
+    ) -> Result<tonic::Response<()>, tonic::Status> {
+        let SessionId { account_id, id } = request.into_inner();
+        let account_id = Uuid::from_str(&account_id).map_err(|_| AuthError::MalformedAccountId)?;
+        let id = Uuid::from_str(&id).map_err(|_| AuthError::MalformedSessionId)?;
+        let conn = &mut self
+            .pool
+            .get()
+            .await
+            .map_err(|_| AuthError::PoolConnectionFailed)?;
+
+        use schema::sessions::dsl as s_dsl;
+
+        let deleted = delete(s_dsl::sessions)
+            .filter(s_dsl::id.eq(id))
+            .filter(s_dsl::account_id.eq(account_id))
+            .execute(conn)
+            .await
+            .map_err(|e| AuthError::QueryFailed(e.to_string()))?;
+
+        if deleted == 0 {
+            return Err(AuthError::SessionNotFound.into());
+        }
+        self.revoke_sessions(conn.deref_mut(), &[id]).await?;
+
+        Ok(tonic::Response::new(()))
+    }
+
+    async fn revoke_all_sessions(
+        &self,
+        request: tonic::Request<AccountId>,
+    ) -> Result<tonic::Response<()>, tonic::Status> {
+        let account_id =
+            Uuid::from_str(&request.get_ref().id).map_err(|_| AuthError::MalformedAccountId)?;
+        let conn = &mut self
+            .pool
+            .get()
+            .await
+            .map_err(|_| AuthError::PoolConnectionFailed)?;
+
+        use schema::sessions::dsl as s_dsl;
+
+        let session_ids = delete(s_dsl::sessions)
+            .filter(s_dsl::account_id.eq(account_id))
+            .returning(s_dsl::id)
+            .get_results::<Uuid>(conn)
+            .await
+            .map_err(|e| AuthError::QueryFailed(e.to_string()))?;
+        self.revoke_sessions(conn.deref_mut(), &session_ids).await?;
+
+        Ok(tonic::Response::new(()))
+    }
+
+    async fn list_revocations(
+        &self,
+        _request: tonic::Request<()>,
+    ) -> Result<tonic::Response<Revocations>, tonic::Status> {
+        let conn = &mut self
+            .pool
+            .get()
+            .await
+            .map_err(|_| AuthError::PoolConnectionFailed)?;
+        // Older revocations only concern access tokens that have expired anyway
+        let since = OffsetDateTime::now_utc() - Self::ACCESS_TOKEN_DURATION;
+
+        use schema::revoked_sessions::dsl as rs_dsl;
+
+        let session_ids = rs_dsl::revoked_sessions
+            .select(rs_dsl::session_id)
+            .filter(rs_dsl::revoked_at.gt(since))
+            .load::<Uuid>(conn)
+            .await
+            .map_err(|e| AuthError::QueryFailed(e.to_string()))?
+            .into_iter()
+            .map(|id| id.to_string())
+            .collect();
+
+        use schema::accounts::dsl as a_dsl;
+
+        let accounts = a_dsl::accounts
+            .select((a_dsl::id, a_dsl::token_generation))
+            .filter(a_dsl::tokens_revoked_at.gt(since))
+            .load::<(Uuid, i32)>(conn)
+            .await
+            .map_err(|e| AuthError::QueryFailed(e.to_string()))?
+            .into_iter()
+            .map(|(id, token_generation)| RevokedAccount {
+                account_id: id.to_string(),
+                token_generation,
+            })
+            .collect();
+
+        Ok(tonic::Response::new(Revocations {
+            session_ids,
+            accounts,
+        }))
+    }
+
+    async fn grant_scope(
+        &self,
+        request: tonic::Request<ScopeGrant>,
+    ) -> Result<tonic::Response<()>, tonic::Status> {
+        let ScopeGrant { account_id, scope } = request.into_inner();
+        let account_id = Uuid::from_str(&account_id).map_err(|_| AuthError::MalformedAccountId)?;
+        let scope = Scope::from_i32(scope)
+            .map(models::ScopeKind::from)
+            .ok_or(AuthError::UnknownScope(scope))?;
+        let conn = &mut self
+            .pool
+            .get()
+            .await
+            .map_err(|_| AuthError::PoolConnectionFailed)?;
+
+        self.check_not_blocked(conn.deref_mut(), account_id).await?;
+
+        use schema::scopes::dsl as sc_dsl;
+
+        insert_into(sc_dsl::scopes)
+            .values(models::Scope { account_id, scope })
+            .on_conflict_do_nothing()
+            .execute(conn)
+            .await
+            .map_err(|e| AuthError::QueryFailed(e.to_string()))?;
+
+        Ok(tonic::Response::new(()))
+    }
+
+    async fn revoke_scope(
+        &self,
+        request: tonic::Request<ScopeGrant>,
+    ) -> Result<tonic::Response<()>, tonic::Status> {
+        let ScopeGrant { account_id, scope } = request.into_inner();
+        let account_id = Uuid::from_str(&account_id).map_err(|_| AuthError::MalformedAccountId)?;
+        let scope = Scope::from_i32(scope)
+            .map(models::ScopeKind::from)
+            .ok_or(AuthError::UnknownScope(scope))?;
+        let conn = &mut self
+            .pool
+            .get()
+            .await
+            .map_err(|_| AuthError::PoolConnectionFailed)?;
+
+        use schema::scopes::dsl as sc_dsl;
+
+        let deleted = delete(sc_dsl::scopes)
+            .filter(sc_dsl::account_id.eq(account_id))
+            .filter(sc_dsl::scope.eq(scope))
+            .execute(conn)
+            .await
+            .map_err(|e| AuthError::QueryFailed(e.to_string()))?;
+
+        if deleted == 0 {
+            return Err(AuthError::ScopeNotGranted(scope).into());
+        }
+        // The access tokens still carry the scope until they are refreshed
+        self.revoke_access_tokens(conn.deref_mut(), account_id)
+            .await?;
+
+        Ok(tonic::Response::new(()))
+    }
+
+    async fn list_scopes(
+        &self,
+        request: tonic::Request<AccountId>,
+    ) -> Result<tonic::Response<AccountScopes>, tonic::Status> {
+        let account_id =
+            Uuid::from_str(&request.get_ref().id).map_err(|_| AuthError::MalformedAccountId)?;
+        let conn = &mut self
+            .pool
+            .get()
+            .await
+            .map_err(|_| AuthError::PoolConnectionFailed)?;
+
+        use schema::scopes::dsl as sc_dsl;
+
+        let scopes = sc_dsl::scopes
+            .select(sc_dsl::scope)
+            .filter(sc_dsl::account_id.eq(account_id))
+            .load::<models::ScopeKind>(conn)
+            .await
+            .map_err(|e| AuthError::QueryFailed(e.to_string()))?
+            .into_iter()
+            .map(|kind| kind as i32)
+            .collect();
+
+        Ok(tonic::Response::new(AccountScopes { scopes }))
+    }
+
+    async fn request_email_change(
+        &self,
+        request: tonic::Request<EmailChange>,
+    ) -> Result<tonic::Response<()>, tonic::Status> {
+        let EmailChange {
+            account_id,
+            new_email,
+        } = request.into_inner();
+        let account_id = Uuid::from_str(&account_id).map_err(|_| AuthError::MalformedAccountId)?;
+        let conn = &mut self
+            .pool
+            .get()
+            .await
+            .map_err(|_| AuthError::PoolConnectionFailed)?;
+
+        self.check_not_blocked(conn.deref_mut(), account_id).await?;
+
+        // The address is only checked to fail early, the confirmation checks it again
+        if self
+            .get_account(conn.deref_mut(), &new_email)
+            .await?
+            .is_some()
+        {
+            return Err(AuthError::AccountForEmailAreadyExists.into());
+        }
+
+        use schema::accounts::dsl as a_dsl;
+
+        let old_email = a_dsl::accounts
+            .select(a_dsl::email)
+            .filter(a_dsl::id.eq(account_id))
+            .first::<String>(conn)
+            .await
+            .map_err(|e| AuthError::QueryFailed(e.to_string()))?;
+
+        let code: String = RNG.with(|rng| {
+            let mut rng = rng.borrow_mut();
+            (0..Self::SESSION_INTENT_CODE_LEN)
+                .map(|_| rng.sample(Alphanumeric) as char)
+                .collect()
+        });
+        let expires_at = OffsetDateTime::now_utc().saturating_add(Self::EMAIL_CHANGE_DURATION);
+
+        use schema::email_change_intents::dsl as eci_dsl;
+
+        // A new request replaces the pending one of the account
+        insert_into(eci_dsl::email_change_intents)
+            .values(models::EmailChangeIntent {
+                account_id,
+                new_email: &new_email,
+                code: &code,
+                expires_at,
+                attempts: 0,
+            })
+            .on_conflict(eci_dsl::account_id)
+            .do_update()
+            .set((
+                eci_dsl::new_email.eq(&new_email),
+                eci_dsl::code.eq(&code),
+                eci_dsl::expires_at.eq(expires_at),
+                eci_dsl::attempts.eq(0),
+            ))
+            .execute(conn)
+            .await
+            .map_err(|e| AuthError::QueryFailed(e.to_string()))?;
+
+        send_email(Email {
+            email: new_email.clone(),
+            template: "email_change_code".into(),
+            params: HashMap::from([("code".into(), code)]),
+            locale: None,
+            ..Default::default()
+        });
+        send_email(Email {
+            email: old_email,
+            template: "email_change_notice".into(),
+            params: HashMap::from([("new_email".into(), new_email)]),
+            locale: None,
+            ..Default::default()
+        });
+
+        Ok(tonic::Response::new(()))
+    }
+
+    async fn confirm_email_change(
+        &self,
+        request: tonic::Request<EmailChangeCode>,
+    ) -> Result<tonic::Response<()>, tonic::Status> {
+        let EmailChangeCode { account_id, code } = request.into_inner();
+        let account_id = Uuid::from_str(&account_id).map_err(|_| AuthError::MalformedAccountId)?;
+        let conn = &mut self
+            .pool
+            .get()
+            .await
+            .map_err(|_| AuthError::PoolConnectionFailed)?;
+
+        use schema::email_change_intents::dsl as eci_dsl;
+
+        let Some((new_email, expected_code, expires_at)) = eci_dsl::email_change_intents
+            .select((eci_dsl::new_email, eci_dsl::code, eci_dsl::expires_at))
+            .filter(eci_dsl::account_id.eq(account_id))
+            .first::<(String, String, OffsetDateTime)>(conn)
+            .await
+            .optional()
+            .map_err(|e| AuthError::QueryFailed(e.to_string()))?
+        else {
+            return Err(AuthError::EmailChangeNotRequested.into());
+        };
+
+        if expires_at < OffsetDateTime::now_utc() {
+            delete(eci_dsl::email_change_intents)
+                .filter(eci_dsl::account_id.eq(account_id))
+                .execute(conn)
+                .await
+                .map_err(|e| AuthError::QueryFailed(e.to_string()))?;
+
+            return Err(AuthError::SessionIntentTimeout.into());
+        }
+        self.check_not_blocked(conn.deref_mut(), account_id).await?;
+
+        // The attempt is counted before the code is compared, so concurrent guesses can't
+        // get past `MAX_CODE_ATTEMPTS`
+        let Some(attempts) = update(eci_dsl::email_change_intents)
+            .filter(eci_dsl::account_id.eq(account_id))
+            .filter(eci_dsl::attempts.lt(Self::MAX_CODE_ATTEMPTS))
+            .set(eci_dsl::attempts.eq(eci_dsl::attempts + 1))
+            .returning(eci_dsl::attempts)
+            .get_result::<i32>(conn)
+            .await
+            .optional()
+            .map_err(|e| AuthError::QueryFailed(e.to_string()))?
+        else {
+            return Err(AuthError::TooManyAttempts(None).into());
+        };
+
+        if code != expected_code {
+            if attempts >= Self::MAX_CODE_ATTEMPTS {
+                delete(eci_dsl::email_change_intents)
+                    .filter(eci_dsl::account_id.eq(account_id))
+                    .execute(conn)
+                    .await
+                    .map_err(|e| AuthError::QueryFailed(e.to_string()))?;
+
+                return Err(AuthError::TooManyAttempts(None).into());
+            }
+
+            return Err(AuthError::PasscodeDoesNotMatch.into());
+        }
+
+        conn.transaction::<_, AuthError, _>(|conn| {
+            async move {
+                // Deleting the intent first makes sure it is only used once, even when it is
+                // confirmed twice at the same time or replaced by a new request in between
+                let deleted = delete(eci_dsl::email_change_intents)
+                    .filter(eci_dsl::account_id.eq(account_id))
+                    .filter(eci_dsl::new_email.eq(&new_email))
+                    .filter(eci_dsl::code.eq(&code))
+                    .execute(conn)
+                    .await?;
+
+                if deleted == 0 {
+                    return Err(AuthError::EmailChangeNotRequested);
+                }
+
+                use schema::accounts::dsl as a_dsl;
+
+                // The unique constraint on the email decides between accounts changing to the
+                // same address at the same time
+                update(a_dsl::accounts)
+                    .filter(a_dsl::id.eq(account_id))
+                    .set(a_dsl::email.eq(&new_email))
+                    .execute(conn)
+                    .await
+                    .map_err(|e| match e {
+                        diesel::result::Error::DatabaseError(
+                            diesel::result::DatabaseErrorKind::UniqueViolation,
+                            _,
+                        ) => AuthError::AccountForEmailAreadyExists,
+                        e => e.into(),
+                    })?;
+
+                // Pending logins were sent to the old address
+                use schema::email_login_intents::dsl as eli_dsl;
+                delete(eli_dsl::email_login_intents)
+                    .filter(eli_dsl::account_id.eq(account_id))
+                    .execute(conn)
+                    .await?;
+
+                use schema::new_pass_login_intents::dsl as fpli_dsl;
+                delete(fpli_dsl::new_pass_login_intents)
+                    .filter(fpli_dsl::account_id.eq(account_id))
+                    .execute(conn)
+                    .await?;
+
+                Ok(())
+            }
+            .scope_boxed()
+        })
+        .await?;
+
+        Ok(tonic::Response::new(()))
+    }
+
+    async fn cleanup_db(
+        &self,
+        _request: tonic::Request<()>,
+    ) -> Result<tonic::Response<()>, tonic::Status> {
+        let conn = &mut self
+            .pool
+            .get()
+            .await
+            .map_err(|_| AuthError::PoolConnectionFailed)?;
+        let now = OffsetDateTime::now_utc();
+
+        use schema::new_pass_login_intents::dsl as fpli_dsl;
+        delete(fpli_dsl::new_pass_login_intents)
+            .filter(fpli_dsl::expires_at.lt(now))
+            .execute(conn)
+            .await
+            .map_err(|e| AuthError::QueryFailed(e.to_string()))?;
+
+        use schema::email_login_intents::dsl as eli_dsl;
+        delete(eli_dsl::email_login_intents)
+            .filter(eli_dsl::expires_at.lt(now))
+            .execute(conn)
+            .await
+            .map_err(|e| AuthError::QueryFailed(e.to_string()))?;
+
+        use schema::email_change_intents::dsl as eci_dsl;
+        delete(eci_dsl::email_change_intents)
+            .filter(eci_dsl::expires_at.lt(now))
+            .execute(conn)
+            .await
+            .map_err(|e| AuthError::QueryFailed(e.to_string()))?;
+
+        use schema::totp_challenges::dsl as tc_dsl;
+        delete(tc_dsl::totp_challenges)
+            .filter(tc_dsl::expires_at.lt(now))
+            .execute(conn)
+            .await
+            .map_err(|e| AuthError::QueryFailed(e.to_string()))?;
+
+        use schema::sessions::dsl as s_dsl;
+        delete(s_dsl::sessions)
+            .filter(s_dsl::expires_at.lt(now))
+            .execute(conn)
+            .await
+            .map_err(|e| AuthError::QueryFailed(e.to_string()))?;
+
+        use schema::revoked_sessions::dsl as rs_dsl;
+        delete(rs_dsl::revoked_sessions)
+            .filter(rs_dsl::revoked_at.lt(now - Self::ACCESS_TOKEN_DURATION))
+            .execute(conn)
+            .await
+            .map_err(|e| AuthError::QueryFailed(e.to_string()))?;
+
+        // Accounts that were signed up for but never verified don't keep their email taken
+        use schema::accounts::dsl as a_dsl;
+        delete(a_dsl::accounts)
+            .filter(a_dsl::verified.eq(false))
+            .filter(a_dsl::created_at.lt(now - Self::UNVERIFIED_ACCOUNT_DURATION))
+            .execute(conn)
+            .await
+            .map_err(|e| AuthError::QueryFailed(e.to_string()))?;
+
+        Ok(tonic::Response::new(()))
+    }
+}
+
+enum AuthError {
+    BadSessionToken,
+    MalformedSessionToken,
+    SessionIntentTimeout,
+    PasscodeDoesNotMatch,
+    QueryFailed(String),
+    PasswordHashingError(argon2::password_hash::Error),
+    PoolConnectionFailed,
+    AccountForEmailAreadyExists,
+    WrongPassword,
+    MalformedAccountId,
+    MalformedApiKeyId,
+    UnknownScope(i32),
+    ScopeNotGranted(models::ScopeKind),
+    ApiKeyNotFound,
+    AccountNotFound,
+    TotpError(String),
+    TotpAlreadyEnabled,
+    TotpNotEnrolled,
+    TotpNotEnabled,
+    WrongTotpCode,
+    TooManyAttempts(Option<Duration>),
+    AccountBlocked,
+    MalformedSessionId,
+    SessionNotFound,
+    WeakPassword(Vec<lunu::auth::PasswordViolation>),
+    TokenSigningFailed(String),
+    EmailChangeNotRequested,
+    RefreshTokenReused,
+    AccountNotVerified,
+}
+
+impl From<diesel::result::Error> for AuthError {
+    fn from(value: diesel::result::Error) -> Self {
+        AuthError::QueryFailed(value.to_string())
+    }
+}
+
+impl From<AuthError> for tonic::Status {
+    fn from(value: AuthError) -> Self {
+        match value {
+            AuthError::BadSessionToken => tonic::Status::invalid_argument(
+                "Session token does not correspond to a session intent",
+            ),
+            AuthError::MalformedSessionToken => {
+                tonic::Status::invalid_argument("Malformed session token")
+            }
+            AuthError::SessionIntentTimeout => {
+                tonic::Status::resource_exhausted("This session token has already timed out")
+            }
+            AuthError::PasscodeDoesNotMatch => {
                 tonic::Status::invalid_argument("Passcode does not match")
             }
             AuthError::QueryFailed(s) => tonic::Status::internal(format!("Query Failed: {s}")),
@@ -622,14 +2711,67 @@ impl From<AuthError> for tonic::Status {
             AuthError::AccountForEmailAreadyExists => tonic::Status::invalid_argument(
                 "Failed to create account for this email as one already exists",
             ),
-            AuthError::NoAccountForEmail(email) => {
-                tonic::Status::invalid_argument(format!("No account found for email: {email}"))
+            AuthError::WrongPassword => {
+                tonic::Status::invalid_argument("The email or the password is wrong")
+            }
+            AuthError::MalformedAccountId => {
+                tonic::Status::invalid_argument("Malformed account id")
+            }
+            AuthError::MalformedApiKeyId => tonic::Status::invalid_argument("Malformed api key id"),
+            AuthError::UnknownScope(scope) => {
+                tonic::Status::invalid_argument(format!("Unknown scope: {scope}"))
             }
-            AuthError::AccountHasNoPasswordLogin => tonic::Status::invalid_argument(
-                "Account has no password login, it only supports email login",
+            AuthError::ScopeNotGranted(scope) => tonic::Status::permission_denied(format!(
+                "The account has not been granted the {scope:?} scope"
+            )),
+            AuthError::ApiKeyNotFound => tonic::Status::not_found("Api key not found"),
+            AuthError::AccountNotFound => tonic::Status::not_found("Account not found"),
+            AuthError::TotpError(err) => tonic::Status::internal(format!("Totp failed: {err}")),
+            AuthError::TotpAlreadyEnabled => tonic::Status::failed_precondition(
+                "Two factor authentication is already enabled for this account",
             ),
-            AuthError::WrongPassword => {
-                tonic::Status::invalid_argument("The password does not match the one on file")
+            AuthError::TotpNotEnrolled => tonic::Status::failed_precondition(
+                "The account has not started enrolling in two factor authentication",
+            ),
+            AuthError::TotpNotEnabled => tonic::Status::failed_precondition(
+                "Two factor authentication is not enabled for this account",
+            ),
+            AuthError::WrongTotpCode => {
+                tonic::Status::invalid_argument("The two factor authentication code is wrong")
+            }
+            AuthError::TooManyAttempts(Some(retry_after)) => {
+                tonic::Status::resource_exhausted(format!(
+                    "Too many failed login attempts, try again in {} seconds",
+                    retry_after.whole_seconds() + 1
+                ))
+            }
+            AuthError::MalformedSessionId => {
+                tonic::Status::invalid_argument("Malformed session id")
+            }
+            AuthError::SessionNotFound => tonic::Status::not_found("Session not found"),
+            AuthError::RefreshTokenReused => tonic::Status::unauthenticated(
+                "This refresh token was already used, the session was ended",
+            ),
+            AuthError::AccountNotVerified => tonic::Status::failed_precondition(
+                "The email of this account is not verified yet, use the code that was sent to it",
+            ),
+            AuthError::AccountBlocked => {
+                tonic::Status::permission_denied("This account is blocked")
+            }
+            AuthError::TooManyAttempts(None) => tonic::Status::resource_exhausted(
+                "Too many wrong codes, this login has been invalidated",
+            ),
+            // The violated rules are sent along so they can be shown one by one
+            AuthError::WeakPassword(violations) => tonic::Status::with_details(
+                tonic::Code::InvalidArgument,
+                "The password does not satisfy the password policy",
+                PasswordViolations { violations }.encode_to_vec().into(),
+            ),
+            AuthError::TokenSigningFailed(s) => {
+                tonic::Status::internal(format!("Failed to sign the access token: {s}"))
+            }
+            AuthError::EmailChangeNotRequested => {
+                tonic::Status::failed_precondition("No email change was requested")
             }
         }
     }
@@ -642,10 +2784,41 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     init_clients().await;
 
     let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    let jwt_secret = env::var("JWT_SECRET").expect("JWT_SECRET must be set");
+    let frontend_url = env::var("FRONTEND_URL").expect("FRONTEND_URL must be set");
+
+    // Raising these upgrades the stored password hashes on the next login
+    let argon_params = Params::new(
+        env::var("ARGON2_M_COST")
+            .map(|v| v.parse().expect("ARGON2_M_COST must be a number"))
+            .unwrap_or(Params::DEFAULT_M_COST),
+        env::var("ARGON2_T_COST")
+            .map(|v| v.parse().expect("ARGON2_T_COST must be a number"))
+            .unwrap_or(Params::DEFAULT_T_COST),
+        env::var("ARGON2_P_COST")
+            .map(|v| v.parse().expect("ARGON2_P_COST must be a number"))
+            .unwrap_or(Params::DEFAULT_P_COST),
+        None,
+    )
+    .expect("Invalid argon2 parameters");
+
+    let argon = Argon2::new(argon2::Algorithm::Argon2id, Version::V0x13, argon_params);
+    let dummy_hash = argon
+        .hash_password(
+            Uuid::new_v4().to_string().as_bytes(),
+            &SaltString::generate(&mut OsRng),
+        )
+        .expect("Failed to hash the dummy password")
+        .to_string();
 
     let config = AsyncDieselConnectionManager::<AsyncPgConnection>::new(&database_url);
     let auth = Auth {
         pool: Pool::builder().build(config).await?,
+        argon,
+        password_policy: PasswordPolicy::from_env(),
+        jwt_key: EncodingKey::from_secret(jwt_secret.as_bytes()),
+        frontend_url: frontend_url.trim_end_matches('/').to_string(),
+        dummy_hash,
     };
 
     let addr = MICROSERVICE_ADDRS[&Microservice::Auth].parse()?;
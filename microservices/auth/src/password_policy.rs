@@ -0,0 +1,106 @@
+use std::{collections::HashSet, env, fs};
+
+use lunu::auth::{PasswordRule, PasswordViolation};
+
+use crate::AuthError;
+
+pub struct PasswordPolicy {
+    min_len: usize,
+    max_len: usize,
+    min_classes: usize,
+    breached: HashSet<String>,
+}
+
+impl PasswordPolicy {
+    // Setting the defaults to the NIST recommended minimum and a maximum that keeps hashing cheap
+    const DEFAULT_MIN_LEN: usize = 8;
+    const DEFAULT_MAX_LEN: usize = 128;
+    // Setting the number of character classes (lowercase, uppercase, digits, symbols) a password needs
+    const DEFAULT_MIN_CLASSES: usize = 3;
+
+    /// Reads the policy from the env.
+    ///
+    /// `PASSWORD_MIN_LEN`, `PASSWORD_MAX_LEN` and `PASSWORD_MIN_CLASSES` override the defaults.
+    /// `BREACHED_PASSWORDS_PATH` points to a file with one known-breached password per line.
+    pub fn from_env() -> Self {
+        let breached = match env::var("BREACHED_PASSWORDS_PATH") {
+            Ok(path) => fs::read_to_string(&path)
+                .unwrap_or_else(|e| panic!("Failed to read breached passwords from {path}: {e}"))
+                .lines()
+                .filter(|line| !line.is_empty())
+                .map(str::to_owned)
+                .collect(),
+            Err(_) => HashSet::new(),
+        };
+
+        PasswordPolicy {
+            min_len: env::var("PASSWORD_MIN_LEN")
+                .map(|v| v.parse().expect("PASSWORD_MIN_LEN must be a number"))
+                .unwrap_or(Self::DEFAULT_MIN_LEN),
+            max_len: env::var("PASSWORD_MAX_LEN")
+                .map(|v| v.parse().expect("PASSWORD_MAX_LEN must be a number"))
+                .unwrap_or(Self::DEFAULT_MAX_LEN),
+            min_classes: env::var("PASSWORD_MIN_CLASSES")
+                .map(|v| v.parse().expect("PASSWORD_MIN_CLASSES must be a number"))
+                .unwrap_or(Self::DEFAULT_MIN_CLASSES),
+            breached,
+        }
+    }
+
+    /// Checks `password` against every rule and returns all the rules it violates at once.
+    pub fn check(&self, password: &str) -> Result<(), AuthError> {
+        let mut violations = Vec::new();
+        let len = password.chars().count();
+
+        if len < self.min_len {
+            violations.push(PasswordViolation {
+                rule: PasswordRule::TooShort as i32,
+                message: format!(
+                    "The password must be at least {} characters long",
+                    self.min_len
+                ),
+            });
+        }
+        if len > self.max_len {
+            violations.push(PasswordViolation {
+                rule: PasswordRule::TooLong as i32,
+                message: format!(
+                    "The password must be at most {} characters long",
+                    self.max_len
+                ),
+            });
+        }
+
+        let classes = [
+            password.chars().any(char::is_lowercase),
+            password.chars().any(char::is_uppercase),
+            password.chars().any(|c| c.is_ascii_digit()),
+            password.chars().any(|c| !c.is_alphanumeric()),
+        ]
+        .into_iter()
+        .filter(|has| *has)
+        .count();
+        if classes < self.min_classes {
+            violations.push(PasswordViolation {
+                rule: PasswordRule::TooFewCharacterClasses as i32,
+                message: format!(
+                    "The password must contain at least {} of: lowercase letters, uppercase letters, digits and symbols",
+                    self.min_classes
+                ),
+            });
+        }
+
+        if self.breached.contains(password) {
+            violations.push(PasswordViolation {
+                rule: PasswordRule::Breached as i32,
+                message: "The password appears in a list of breached passwords".to_string(),
+            });
+        }
+
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(AuthError::WeakPassword(violations))
+        }
+    }
+}
@@ -1,27 +1,338 @@
+mod outbox;
+mod templates;
+mod transport;
+
+use std::{env, path::PathBuf, str::FromStr, sync::Arc};
+
+use lettre::{
+    message::{header::ContentType, Mailbox, MultiPart, SinglePart},
+    Message,
+};
 use lunu::{
+    diesel::{self, ExpressionMethods, QueryDsl},
+    diesel_async::{
+        pooled_connection::{bb8::Pool, AsyncDieselConnectionManager},
+        AsyncPgConnection, RunQueryDsl,
+    },
     dotenvy::dotenv,
-    email::{mail_server::MailServer, Email},
-    Microservice, MICROSERVICE_ADDRS,
+    email::{
+        attachment, mail_server::MailServer, Attachment, DeliveryState, DeliveryStatus, Email,
+        FailedMessages, MessageId,
+    },
+    models, register_tonic_clients, schema, Microservice, MICROSERVICE_ADDRS,
 };
-use tonic::transport::Server;
+use outbox::Outbox;
+use templates::Templates;
+use time::OffsetDateTime;
+use tokio::sync::Notify;
+use tonic::transport::{Channel, Server};
+use uuid::Uuid;
+
+register_tonic_clients! {
+    (STORAGE_CLIENT, lunu::storage::storage_client::StorageClient<Channel>, lunu::Microservice::Storage, "storage"),
+}
+
+struct Mail {
+    pool: Pool<AsyncPgConnection>,
+    templates: Templates,
+    from: Mailbox,
+    notify: Arc<Notify>,
+}
+
+type OutboxRow = (
+    Uuid,
+    String,
+    String,
+    models::EmailStatus,
+    i32,
+    Option<String>,
+    OffsetDateTime,
+    Option<OffsetDateTime>,
+);
+
+impl Mail {
+    async fn attachment(&self, attachment: Attachment) -> Result<SinglePart, MailError> {
+        let Attachment {
+            filename,
+            content_type,
+            content,
+        } = attachment;
+
+        let content_type = if content_type.is_empty() {
+            ContentType::parse("application/octet-stream")
+        } else {
+            ContentType::parse(&content_type)
+        }
+        .map_err(|_| MailError::InvalidAttachment(filename.clone()))?;
+
+        let data = match content {
+            Some(attachment::Content::Data(data)) => data,
+            Some(attachment::Content::File(file)) => {
+                let mut client = STORAGE_CLIENT
+                    .get()
+                    .expect("STORAGE_CLIENT used before it was initalized")
+                    .clone();
+
+                client
+                    .get(file)
+                    .await
+                    .map_err(|status| MailError::StorageFailed(status.message().to_string()))?
+                    .into_inner()
+                    .data
+                    .ok_or(MailError::AttachmentNotFound(filename.clone()))?
+            }
+            None => return Err(MailError::InvalidAttachment(filename)),
+        };
+
+        Ok(lettre::message::Attachment::new(filename).body(data, content_type))
+    }
+}
+
+fn mailbox(address: String) -> Result<Mailbox, MailError> {
+    address
+        .parse::<Mailbox>()
+        .map_err(|_| MailError::InvalidAddress(address))
+}
 
-struct Mail {}
+fn delivery_status(row: OutboxRow) -> DeliveryStatus {
+    let (id, email, template, status, attempts, last_error, created_at, sent_at) = row;
+
+    DeliveryStatus {
+        id: id.to_string(),
+        email,
+        template,
+        state: DeliveryState::from(status) as i32,
+        attempts: attempts as u32,
+        last_error,
+        created_at: created_at.to_string(),
+        sent_at: sent_at.map(|time| time.to_string()),
+    }
+}
 
 #[tonic::async_trait]
 impl lunu::email::mail_server::Mail for Mail {
     async fn send(
         &self,
         request: tonic::Request<Email>,
-    ) -> Result<tonic::Response<()>, tonic::Status> {
+    ) -> Result<tonic::Response<MessageId>, tonic::Status> {
         let Email {
             email,
-            subject,
-            body_html,
+            template,
+            params,
+            locale,
+            cc,
+            bcc,
+            reply_to,
+            attachments,
         } = request.into_inner();
 
-        println!("Dummy sent email to {email}.\nSubject: {subject}\n{body_html}");
+        let rendered = self
+            .templates
+            .render(&template, locale.as_deref(), &params)?;
+
+        let mut builder = Message::builder()
+            .from(self.from.clone())
+            .to(mailbox(email.clone())?)
+            .subject(rendered.subject);
+        for address in cc {
+            builder = builder.cc(mailbox(address)?);
+        }
+        // The bcc recipients only end up in the envelope, not in the headers
+        for address in bcc {
+            builder = builder.bcc(mailbox(address)?);
+        }
+        if let Some(address) = reply_to {
+            builder = builder.reply_to(mailbox(address)?);
+        }
+
+        let message = if attachments.is_empty() {
+            match rendered.text {
+                Some(text) => {
+                    builder.multipart(MultiPart::alternative_plain_html(text, rendered.html))
+                }
+                None => builder.singlepart(SinglePart::html(rendered.html)),
+            }
+        } else {
+            let mut mixed = match rendered.text {
+                Some(text) => MultiPart::mixed()
+                    .multipart(MultiPart::alternative_plain_html(text, rendered.html)),
+                None => MultiPart::mixed().singlepart(SinglePart::html(rendered.html)),
+            };
+            // The files are fetched now, so the outbox holds the complete message
+            for attachment in attachments {
+                mixed = mixed.singlepart(self.attachment(attachment).await?);
+            }
+            builder.multipart(mixed)
+        }
+        .map_err(|e| MailError::BuildFailed(e.to_string()))?;
+
+        let envelope = message.envelope();
+        let sender = envelope
+            .from()
+            .map(|address| address.to_string())
+            .unwrap_or_default();
+        let recipients = envelope
+            .to()
+            .iter()
+            .map(|address| address.to_string())
+            .collect::<Vec<_>>();
+        let formatted = message.formatted();
+
+        let conn = &mut self
+            .pool
+            .get()
+            .await
+            .map_err(|_| MailError::PoolConnectionFailed)?;
+
+        let id = Uuid::new_v4();
+        diesel::insert_into(schema::email_outbox::table)
+            .values(models::NewOutboxEmail {
+                id,
+                email: &email,
+                template: &template,
+                sender: &sender,
+                recipients: recipients.iter().map(String::as_str).collect(),
+                message: &formatted,
+            })
+            .execute(conn)
+            .await
+            .map_err(MailError::from)?;
+
+        self.notify.notify_one();
 
-        Ok(tonic::Response::new(()))
+        Ok(tonic::Response::new(MessageId { id: id.to_string() }))
+    }
+
+    async fn get_delivery_status(
+        &self,
+        request: tonic::Request<MessageId>,
+    ) -> Result<tonic::Response<DeliveryStatus>, tonic::Status> {
+        let id =
+            Uuid::from_str(&request.get_ref().id).map_err(|_| MailError::MalformedMessageId)?;
+
+        let conn = &mut self
+            .pool
+            .get()
+            .await
+            .map_err(|_| MailError::PoolConnectionFailed)?;
+
+        use schema::email_outbox::dsl as eo_dsl;
+
+        let row = eo_dsl::email_outbox
+            .select((
+                eo_dsl::id,
+                eo_dsl::email,
+                eo_dsl::template,
+                eo_dsl::status,
+                eo_dsl::attempts,
+                eo_dsl::last_error,
+                eo_dsl::created_at,
+                eo_dsl::sent_at,
+            ))
+            .filter(eo_dsl::id.eq(id))
+            .first::<OutboxRow>(conn)
+            .await
+            .map_err(|e| match e {
+                diesel::result::Error::NotFound => MailError::MessageNotFound,
+                e => MailError::from(e),
+            })?;
+
+        Ok(tonic::Response::new(delivery_status(row)))
+    }
+
+    async fn list_failed(
+        &self,
+        _request: tonic::Request<()>,
+    ) -> Result<tonic::Response<FailedMessages>, tonic::Status> {
+        let conn = &mut self
+            .pool
+            .get()
+            .await
+            .map_err(|_| MailError::PoolConnectionFailed)?;
+
+        use schema::email_outbox::dsl as eo_dsl;
+
+        let messages = eo_dsl::email_outbox
+            .select((
+                eo_dsl::id,
+                eo_dsl::email,
+                eo_dsl::template,
+                eo_dsl::status,
+                eo_dsl::attempts,
+                eo_dsl::last_error,
+                eo_dsl::created_at,
+                eo_dsl::sent_at,
+            ))
+            .filter(eo_dsl::status.eq(models::EmailStatus::Failed))
+            .order(eo_dsl::created_at.desc())
+            .load::<OutboxRow>(conn)
+            .await
+            .map_err(MailError::from)?
+            .into_iter()
+            .map(delivery_status)
+            .collect();
+
+        Ok(tonic::Response::new(FailedMessages { messages }))
+    }
+}
+
+pub enum MailError {
+    TemplateNotFound(String),
+    RenderFailed(String),
+    InvalidAddress(String),
+    BuildFailed(String),
+    SendFailed(String),
+    PoolConnectionFailed,
+    QueryFailed(String),
+    MalformedMessageId,
+    MessageNotFound,
+    InvalidAttachment(String),
+    AttachmentNotFound(String),
+    StorageFailed(String),
+}
+
+impl From<diesel::result::Error> for MailError {
+    fn from(value: diesel::result::Error) -> Self {
+        MailError::QueryFailed(value.to_string())
+    }
+}
+
+impl From<MailError> for tonic::Status {
+    fn from(value: MailError) -> Self {
+        match value {
+            MailError::TemplateNotFound(template) => {
+                tonic::Status::not_found(format!("No email template named {template}"))
+            }
+            MailError::RenderFailed(s) => {
+                tonic::Status::invalid_argument(format!("Failed to render the email: {s}"))
+            }
+            MailError::InvalidAddress(address) => {
+                tonic::Status::invalid_argument(format!("Invalid email address: {address}"))
+            }
+            MailError::BuildFailed(s) => {
+                tonic::Status::internal(format!("Failed to build the email: {s}"))
+            }
+            MailError::SendFailed(s) => {
+                tonic::Status::unavailable(format!("Failed to send the email: {s}"))
+            }
+            MailError::PoolConnectionFailed => {
+                tonic::Status::internal("Failed to connect to the internal pool")
+            }
+            MailError::QueryFailed(s) => tonic::Status::internal(format!("Query Failed: {s}")),
+            MailError::MalformedMessageId => {
+                tonic::Status::invalid_argument("Malformed message id")
+            }
+            MailError::MessageNotFound => tonic::Status::not_found("Message not found"),
+            MailError::InvalidAttachment(filename) => tonic::Status::invalid_argument(format!(
+                "Attachment {filename} needs a valid content type and either data or a file"
+            )),
+            MailError::AttachmentNotFound(filename) => {
+                tonic::Status::not_found(format!("No file in storage for attachment {filename}"))
+            }
+            MailError::StorageFailed(s) => tonic::Status::unavailable(format!(
+                "Failed to fetch the attachment from storage: {s}"
+            )),
+        }
     }
 }
 
@@ -29,9 +340,41 @@ impl lunu::email::mail_server::Mail for Mail {
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     dotenv().ok();
 
+    init_clients().await;
+
+    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    let from = env::var("MAIL_FROM")
+        .unwrap_or("lunu <noreply@localhost>".to_string())
+        .parse::<Mailbox>()
+        .expect("MAIL_FROM must be an email address");
+    let templates_path = env::var("MAIL_TEMPLATES_PATH")
+        .map(PathBuf::from)
+        .unwrap_or(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("templates"));
+    let default_locale = env::var("MAIL_DEFAULT_LOCALE").unwrap_or("en".to_string());
+
+    let config = AsyncDieselConnectionManager::<AsyncPgConnection>::new(&database_url);
+    let pool = Pool::builder().build(config).await?;
+    let notify = Arc::new(Notify::new());
+
+    tokio::spawn(
+        Outbox {
+            pool: pool.clone(),
+            transport: transport::from_env(),
+            notify: notify.clone(),
+        }
+        .run(),
+    );
+
+    let mail = Mail {
+        pool,
+        templates: Templates::load(&templates_path, default_locale)?,
+        from,
+        notify,
+    };
+
     let addr = MICROSERVICE_ADDRS[&Microservice::Email].parse()?;
     Server::builder()
-        .add_service(MailServer::new(Mail {}))
+        .add_service(MailServer::new(mail))
         .serve(addr)
         .await?;
 
@@ -0,0 +1,218 @@
+use std::{sync::Arc, time::Instant};
+
+use lettre::{address::Envelope, Address};
+use lunu::{
+    diesel::{self, BoolExpressionMethods, ExpressionMethods, QueryDsl},
+    diesel_async::{
+        pooled_connection::bb8::Pool, scoped_futures::ScopedFutureExt, AsyncConnection,
+        AsyncPgConnection, RunQueryDsl,
+    },
+    models, schema,
+};
+use time::{Duration, OffsetDateTime};
+use tokio::sync::Notify;
+use uuid::Uuid;
+
+use crate::{transport::MailTransport, MailError};
+
+/// Sends the emails queued in the outbox, retrying the ones that failed with an
+/// exponential backoff until `MAX_ATTEMPTS` is reached.
+pub struct Outbox {
+    pub pool: Pool<AsyncPgConnection>,
+    pub transport: Arc<dyn MailTransport>,
+    pub notify: Arc<Notify>,
+}
+
+impl Outbox {
+    // Setting how often the outbox is checked when no new email was queued
+    const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+    // Setting the number of emails sent in one go
+    const BATCH_SIZE: i64 = 20;
+    // Setting the number of attempts after which an email is marked as failed
+    const MAX_ATTEMPTS: i32 = 10;
+    // Setting the first retry to 30 seconds, it doubles with every further attempt
+    const RETRY_BASE: Duration = Duration::seconds(30);
+    // Setting the longest wait between two attempts to 6 hours
+    const RETRY_MAX: Duration = Duration::hours(6);
+    // Setting how long a claimed email is left to its worker before another one sends it
+    const LEASE: Duration = Duration::minutes(10);
+    // Setting how long sent and failed emails are kept, their messages hold login codes
+    const RETENTION: Duration = Duration::days(30);
+    // Setting how often the emails past the retention are deleted
+    const CLEANUP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
+    pub async fn run(self) {
+        let mut cleaned_at: Option<Instant> = None;
+        loop {
+            if let Err(err) = self.send_due().await {
+                eprintln!(
+                    "Failed to send from the outbox: {}",
+                    tonic::Status::from(err).message()
+                );
+            }
+
+            if cleaned_at.is_none_or(|time| time.elapsed() >= Self::CLEANUP_INTERVAL) {
+                if let Err(err) = self.cleanup().await {
+                    eprintln!(
+                        "Failed to clean up the outbox: {}",
+                        tonic::Status::from(err).message()
+                    );
+                }
+                cleaned_at = Some(Instant::now());
+            }
+
+            // Waking up right away when a new email is queued
+            let _ = tokio::time::timeout(Self::POLL_INTERVAL, self.notify.notified()).await;
+        }
+    }
+
+    async fn send_due(&self) -> Result<(), MailError> {
+        let conn = &mut self
+            .pool
+            .get()
+            .await
+            .map_err(|_| MailError::PoolConnectionFailed)?;
+
+        use schema::email_outbox::dsl as eo_dsl;
+
+        let lease_expires_at = OffsetDateTime::now_utc() + Self::LEASE;
+        // Nothing is sent while the rows are locked, the claim only lasts as long as the lease
+        let due = conn
+            .transaction::<_, MailError, _>(|conn| {
+                async move {
+                    let now = OffsetDateTime::now_utc();
+                    // The locked rows are being claimed by another worker
+                    let ids = eo_dsl::email_outbox
+                        .select(eo_dsl::id)
+                        .filter(
+                            eo_dsl::status
+                                .eq(models::EmailStatus::Pending)
+                                .and(eo_dsl::next_attempt_at.le(now))
+                                .or(eo_dsl::status
+                                    .eq(models::EmailStatus::Sending)
+                                    .and(eo_dsl::lease_expires_at.le(now))),
+                        )
+                        .order(eo_dsl::next_attempt_at)
+                        .limit(Self::BATCH_SIZE)
+                        .for_update()
+                        .skip_locked()
+                        .load::<Uuid>(conn)
+                        .await?;
+
+                    Ok(diesel::update(eo_dsl::email_outbox)
+                        .filter(eo_dsl::id.eq_any(ids))
+                        .set((
+                            eo_dsl::status.eq(models::EmailStatus::Sending),
+                            eo_dsl::lease_expires_at.eq(lease_expires_at),
+                        ))
+                        .returning((
+                            eo_dsl::id,
+                            eo_dsl::sender,
+                            eo_dsl::recipients,
+                            eo_dsl::message,
+                            eo_dsl::attempts,
+                        ))
+                        .get_results::<(Uuid, String, Vec<Option<String>>, Vec<u8>, i32)>(conn)
+                        .await?)
+                }
+                .scope_boxed()
+            })
+            .await?;
+
+        for (id, sender, recipients, message, attempts) in due {
+            let attempts = attempts + 1;
+            let result = match envelope(&sender, recipients) {
+                Ok(envelope) => self.transport.send(&envelope, &message).await,
+                Err(err) => Err(err),
+            };
+
+            // A worker that took longer than its lease leaves the email to the one that
+            // claimed it after
+            let claimed = eo_dsl::id
+                .eq(id)
+                .and(eo_dsl::status.eq(models::EmailStatus::Sending))
+                .and(eo_dsl::lease_expires_at.eq(lease_expires_at));
+
+            match result {
+                Ok(()) => {
+                    diesel::update(eo_dsl::email_outbox)
+                        .filter(claimed)
+                        .set((
+                            eo_dsl::status.eq(models::EmailStatus::Sent),
+                            eo_dsl::attempts.eq(attempts),
+                            eo_dsl::sent_at.eq(OffsetDateTime::now_utc()),
+                            // The message isn't needed anymore and holds codes and links
+                            eo_dsl::message.eq(Vec::<u8>::new()),
+                            eo_dsl::lease_expires_at.eq(None::<OffsetDateTime>),
+                        ))
+                        .execute(conn)
+                        .await?;
+                }
+                Err(err) => {
+                    let error = tonic::Status::from(err).message().to_string();
+                    let status = if attempts >= Self::MAX_ATTEMPTS {
+                        models::EmailStatus::Failed
+                    } else {
+                        models::EmailStatus::Pending
+                    };
+
+                    diesel::update(eo_dsl::email_outbox)
+                        .filter(claimed)
+                        .set((
+                            eo_dsl::status.eq(status),
+                            eo_dsl::attempts.eq(attempts),
+                            eo_dsl::next_attempt_at
+                                .eq(OffsetDateTime::now_utc() + Self::backoff(attempts)),
+                            eo_dsl::last_error.eq(error),
+                            eo_dsl::lease_expires_at.eq(None::<OffsetDateTime>),
+                        ))
+                        .execute(conn)
+                        .await?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn cleanup(&self) -> Result<(), MailError> {
+        let conn = &mut self
+            .pool
+            .get()
+            .await
+            .map_err(|_| MailError::PoolConnectionFailed)?;
+
+        use schema::email_outbox::dsl as eo_dsl;
+
+        diesel::delete(eo_dsl::email_outbox)
+            .filter(eo_dsl::status.eq_any([models::EmailStatus::Sent, models::EmailStatus::Failed]))
+            .filter(eo_dsl::created_at.lt(OffsetDateTime::now_utc() - Self::RETENTION))
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    fn backoff(attempts: i32) -> Duration {
+        // Capping the exponent keeps the multiplication from overflowing
+        let exponent = (attempts - 1).clamp(0, 16) as u32;
+        (Self::RETRY_BASE * 2u32.pow(exponent)).min(Self::RETRY_MAX)
+    }
+}
+
+fn envelope(sender: &str, recipients: Vec<Option<String>>) -> Result<Envelope, MailError> {
+    let sender = sender
+        .parse::<Address>()
+        .map_err(|_| MailError::InvalidAddress(sender.to_string()))?;
+    let recipients = recipients
+        .into_iter()
+        .flatten()
+        .map(|recipient| {
+            recipient
+                .parse::<Address>()
+                .map_err(|_| MailError::InvalidAddress(recipient))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Envelope::new(Some(sender), recipients).map_err(|e| MailError::BuildFailed(e.to_string()))
+}
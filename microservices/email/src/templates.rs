@@ -0,0 +1,156 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use handlebars::Handlebars;
+
+use crate::MailError;
+
+/// The email templates, loaded from a directory laid out as
+///
+/// ```text
+/// layout.html.hbs
+/// layout.txt.hbs
+/// <locale>/<template>.subject.hbs
+/// <locale>/<template>.html.hbs
+/// <locale>/<template>.txt.hbs
+/// ```
+///
+/// The rendered bodies are wrapped in the layout of their kind, which gets them as `body`
+/// along with the `subject`. The plain text variant of a template is optional.
+pub struct Templates {
+    html: Handlebars<'static>,
+    text: Handlebars<'static>,
+    default_locale: String,
+}
+
+pub struct Rendered {
+    pub subject: String,
+    pub html: String,
+    pub text: Option<String>,
+}
+
+impl Templates {
+    const LAYOUT: &'static str = "layout";
+
+    pub fn load(path: &Path, default_locale: String) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut html = Handlebars::new();
+        html.set_strict_mode(true);
+        // Only the html templates escape their params, the subject and plain text are sent as is
+        let mut text = Handlebars::new();
+        text.set_strict_mode(true);
+        text.register_escape_fn(handlebars::no_escape);
+
+        let mut register = |name: &str, path: &Path| -> Result<(), Box<dyn std::error::Error>> {
+            let source = fs::read_to_string(path)?;
+            if name.ends_with(".html") {
+                html.register_template_string(name, source)?;
+            } else {
+                text.register_template_string(name, source)?;
+            }
+
+            Ok(())
+        };
+
+        for entry in fs::read_dir(path)? {
+            let entry = entry?;
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+
+            if entry.file_type()?.is_dir() {
+                // A directory holds the templates of one locale
+                for template in fs::read_dir(entry.path())? {
+                    let template = template?;
+                    let template_name = template.file_name().to_string_lossy().into_owned();
+                    if let Some(name) = template_name.strip_suffix(".hbs") {
+                        register(&format!("{file_name}/{name}"), &template.path())?;
+                    }
+                }
+            } else if let Some(name) = file_name.strip_suffix(".hbs") {
+                register(name, &entry.path())?;
+            }
+        }
+
+        Ok(Templates {
+            html,
+            text,
+            default_locale,
+        })
+    }
+
+    /// Finds the locale to render `template` in. A locale like `de-CH` falls back to `de`,
+    /// and then to the default locale.
+    fn locale(&self, template: &str, locale: Option<&str>) -> Result<String, MailError> {
+        let candidates = locale
+            .into_iter()
+            .flat_map(|locale| [locale, locale.split('-').next().unwrap_or(locale)])
+            .chain([self.default_locale.as_str()]);
+
+        for candidate in candidates {
+            if self
+                .html
+                .has_template(&format!("{candidate}/{template}.html"))
+            {
+                return Ok(candidate.to_string());
+            }
+        }
+
+        Err(MailError::TemplateNotFound(template.to_string()))
+    }
+
+    pub fn render(
+        &self,
+        template: &str,
+        locale: Option<&str>,
+        params: &HashMap<String, String>,
+    ) -> Result<Rendered, MailError> {
+        let locale = self.locale(template, locale)?;
+        let name = format!("{locale}/{template}");
+
+        let subject = self
+            .text
+            .render(&format!("{name}.subject"), params)
+            .map_err(|e| MailError::RenderFailed(e.to_string()))?
+            .trim()
+            .to_string();
+
+        let html = self
+            .html
+            .render(&format!("{name}.html"), params)
+            .map_err(|e| MailError::RenderFailed(e.to_string()))?;
+        let html = self.layout(&self.html, ".html", &subject, html)?;
+
+        let text = if self.text.has_template(&format!("{name}.txt")) {
+            let text = self
+                .text
+                .render(&format!("{name}.txt"), params)
+                .map_err(|e| MailError::RenderFailed(e.to_string()))?;
+            Some(self.layout(&self.text, ".txt", &subject, text)?)
+        } else {
+            None
+        };
+
+        Ok(Rendered {
+            subject,
+            html,
+            text,
+        })
+    }
+
+    fn layout(
+        &self,
+        registry: &Handlebars<'static>,
+        kind: &str,
+        subject: &str,
+        body: String,
+    ) -> Result<String, MailError> {
+        let layout = format!("{}{kind}", Self::LAYOUT);
+        if !registry.has_template(&layout) {
+            return Ok(body);
+        }
+
+        registry
+            .render(
+                &layout,
+                &HashMap::from([("subject", subject), ("body", body.as_str())]),
+            )
+            .map_err(|e| MailError::RenderFailed(e.to_string()))
+    }
+}
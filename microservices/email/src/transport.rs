@@ -0,0 +1,100 @@
+use std::{env, path::PathBuf, sync::Arc};
+
+use lettre::{
+    address::Envelope, transport::smtp::authentication::Credentials, AsyncFileTransport,
+    AsyncSmtpTransport, AsyncTransport, Tokio1Executor,
+};
+
+use crate::MailError;
+
+/// Where the mails sent by the Mail service end up.
+#[tonic::async_trait]
+pub trait MailTransport: Send + Sync {
+    /// Sends the already formatted `message` to the recipients of the `envelope`.
+    async fn send(&self, envelope: &Envelope, message: &[u8]) -> Result<(), MailError>;
+}
+
+/// Sends the mails to an SMTP server.
+pub struct SmtpTransport(AsyncSmtpTransport<Tokio1Executor>);
+
+#[tonic::async_trait]
+impl MailTransport for SmtpTransport {
+    async fn send(&self, envelope: &Envelope, message: &[u8]) -> Result<(), MailError> {
+        self.0
+            .send_raw(envelope, message)
+            .await
+            .map_err(|e| MailError::SendFailed(e.to_string()))?;
+
+        Ok(())
+    }
+}
+
+/// Writes every mail as an `.eml` file into a directory, for local development.
+pub struct FileTransport(AsyncFileTransport<Tokio1Executor>);
+
+#[tonic::async_trait]
+impl MailTransport for FileTransport {
+    async fn send(&self, envelope: &Envelope, message: &[u8]) -> Result<(), MailError> {
+        self.0
+            .send_raw(envelope, message)
+            .await
+            .map_err(|e| MailError::SendFailed(e.to_string()))?;
+
+        Ok(())
+    }
+}
+
+/// Prints the mails instead of sending them.
+pub struct StdoutTransport;
+
+#[tonic::async_trait]
+impl MailTransport for StdoutTransport {
+    async fn send(&self, _envelope: &Envelope, message: &[u8]) -> Result<(), MailError> {
+        println!("{}", String::from_utf8_lossy(message));
+
+        Ok(())
+    }
+}
+
+/// Builds the transport selected by `MAIL_TRANSPORT`, which is one of `smtp`, `file` or
+/// `stdout` (the default).
+///
+/// The smtp transport is configured with `SMTP_HOST`, `SMTP_PORT`, `SMTP_STARTTLS` and
+/// optionally `SMTP_USERNAME` and `SMTP_PASSWORD`. Without STARTTLS the connection is
+/// unencrypted, which is only meant for local test servers. The file transport writes to
+/// `MAIL_FILE_PATH`.
+pub fn from_env() -> Arc<dyn MailTransport> {
+    match env::var("MAIL_TRANSPORT").as_deref() {
+        Ok("smtp") => {
+            let host = env::var("SMTP_HOST").expect("SMTP_HOST must be set");
+            let starttls = env::var("SMTP_STARTTLS")
+                .map(|v| v.parse().expect("SMTP_STARTTLS must be true or false"))
+                .unwrap_or(true);
+
+            let mut builder = if starttls {
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&host)
+                    .expect("Failed to set up the smtp relay")
+            } else {
+                AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&host)
+            };
+            if let Ok(port) = env::var("SMTP_PORT") {
+                builder = builder.port(port.parse().expect("SMTP_PORT must be a port number"));
+            }
+            if let (Ok(username), Ok(password)) =
+                (env::var("SMTP_USERNAME"), env::var("SMTP_PASSWORD"))
+            {
+                builder = builder.credentials(Credentials::new(username, password));
+            }
+
+            Arc::new(SmtpTransport(builder.build()))
+        }
+        Ok("file") => {
+            let path = PathBuf::from(env::var("MAIL_FILE_PATH").unwrap_or("./mails".to_string()));
+            std::fs::create_dir_all(&path).expect("Failed to create the mail directory");
+
+            Arc::new(FileTransport(AsyncFileTransport::new(path)))
+        }
+        Ok("stdout") | Err(_) => Arc::new(StdoutTransport),
+        Ok(other) => panic!("Unknown MAIL_TRANSPORT: {other}"),
+    }
+}
@@ -5,9 +5,10 @@ use actix_web::{
 };
 use lunu::{
     account::{
-        Approval, CustomerDesc, Id, LimitLevel, LimitPeriod, Limits, Money, PartnerDesc,
-        PutPartnerFeeEntry, PutPartnerFees, PutRetailerFeeEntry, PutRetailerFees, RetailerDesc,
-        RetailerPartner, Routing, SetApproval, SetLimit, SetLimitGlobal, SetMinPurchase,
+        Approval, CustomerDesc, Id, LimitLevel, LimitPeriod, Limits, ListProvider, Money,
+        NewProvider, PartnerDesc, PaymentMethodFee, ProviderId, ProviderKind, PutPartnerFeeEntry,
+        PutPartnerFees, PutRetailerFeeEntry, PutRetailerFees, RetailerDesc, RetailerPartner,
+        Routing, SetApproval, SetLimit, SetLimitGlobal, SetMinPurchase, SetProviderName,
         SetRouting,
     },
     auth::Scope,
@@ -1277,6 +1278,54 @@ pub async fn set_retailer_fees(
     }
 }
 
+#[actix_web::delete("/retailer/{retailer_id}/fees/{payment_method_id}")]
+pub async fn delete_retailer_fee(user: User, path: web::Path<(String, String)>) -> impl Responder {
+    let User::Authenticated { scopes , .. } = user else {
+        return (
+            Json(serde_json::json!({
+                "error": "You are not authenticated and can't access this api."
+            })),
+            StatusCode::UNAUTHORIZED,
+        );
+    };
+
+    if !scopes.contains(&Scope::Admin) {
+        return (
+            Json(serde_json::json!({
+                "error": "You do not have permission to access this api."
+            })),
+            StatusCode::UNAUTHORIZED,
+        );
+    }
+
+    let (retailer_id, payment_method_id) = path.into_inner();
+    let mut client = ACCOUNT_CLIENT
+        .get()
+        .expect("ACCOUNT_CLIENT used before it was initalized")
+        .clone();
+
+    match client
+        .delete_retailer_fee(PaymentMethodFee {
+            id: retailer_id,
+            payment_method_id,
+        })
+        .await
+    {
+        Ok(_) => (
+            Json(serde_json::json!({
+                "success": [],
+            })),
+            StatusCode::OK,
+        ),
+        Err(status) => (
+            Json(serde_json::json!({
+                "error": status.message(),
+            })),
+            tonic_code_to_status_code(status.code()),
+        ),
+    }
+}
+
 #[actix_web::get("/partner/{partner_id}/fees")]
 pub async fn get_partner_fees(user: User, path: web::Path<String>) -> impl Responder {
     let User::Authenticated { .. } = user else {
@@ -1356,3 +1405,252 @@ pub async fn set_partner_fees(
         ),
     }
 }
+
+#[actix_web::delete("/partner/{partner_id}/fees/{payment_method_id}")]
+pub async fn delete_partner_fee(user: User, path: web::Path<(String, String)>) -> impl Responder {
+    let User::Authenticated { scopes , .. } = user else {
+        return (
+            Json(serde_json::json!({
+                "error": "You are not authenticated and can't access this api."
+            })),
+            StatusCode::UNAUTHORIZED,
+        );
+    };
+
+    if !scopes.contains(&Scope::Admin) {
+        return (
+            Json(serde_json::json!({
+                "error": "You do not have permission to access this api."
+            })),
+            StatusCode::UNAUTHORIZED,
+        );
+    }
+
+    let (partner_id, payment_method_id) = path.into_inner();
+    let mut client = ACCOUNT_CLIENT
+        .get()
+        .expect("ACCOUNT_CLIENT used before it was initalized")
+        .clone();
+
+    match client
+        .delete_partner_fee(PaymentMethodFee {
+            id: partner_id,
+            payment_method_id,
+        })
+        .await
+    {
+        Ok(_) => (
+            Json(serde_json::json!({
+                "success": [],
+            })),
+            StatusCode::OK,
+        ),
+        Err(status) => (
+            Json(serde_json::json!({
+                "error": status.message(),
+            })),
+            tonic_code_to_status_code(status.code()),
+        ),
+    }
+}
+
+#[actix_web::get("/providers/{kind}")]
+pub async fn list_providers(user: User, path: web::Path<ProviderKind>) -> impl Responder {
+    let User::Authenticated { scopes , .. } = user else {
+        return (
+            Either::Right(Json(serde_json::json!({
+                "error": "You are not authenticated and can't access this api."
+            }))),
+            StatusCode::UNAUTHORIZED,
+        );
+    };
+
+    if !scopes.contains(&Scope::Admin) {
+        return (
+            Either::Right(Json(serde_json::json!({
+                "error": "You do not have permission to access this api."
+            }))),
+            StatusCode::UNAUTHORIZED,
+        );
+    }
+
+    let mut client = ACCOUNT_CLIENT
+        .get()
+        .expect("ACCOUNT_CLIENT used before it was initalized")
+        .clone();
+
+    match client
+        .list_providers(ListProvider {
+            kind: path.into_inner() as i32,
+        })
+        .await
+    {
+        Ok(resp) => (Either::Left(Json(resp.into_inner())), StatusCode::OK),
+        Err(status) => (
+            Either::Right(Json(serde_json::json!({
+                "error": status.message(),
+            }))),
+            tonic_code_to_status_code(status.code()),
+        ),
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct ProviderParams {
+    name: String,
+}
+
+#[actix_web::post("/providers/{kind}")]
+pub async fn create_provider(
+    user: User,
+    path: web::Path<ProviderKind>,
+    params: Json<ProviderParams>,
+) -> impl Responder {
+    let User::Authenticated { scopes , .. } = user else {
+        return (
+            Json(serde_json::json!({
+                "error": "You are not authenticated and can't access this api."
+            })),
+            StatusCode::UNAUTHORIZED,
+        );
+    };
+
+    if !scopes.contains(&Scope::Admin) {
+        return (
+            Json(serde_json::json!({
+                "error": "You do not have permission to access this api."
+            })),
+            StatusCode::UNAUTHORIZED,
+        );
+    }
+
+    let mut client = ACCOUNT_CLIENT
+        .get()
+        .expect("ACCOUNT_CLIENT used before it was initalized")
+        .clone();
+
+    match client
+        .create_provider(NewProvider {
+            kind: path.into_inner() as i32,
+            name: params.0.name,
+        })
+        .await
+    {
+        Ok(id) => (
+            Json(serde_json::json!({
+                "id": id.into_inner().id,
+            })),
+            StatusCode::CREATED,
+        ),
+        Err(status) => (
+            Json(serde_json::json!({
+                "error": status.message(),
+            })),
+            tonic_code_to_status_code(status.code()),
+        ),
+    }
+}
+
+#[actix_web::post("/providers/{kind}/{id}")]
+pub async fn rename_provider(
+    user: User,
+    path: web::Path<(ProviderKind, String)>,
+    params: Json<ProviderParams>,
+) -> impl Responder {
+    let User::Authenticated { scopes , .. } = user else {
+        return (
+            Json(serde_json::json!({
+                "error": "You are not authenticated and can't access this api."
+            })),
+            StatusCode::UNAUTHORIZED,
+        );
+    };
+
+    if !scopes.contains(&Scope::Admin) {
+        return (
+            Json(serde_json::json!({
+                "error": "You do not have permission to access this api."
+            })),
+            StatusCode::UNAUTHORIZED,
+        );
+    }
+
+    let (kind, id) = path.into_inner();
+    let mut client = ACCOUNT_CLIENT
+        .get()
+        .expect("ACCOUNT_CLIENT used before it was initalized")
+        .clone();
+
+    match client
+        .rename_provider(SetProviderName {
+            kind: kind as i32,
+            id,
+            name: params.0.name,
+        })
+        .await
+    {
+        Ok(_) => (
+            Json(serde_json::json!({
+                "success": [],
+            })),
+            StatusCode::OK,
+        ),
+        Err(status) => (
+            Json(serde_json::json!({
+                "error": status.message(),
+            })),
+            tonic_code_to_status_code(status.code()),
+        ),
+    }
+}
+
+#[actix_web::delete("/providers/{kind}/{id}")]
+pub async fn delete_provider(
+    user: User,
+    path: web::Path<(ProviderKind, String)>,
+) -> impl Responder {
+    let User::Authenticated { scopes , .. } = user else {
+        return (
+            Json(serde_json::json!({
+                "error": "You are not authenticated and can't access this api."
+            })),
+            StatusCode::UNAUTHORIZED,
+        );
+    };
+
+    if !scopes.contains(&Scope::Admin) {
+        return (
+            Json(serde_json::json!({
+                "error": "You do not have permission to access this api."
+            })),
+            StatusCode::UNAUTHORIZED,
+        );
+    }
+
+    let (kind, id) = path.into_inner();
+    let mut client = ACCOUNT_CLIENT
+        .get()
+        .expect("ACCOUNT_CLIENT used before it was initalized")
+        .clone();
+
+    match client
+        .delete_provider(ProviderId {
+            kind: kind as i32,
+            id,
+        })
+        .await
+    {
+        Ok(_) => (
+            Json(serde_json::json!({
+                "success": [],
+            })),
+            StatusCode::OK,
+        ),
+        Err(status) => (
+            Json(serde_json::json!({
+                "error": status.message(),
+            })),
+            tonic_code_to_status_code(status.code()),
+        ),
+    }
+}
@@ -1,12 +1,101 @@
-use std::{collections::HashSet, fmt};
+use std::{
+    collections::{HashMap, HashSet},
+    env, fmt,
+    sync::RwLock,
+    time::{Duration, Instant},
+};
 
-use actix_web::{http, web::Json, FromRequest, Responder, ResponseError};
+use actix_web::{
+    http,
+    web::{self, Json},
+    FromRequest, HttpRequest, Responder, ResponseError,
+};
 use futures_util::future::LocalBoxFuture;
-use lunu::auth::{AccountEmail, Scope, SessionToken};
+use jsonwebtoken::{DecodingKey, Validation};
+use lunu::auth::{
+    login_result, AccessClaims, AccountEmail, AccountId, ApiKeyId, ApiKeyToken, ClientInfo,
+    EmailChange, EmailChangeCode, LoginResult, NewApiKey, PasswordViolations, Scope, ScopeGrant,
+    SessionId, SessionToken, TotpCode,
+};
+use prost::Message;
 use tonic::Status;
 
 use crate::{tonic_code_to_status_code, AUTH_CLIENT};
 
+lazy_static::lazy_static! {
+    // Setting the key to validate access tokens with, the auth microservice signs them
+    // with the same secret
+    pub static ref JWT_DECODING_KEY: DecodingKey = DecodingKey::from_secret(
+        env::var("JWT_SECRET").expect("JWT_SECRET must be set").as_bytes(),
+    );
+    // Setting the revocations the access tokens are checked against, synced from the auth
+    // microservice by `sync_revocations`
+    pub static ref REVOCATIONS: RwLock<Revocations> = RwLock::new(Revocations::default());
+}
+
+#[derive(Default)]
+pub struct Revocations {
+    session_ids: HashSet<String>,
+    /// The token generation the access tokens of an account need to be from
+    accounts: HashMap<String, i32>,
+    synced_at: Option<Instant>,
+}
+
+impl Revocations {
+    // Setting how often the revocations are synced, which is how long a revoked access token
+    // can still be used
+    const SYNC_INTERVAL: Duration = Duration::from_secs(2);
+    // Setting how old the revocations can get before access tokens are refused altogether
+    const MAX_AGE: Duration = Duration::from_secs(30);
+
+    /// Returns why the access token with these claims is refused, if it is.
+    fn refusal(&self, claims: &AccessClaims) -> Option<AuthError> {
+        if self
+            .synced_at
+            .is_none_or(|synced_at| synced_at.elapsed() >= Self::MAX_AGE)
+        {
+            return Some(AuthError::RevocationsUnavailable);
+        }
+
+        let account_revoked = self
+            .accounts
+            .get(&claims.sub)
+            .is_some_and(|generation| claims.gen < *generation);
+        if account_revoked || self.session_ids.contains(&claims.sid) {
+            return Some(AuthError::RevokedAccessToken);
+        }
+
+        None
+    }
+}
+
+/// Keeps `REVOCATIONS` up to date, runs for as long as the gateway does.
+pub async fn sync_revocations() {
+    let mut client = AUTH_CLIENT
+        .get()
+        .expect("AUTH_CLIENT used before it was initalized")
+        .clone();
+
+    loop {
+        match client.list_revocations(()).await {
+            Ok(resp) => {
+                let revocations = resp.into_inner();
+                let mut cached = REVOCATIONS.write().expect("REVOCATIONS lock was poisoned");
+                cached.session_ids = revocations.session_ids.into_iter().collect();
+                cached.accounts = revocations
+                    .accounts
+                    .into_iter()
+                    .map(|account| (account.account_id, account.token_generation))
+                    .collect();
+                cached.synced_at = Some(Instant::now());
+            }
+            Err(err) => tracing::error!("Error in syncing the revoked access tokens: {err}"),
+        }
+
+        tokio::time::sleep(Revocations::SYNC_INTERVAL).await;
+    }
+}
+
 pub enum User {
     Authenticated {
         account_id: String,
@@ -15,12 +104,16 @@ pub enum User {
         partner_id: Option<String>,
         scopes: HashSet<Scope>,
         password_login: bool,
+        two_factor: bool,
+        session_id: Option<String>,
     },
     UnAuthenticated,
 }
 
 impl User {
     const SESSION_COOKIE: &'static str = "LUNU_SESSION";
+    const BEARER_PREFIX: &'static str = "Bearer ";
+    const API_KEY_PREFIX: &'static str = "lunu_";
     const DEFAULT_SCOPES: [Scope; 1] = [Scope::Public];
 
     pub fn is_account(&self, id: &str) -> bool {
@@ -57,47 +150,97 @@ impl FromRequest for User {
         req: &actix_web::HttpRequest,
         _payload: &mut actix_web::dev::Payload,
     ) -> Self::Future {
-        if let Some(session) = req
+        let bearer = req
+            .headers()
+            .get(http::header::AUTHORIZATION)
+            .and_then(|header| header.to_str().ok())
+            .and_then(|header| header.strip_prefix(Self::BEARER_PREFIX))
+            .map(|token| token.trim().to_string());
+        let session = req
             .cookie(Self::SESSION_COOKIE)
             .as_ref()
-            .map(|cookie| cookie.value())
-        {
-            let session = session.to_string();
-
-            Box::pin(async move {
-                let mut client = AUTH_CLIENT
-                    .get()
-                    .expect("AUTH_CLIENT used before it was initalized")
-                    .clone();
-
-                let account = client
-                    .fetch_account(SessionToken { token: session })
-                    .await
-                    .map_err(|err| AuthError::FailedToFetchUser(err))?
-                    .into_inner();
-
-                if let Some(acc) = account.account {
-                    Ok(User::Authenticated {
-                        scopes: acc.scopes().collect(),
-                        account_id: acc.id,
-                        customer_id: acc.customer_id,
-                        retailer_id: acc.retailer_id,
-                        partner_id: acc.partner_id,
-                        password_login: account.password_login,
-                    })
-                } else {
-                    Ok(User::UnAuthenticated)
-                }
-            })
-        } else {
-            Box::pin(async { Ok(User::UnAuthenticated) })
+            .map(|cookie| cookie.value().to_string());
+
+        // The bearer token takes precedence over the session cookie
+        let Some(token) = bearer.or(session) else {
+            return Box::pin(async { Ok(User::UnAuthenticated) });
+        };
+
+        // Access tokens are validated locally, without a round trip to the auth microservice
+        if !token.starts_with(Self::API_KEY_PREFIX) {
+            let claims = match jsonwebtoken::decode::<AccessClaims>(
+                &token,
+                &JWT_DECODING_KEY,
+                &Validation::default(),
+            ) {
+                Ok(data) => data.claims,
+                Err(err) => return Box::pin(async { Err(AuthError::InvalidAccessToken(err)) }),
+            };
+
+            // A valid signature isn't enough, the session or account may have been revoked since
+            let refusal = REVOCATIONS
+                .read()
+                .expect("REVOCATIONS lock was poisoned")
+                .refusal(&claims);
+            if let Some(err) = refusal {
+                return Box::pin(async { Err(err) });
+            }
+
+            let user = User::Authenticated {
+                scopes: claims
+                    .scopes
+                    .iter()
+                    .filter_map(|scope| Scope::from_str_name(scope))
+                    .collect(),
+                account_id: claims.sub,
+                customer_id: claims.customer_id,
+                retailer_id: claims.retailer_id,
+                partner_id: claims.partner_id,
+                password_login: claims.password_login,
+                two_factor: claims.two_factor,
+                session_id: Some(claims.sid),
+            };
+
+            return Box::pin(async move { Ok(user) });
         }
+
+        Box::pin(async move {
+            let mut client = AUTH_CLIENT
+                .get()
+                .expect("AUTH_CLIENT used before it was initalized")
+                .clone();
+
+            // Server to server requests use an api key
+            let account = client
+                .fetch_api_key_account(ApiKeyToken { key: token })
+                .await
+                .map_err(AuthError::FailedToFetchUser)?
+                .into_inner();
+
+            if let Some(acc) = account.account {
+                Ok(User::Authenticated {
+                    scopes: acc.scopes().collect(),
+                    account_id: acc.id,
+                    customer_id: acc.customer_id,
+                    retailer_id: acc.retailer_id,
+                    partner_id: acc.partner_id,
+                    password_login: account.password_login,
+                    two_factor: account.two_factor,
+                    session_id: account.session_id,
+                })
+            } else {
+                Ok(User::UnAuthenticated)
+            }
+        })
     }
 }
 
 #[derive(Debug)]
 pub enum AuthError {
     FailedToFetchUser(Status),
+    InvalidAccessToken(jsonwebtoken::errors::Error),
+    RevokedAccessToken,
+    RevocationsUnavailable,
 }
 
 impl fmt::Display for AuthError {
@@ -107,6 +250,13 @@ impl fmt::Display for AuthError {
                 "Failed to fetch user from the auth microservice: {:?}",
                 status.message()
             )),
+            AuthError::InvalidAccessToken(err) => {
+                f.write_fmt(format_args!("Invalid access token: {err}"))
+            }
+            AuthError::RevokedAccessToken => f.write_str("The access token was revoked"),
+            AuthError::RevocationsUnavailable => {
+                f.write_str("Access tokens can't be checked for revocation right now")
+            }
         }
     }
 }
@@ -114,14 +264,89 @@ impl fmt::Display for AuthError {
 impl ResponseError for AuthError {
     fn status_code(&self) -> actix_web::http::StatusCode {
         match self {
-            AuthError::FailedToFetchUser(_) => actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
+            // Lets refusals like a blocked account through instead of hiding them as a 500
+            AuthError::FailedToFetchUser(status) => tonic_code_to_status_code(status.code()),
+            // Tells the client to get a new access token with its refresh token
+            AuthError::InvalidAccessToken(_) | AuthError::RevokedAccessToken => {
+                http::StatusCode::UNAUTHORIZED
+            }
+            // Failing closed, a revoked access token must not be let through
+            AuthError::RevocationsUnavailable => http::StatusCode::SERVICE_UNAVAILABLE,
         }
     }
 }
 
+fn client_info(req: &HttpRequest) -> ClientInfo {
+    ClientInfo {
+        user_agent: req
+            .headers()
+            .get(http::header::USER_AGENT)
+            .and_then(|header| header.to_str().ok())
+            .map(|user_agent| user_agent.to_string()),
+        ip: req
+            .connection_info()
+            .realip_remote_addr()
+            .map(|ip| ip.to_string()),
+    }
+}
+
+/// Responds with the session token, or with the totp challenge token when the account
+/// has two factor authentication enabled.
+fn login_result_response(result: LoginResult) -> (Json<serde_json::Value>, http::StatusCode) {
+    match result.result {
+        Some(login_result::Result::Session(session)) => (
+            Json(serde_json::json!({
+                "token": session.access_token,
+                "refresh_token": session.refresh_token,
+            })),
+            http::StatusCode::OK,
+        ),
+        Some(login_result::Result::Challenge(challenge)) => (
+            Json(serde_json::json!({
+                "totp_challenge": challenge.token,
+            })),
+            http::StatusCode::OK,
+        ),
+        None => (
+            Json(serde_json::json!({
+                "error": "The auth microservice did not return a login result",
+            })),
+            http::StatusCode::INTERNAL_SERVER_ERROR,
+        ),
+    }
+}
+
+/// Responds with the error, listing every violated password rule when the password was
+/// rejected by the password policy.
+fn password_error_response(status: Status) -> (Json<serde_json::Value>, http::StatusCode) {
+    match PasswordViolations::decode(status.details()) {
+        Ok(PasswordViolations { violations }) if !violations.is_empty() => (
+            Json(serde_json::json!({
+                "error": status.message(),
+                "violations": violations
+                    .iter()
+                    .map(|violation| serde_json::json!({
+                        "rule": violation.rule().as_str_name(),
+                        "message": violation.message,
+                    }))
+                    .collect::<Vec<_>>(),
+            })),
+            tonic_code_to_status_code(status.code()),
+        ),
+        _ => (
+            Json(serde_json::json!({
+                "error": status.message(),
+            })),
+            tonic_code_to_status_code(status.code()),
+        ),
+    }
+}
+
 #[derive(serde::Deserialize)]
 pub(super) struct EmailLoginIntent {
     email: String,
+    #[serde(default)]
+    sign_up: bool,
 }
 
 #[actix_web::post("/create_email_login_intent")]
@@ -131,9 +356,9 @@ pub(super) async fn create_email_login_intent(intent: Json<EmailLoginIntent>) ->
         .expect("AUTH_CLIENT used before it was initalized")
         .clone();
 
-    let EmailLoginIntent { email } = intent.0;
+    let EmailLoginIntent { email, sign_up } = intent.0;
     match client
-        .create_email_login_intent(AccountEmail { email })
+        .create_email_login_intent(AccountEmail { email, sign_up })
         .await
     {
         Ok(resp) => (
@@ -158,7 +383,10 @@ pub(super) struct EmailLoginParams {
 }
 
 #[actix_web::post("/login_to_email_login_intent")]
-pub(super) async fn login_to_email_login_intent(params: Json<EmailLoginParams>) -> impl Responder {
+pub(super) async fn login_to_email_login_intent(
+    req: HttpRequest,
+    params: Json<EmailLoginParams>,
+) -> impl Responder {
     let mut client = AUTH_CLIENT
         .get()
         .expect("AUTH_CLIENT used before it was initalized")
@@ -166,15 +394,14 @@ pub(super) async fn login_to_email_login_intent(params: Json<EmailLoginParams>)
 
     let EmailLoginParams { token, code } = params.0;
     match client
-        .login_with_email_login(lunu::auth::EmailLoginParams { token, code })
+        .login_with_email_login(lunu::auth::EmailLoginParams {
+            token,
+            code,
+            client: Some(client_info(&req)),
+        })
         .await
     {
-        Ok(resp) => (
-            Json(serde_json::json!({
-                "token": resp.into_inner().token,
-            })),
-            http::StatusCode::OK,
-        ),
+        Ok(resp) => login_result_response(resp.into_inner()),
         Err(status) => (
             Json(serde_json::json!({
                 "error": status.message(),
@@ -187,6 +414,8 @@ pub(super) async fn login_to_email_login_intent(params: Json<EmailLoginParams>)
 #[derive(serde::Deserialize)]
 pub(super) struct CreateNewPassLoginParams {
     email: String,
+    #[serde(default)]
+    sign_up: bool,
 }
 
 #[actix_web::post("/create_new_pass_login_intent")]
@@ -198,14 +427,14 @@ pub(super) async fn create_new_pass_login_intent(
         .expect("AUTH_CLIENT used before it was initalized")
         .clone();
 
-    let CreateNewPassLoginParams { email } = params.0;
+    let CreateNewPassLoginParams { email, sign_up } = params.0;
     match client
-        .create_new_pass_login_intent(lunu::auth::AccountEmail { email })
+        .create_new_pass_login_intent(lunu::auth::AccountEmail { email, sign_up })
         .await
     {
         Ok(_resp) => (
             Json(serde_json::json!({
-                "success": "The new passoword link was sent in the email",
+                "success": "If there is an account for this email, a new password link was sent to it",
             })),
             http::StatusCode::OK,
         ),
@@ -219,26 +448,25 @@ pub(super) async fn create_new_pass_login_intent(
 }
 
 #[derive(serde::Deserialize)]
-pub(super) struct NewPassLoginParams {
+pub(super) struct NewPassLoginToken {
     token: String,
-    password: String,
 }
 
-#[actix_web::post("/login_with_new_pass_login")]
-pub(super) async fn login_with_new_pass_login(params: Json<NewPassLoginParams>) -> impl Responder {
+#[actix_web::post("/check_new_pass_login_intent")]
+pub(super) async fn check_new_pass_login_intent(params: Json<NewPassLoginToken>) -> impl Responder {
     let mut client = AUTH_CLIENT
         .get()
         .expect("AUTH_CLIENT used before it was initalized")
         .clone();
 
-    let NewPassLoginParams { token, password } = params.0;
+    let NewPassLoginToken { token } = params.0;
     match client
-        .login_with_new_pass_login(lunu::auth::NewPassLoginParams { token, password })
+        .check_new_pass_login_intent(lunu::auth::NewPassLoginToken { token })
         .await
     {
         Ok(resp) => (
             Json(serde_json::json!({
-                "token": resp.into_inner().token,
+                "expires_at": resp.into_inner().expires_at,
             })),
             http::StatusCode::OK,
         ),
@@ -251,6 +479,36 @@ pub(super) async fn login_with_new_pass_login(params: Json<NewPassLoginParams>)
     }
 }
 
+#[derive(serde::Deserialize)]
+pub(super) struct NewPassLoginParams {
+    token: String,
+    password: String,
+}
+
+#[actix_web::post("/login_with_new_pass_login")]
+pub(super) async fn login_with_new_pass_login(
+    req: HttpRequest,
+    params: Json<NewPassLoginParams>,
+) -> impl Responder {
+    let mut client = AUTH_CLIENT
+        .get()
+        .expect("AUTH_CLIENT used before it was initalized")
+        .clone();
+
+    let NewPassLoginParams { token, password } = params.0;
+    match client
+        .login_with_new_pass_login(lunu::auth::NewPassLoginParams {
+            token,
+            password,
+            client: Some(client_info(&req)),
+        })
+        .await
+    {
+        Ok(resp) => login_result_response(resp.into_inner()),
+        Err(status) => password_error_response(status),
+    }
+}
+
 #[derive(serde::Deserialize)]
 pub(super) struct PasswordParams {
     email: String,
@@ -258,7 +516,10 @@ pub(super) struct PasswordParams {
 }
 
 #[actix_web::post("/create_with_password")]
-pub(super) async fn create_with_password(params: Json<PasswordParams>) -> impl Responder {
+pub(super) async fn create_with_password(
+    req: HttpRequest,
+    params: Json<PasswordParams>,
+) -> impl Responder {
     let mut client = AUTH_CLIENT
         .get()
         .expect("AUTH_CLIENT used before it was initalized")
@@ -266,15 +527,44 @@ pub(super) async fn create_with_password(params: Json<PasswordParams>) -> impl R
 
     let PasswordParams { email, password } = params.0;
     match client
-        .create_with_password(lunu::auth::PasswordParams { email, password })
+        .create_with_password(lunu::auth::PasswordParams {
+            email,
+            password,
+            client: Some(client_info(&req)),
+        })
         .await
     {
+        // The code sent to the email logs in with `login_to_email_login_intent`
         Ok(resp) => (
             Json(serde_json::json!({
                 "token": resp.into_inner().token,
             })),
             http::StatusCode::OK,
         ),
+        Err(status) => password_error_response(status),
+    }
+}
+
+#[actix_web::post("/login_with_password")]
+pub(super) async fn login_with_password(
+    req: HttpRequest,
+    params: Json<PasswordParams>,
+) -> impl Responder {
+    let mut client = AUTH_CLIENT
+        .get()
+        .expect("AUTH_CLIENT used before it was initalized")
+        .clone();
+
+    let PasswordParams { email, password } = params.0;
+    match client
+        .login_with_password(lunu::auth::PasswordParams {
+            email,
+            password,
+            client: Some(client_info(&req)),
+        })
+        .await
+    {
+        Ok(resp) => login_result_response(resp.into_inner()),
         Err(status) => (
             Json(serde_json::json!({
                 "error": status.message(),
@@ -284,21 +574,888 @@ pub(super) async fn create_with_password(params: Json<PasswordParams>) -> impl R
     }
 }
 
-#[actix_web::post("/login_with_password")]
-pub(super) async fn login_with_password(params: Json<PasswordParams>) -> impl Responder {
+#[derive(serde::Deserialize)]
+pub(super) struct TotpLoginParams {
+    token: String,
+    code: String,
+}
+
+#[actix_web::post("/login_with_totp")]
+pub(super) async fn login_with_totp(
+    req: HttpRequest,
+    params: Json<TotpLoginParams>,
+) -> impl Responder {
     let mut client = AUTH_CLIENT
         .get()
         .expect("AUTH_CLIENT used before it was initalized")
         .clone();
 
-    let PasswordParams { email, password } = params.0;
+    let TotpLoginParams { token, code } = params.0;
     match client
-        .login_with_password(lunu::auth::PasswordParams { email, password })
+        .login_with_totp(lunu::auth::TotpLoginParams {
+            token,
+            code,
+            client: Some(client_info(&req)),
+        })
         .await
     {
+        Ok(resp) => {
+            let tokens = resp.into_inner();
+            (
+                Json(serde_json::json!({
+                    "token": tokens.access_token,
+                    "refresh_token": tokens.refresh_token,
+                })),
+                http::StatusCode::OK,
+            )
+        }
+        Err(status) => (
+            Json(serde_json::json!({
+                "error": status.message(),
+            })),
+            tonic_code_to_status_code(status.code()),
+        ),
+    }
+}
+
+#[actix_web::post("/totp/enroll")]
+pub(super) async fn enroll_totp(user: User) -> impl Responder {
+    let User::Authenticated { account_id, session_id: Some(_), .. } = user else {
+        return (
+            Json(serde_json::json!({
+                "error": "You need to be logged in to access this api, api keys can't access it."
+            })),
+            http::StatusCode::UNAUTHORIZED,
+        );
+    };
+
+    let mut client = AUTH_CLIENT
+        .get()
+        .expect("AUTH_CLIENT used before it was initalized")
+        .clone();
+
+    match client.enroll_totp(AccountId { id: account_id }).await {
+        Ok(resp) => {
+            let enrollment = resp.into_inner();
+            (
+                Json(serde_json::json!({
+                    "secret": enrollment.secret,
+                    "otpauth_url": enrollment.otpauth_url,
+                })),
+                http::StatusCode::OK,
+            )
+        }
+        Err(status) => (
+            Json(serde_json::json!({
+                "error": status.message(),
+            })),
+            tonic_code_to_status_code(status.code()),
+        ),
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub(super) struct TotpCodeParams {
+    code: String,
+}
+
+#[actix_web::post("/totp/confirm")]
+pub(super) async fn confirm_totp(user: User, params: Json<TotpCodeParams>) -> impl Responder {
+    let User::Authenticated { account_id, session_id: Some(_), .. } = user else {
+        return (
+            Json(serde_json::json!({
+                "error": "You need to be logged in to access this api, api keys can't access it."
+            })),
+            http::StatusCode::UNAUTHORIZED,
+        );
+    };
+
+    let mut client = AUTH_CLIENT
+        .get()
+        .expect("AUTH_CLIENT used before it was initalized")
+        .clone();
+
+    let TotpCodeParams { code } = params.0;
+    match client.confirm_totp(TotpCode { account_id, code }).await {
         Ok(resp) => (
             Json(serde_json::json!({
-                "token": resp.into_inner().token,
+                "recovery_codes": resp.into_inner().codes,
+            })),
+            http::StatusCode::OK,
+        ),
+        Err(status) => (
+            Json(serde_json::json!({
+                "error": status.message(),
+            })),
+            tonic_code_to_status_code(status.code()),
+        ),
+    }
+}
+
+#[actix_web::post("/totp/disable")]
+pub(super) async fn disable_totp(user: User, params: Json<TotpCodeParams>) -> impl Responder {
+    let User::Authenticated { account_id, session_id: Some(_), .. } = user else {
+        return (
+            Json(serde_json::json!({
+                "error": "You need to be logged in to access this api, api keys can't access it."
+            })),
+            http::StatusCode::UNAUTHORIZED,
+        );
+    };
+
+    let mut client = AUTH_CLIENT
+        .get()
+        .expect("AUTH_CLIENT used before it was initalized")
+        .clone();
+
+    let TotpCodeParams { code } = params.0;
+    match client.disable_totp(TotpCode { account_id, code }).await {
+        Ok(_resp) => (
+            Json(serde_json::json!({
+                "success": "Two factor authentication was disabled",
+            })),
+            http::StatusCode::OK,
+        ),
+        Err(status) => (
+            Json(serde_json::json!({
+                "error": status.message(),
+            })),
+            tonic_code_to_status_code(status.code()),
+        ),
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub(super) struct CreateApiKeyParams {
+    name: String,
+    scopes: Vec<String>,
+}
+
+#[actix_web::get("/api_keys")]
+pub(super) async fn list_api_keys(user: User) -> impl Responder {
+    let User::Authenticated { account_id, scopes, session_id: Some(_), .. } = user else {
+        return (
+            Json(serde_json::json!({
+                "error": "You need to be logged in to access this api, api keys can't access it."
+            })),
+            http::StatusCode::UNAUTHORIZED,
+        );
+    };
+
+    if !scopes.contains(&Scope::Merchant) {
+        return (
+            Json(serde_json::json!({
+                "error": "You do not have permission to access this api."
+            })),
+            http::StatusCode::UNAUTHORIZED,
+        );
+    }
+
+    let mut client = AUTH_CLIENT
+        .get()
+        .expect("AUTH_CLIENT used before it was initalized")
+        .clone();
+
+    match client.list_api_keys(AccountId { id: account_id }).await {
+        Ok(resp) => (
+            Json(serde_json::json!(resp
+                .into_inner()
+                .api_keys
+                .into_iter()
+                .map(|api_key| serde_json::json!({
+                    "id": api_key.id,
+                    "name": api_key.name,
+                    "scopes": api_key
+                        .scopes()
+                        .map(|scope| scope.as_str_name())
+                        .collect::<Vec<_>>(),
+                    "created_at": api_key.created_at,
+                    "last_used_at": api_key.last_used_at,
+                }))
+                .collect::<Vec<_>>())),
+            http::StatusCode::OK,
+        ),
+        Err(status) => (
+            Json(serde_json::json!({
+                "error": status.message(),
+            })),
+            tonic_code_to_status_code(status.code()),
+        ),
+    }
+}
+
+#[actix_web::post("/api_keys")]
+pub(super) async fn create_api_key(user: User, params: Json<CreateApiKeyParams>) -> impl Responder {
+    let User::Authenticated { account_id, scopes: user_scopes, session_id: Some(_), .. } = user else {
+        return (
+            Json(serde_json::json!({
+                "error": "You need to be logged in to access this api, api keys can't access it."
+            })),
+            http::StatusCode::UNAUTHORIZED,
+        );
+    };
+
+    if !user_scopes.contains(&Scope::Merchant) {
+        return (
+            Json(serde_json::json!({
+                "error": "You do not have permission to access this api."
+            })),
+            http::StatusCode::UNAUTHORIZED,
+        );
+    }
+
+    let CreateApiKeyParams { name, scopes } = params.0;
+    let mut key_scopes = Vec::with_capacity(scopes.len());
+    for scope in scopes {
+        let Some(scope) = Scope::from_str_name(&scope) else {
+            return (
+                Json(serde_json::json!({
+                    "error": format!("Unknown scope: {scope}"),
+                })),
+                http::StatusCode::BAD_REQUEST,
+            );
+        };
+
+        // A key can't have more scopes than the account making it
+        if !user_scopes.contains(&scope) {
+            return (
+                Json(serde_json::json!({
+                    "error": format!("You can't grant the {} scope", scope.as_str_name()),
+                })),
+                http::StatusCode::FORBIDDEN,
+            );
+        }
+
+        key_scopes.push(scope as i32);
+    }
+
+    let mut client = AUTH_CLIENT
+        .get()
+        .expect("AUTH_CLIENT used before it was initalized")
+        .clone();
+
+    match client
+        .create_api_key(NewApiKey {
+            account_id,
+            name,
+            scopes: key_scopes,
+        })
+        .await
+    {
+        Ok(resp) => {
+            let api_key = resp.into_inner();
+            (
+                Json(serde_json::json!({
+                    "id": api_key.id,
+                    "key": api_key.key,
+                })),
+                http::StatusCode::OK,
+            )
+        }
+        Err(status) => (
+            Json(serde_json::json!({
+                "error": status.message(),
+            })),
+            tonic_code_to_status_code(status.code()),
+        ),
+    }
+}
+
+#[actix_web::delete("/api_keys/{id}")]
+pub(super) async fn revoke_api_key(user: User, path: web::Path<String>) -> impl Responder {
+    let User::Authenticated { account_id, scopes, session_id: Some(_), .. } = user else {
+        return (
+            Json(serde_json::json!({
+                "error": "You need to be logged in to access this api, api keys can't access it."
+            })),
+            http::StatusCode::UNAUTHORIZED,
+        );
+    };
+
+    if !scopes.contains(&Scope::Merchant) {
+        return (
+            Json(serde_json::json!({
+                "error": "You do not have permission to access this api."
+            })),
+            http::StatusCode::UNAUTHORIZED,
+        );
+    }
+
+    let mut client = AUTH_CLIENT
+        .get()
+        .expect("AUTH_CLIENT used before it was initalized")
+        .clone();
+
+    match client
+        .revoke_api_key(ApiKeyId {
+            account_id,
+            id: path.into_inner(),
+        })
+        .await
+    {
+        Ok(_resp) => (
+            Json(serde_json::json!({
+                "success": "The api key was revoked",
+            })),
+            http::StatusCode::OK,
+        ),
+        Err(status) => (
+            Json(serde_json::json!({
+                "error": status.message(),
+            })),
+            tonic_code_to_status_code(status.code()),
+        ),
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub(super) struct BlockAccountParams {
+    reason: String,
+}
+
+#[actix_web::post("/accounts/{account_id}/block")]
+pub(super) async fn block_account(
+    user: User,
+    path: web::Path<String>,
+    params: Json<BlockAccountParams>,
+) -> impl Responder {
+    let User::Authenticated { scopes , .. } = user else {
+        return (
+            Json(serde_json::json!({
+                "error": "You are not authenticated and can't access this api."
+            })),
+            http::StatusCode::UNAUTHORIZED,
+        );
+    };
+
+    if !scopes.contains(&Scope::Admin) {
+        return (
+            Json(serde_json::json!({
+                "error": "You do not have permission to access this api."
+            })),
+            http::StatusCode::UNAUTHORIZED,
+        );
+    }
+
+    let mut client = AUTH_CLIENT
+        .get()
+        .expect("AUTH_CLIENT used before it was initalized")
+        .clone();
+
+    let BlockAccountParams { reason } = params.0;
+    match client
+        .block_account(lunu::auth::BlockAccountParams {
+            account_id: path.into_inner(),
+            reason,
+        })
+        .await
+    {
+        Ok(_resp) => (
+            Json(serde_json::json!({
+                "success": "The account was blocked",
+            })),
+            http::StatusCode::OK,
+        ),
+        Err(status) => (
+            Json(serde_json::json!({
+                "error": status.message(),
+            })),
+            tonic_code_to_status_code(status.code()),
+        ),
+    }
+}
+
+#[actix_web::post("/accounts/{account_id}/unblock")]
+pub(super) async fn unblock_account(user: User, path: web::Path<String>) -> impl Responder {
+    let User::Authenticated { scopes , .. } = user else {
+        return (
+            Json(serde_json::json!({
+                "error": "You are not authenticated and can't access this api."
+            })),
+            http::StatusCode::UNAUTHORIZED,
+        );
+    };
+
+    if !scopes.contains(&Scope::Admin) {
+        return (
+            Json(serde_json::json!({
+                "error": "You do not have permission to access this api."
+            })),
+            http::StatusCode::UNAUTHORIZED,
+        );
+    }
+
+    let mut client = AUTH_CLIENT
+        .get()
+        .expect("AUTH_CLIENT used before it was initalized")
+        .clone();
+
+    match client
+        .unblock_account(AccountId {
+            id: path.into_inner(),
+        })
+        .await
+    {
+        Ok(_resp) => (
+            Json(serde_json::json!({
+                "success": "The account was unblocked",
+            })),
+            http::StatusCode::OK,
+        ),
+        Err(status) => (
+            Json(serde_json::json!({
+                "error": status.message(),
+            })),
+            tonic_code_to_status_code(status.code()),
+        ),
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub(super) struct RefreshParams {
+    refresh_token: String,
+}
+
+#[actix_web::post("/refresh")]
+pub(super) async fn refresh(params: Json<RefreshParams>) -> impl Responder {
+    let mut client = AUTH_CLIENT
+        .get()
+        .expect("AUTH_CLIENT used before it was initalized")
+        .clone();
+
+    match client
+        .refresh_session(SessionToken {
+            token: params.0.refresh_token,
+        })
+        .await
+    {
+        Ok(resp) => {
+            let tokens = resp.into_inner();
+            (
+                Json(serde_json::json!({
+                    "token": tokens.access_token,
+                    "refresh_token": tokens.refresh_token,
+                })),
+                http::StatusCode::OK,
+            )
+        }
+        Err(status) => (
+            Json(serde_json::json!({
+                "error": status.message(),
+            })),
+            tonic_code_to_status_code(status.code()),
+        ),
+    }
+}
+
+#[actix_web::post("/logout")]
+pub(super) async fn logout(user: User) -> impl Responder {
+    let User::Authenticated { account_id, session_id: Some(session_id), .. } = user else {
+        return (
+            Json(serde_json::json!({
+                "error": "You are not logged in."
+            })),
+            http::StatusCode::UNAUTHORIZED,
+        );
+    };
+
+    let mut client = AUTH_CLIENT
+        .get()
+        .expect("AUTH_CLIENT used before it was initalized")
+        .clone();
+
+    // The access token keeps working until it expires, but it can't be refreshed anymore
+    match client
+        .revoke_session(SessionId {
+            account_id,
+            id: session_id,
+        })
+        .await
+    {
+        Ok(_resp) => (
+            Json(serde_json::json!({
+                "success": "You were logged out",
+            })),
+            http::StatusCode::OK,
+        ),
+        Err(status) => (
+            Json(serde_json::json!({
+                "error": status.message(),
+            })),
+            tonic_code_to_status_code(status.code()),
+        ),
+    }
+}
+
+#[actix_web::get("/sessions")]
+pub(super) async fn list_sessions(user: User) -> impl Responder {
+    let User::Authenticated { account_id, session_id, .. } = user else {
+        return (
+            Json(serde_json::json!({
+                "error": "You are not authenticated and can't access this api."
+            })),
+            http::StatusCode::UNAUTHORIZED,
+        );
+    };
+
+    let mut client = AUTH_CLIENT
+        .get()
+        .expect("AUTH_CLIENT used before it was initalized")
+        .clone();
+
+    match client.list_sessions(AccountId { id: account_id }).await {
+        Ok(resp) => (
+            Json(serde_json::json!(resp
+                .into_inner()
+                .sessions
+                .into_iter()
+                .map(|session| serde_json::json!({
+                    "current": session_id.as_ref() == Some(&session.id),
+                    "id": session.id,
+                    "created_at": session.created_at,
+                    "last_used_at": session.last_used_at,
+                    "expires_at": session.expires_at,
+                    "user_agent": session.user_agent,
+                    "ip": session.ip,
+                    "password_login": session.password_login,
+                    "two_factor": session.two_factor,
+                }))
+                .collect::<Vec<_>>())),
+            http::StatusCode::OK,
+        ),
+        Err(status) => (
+            Json(serde_json::json!({
+                "error": status.message(),
+            })),
+            tonic_code_to_status_code(status.code()),
+        ),
+    }
+}
+
+#[actix_web::delete("/sessions/{id}")]
+pub(super) async fn revoke_session(user: User, path: web::Path<String>) -> impl Responder {
+    let User::Authenticated { account_id, session_id: Some(_), .. } = user else {
+        return (
+            Json(serde_json::json!({
+                "error": "You need to be logged in to access this api, api keys can't access it."
+            })),
+            http::StatusCode::UNAUTHORIZED,
+        );
+    };
+
+    let mut client = AUTH_CLIENT
+        .get()
+        .expect("AUTH_CLIENT used before it was initalized")
+        .clone();
+
+    match client
+        .revoke_session(SessionId {
+            account_id,
+            id: path.into_inner(),
+        })
+        .await
+    {
+        Ok(_resp) => (
+            Json(serde_json::json!({
+                "success": "The session was revoked",
+            })),
+            http::StatusCode::OK,
+        ),
+        Err(status) => (
+            Json(serde_json::json!({
+                "error": status.message(),
+            })),
+            tonic_code_to_status_code(status.code()),
+        ),
+    }
+}
+
+#[actix_web::delete("/sessions")]
+pub(super) async fn revoke_all_sessions(user: User) -> impl Responder {
+    let User::Authenticated { account_id, session_id: Some(_), .. } = user else {
+        return (
+            Json(serde_json::json!({
+                "error": "You need to be logged in to access this api, api keys can't access it."
+            })),
+            http::StatusCode::UNAUTHORIZED,
+        );
+    };
+
+    let mut client = AUTH_CLIENT
+        .get()
+        .expect("AUTH_CLIENT used before it was initalized")
+        .clone();
+
+    match client
+        .revoke_all_sessions(AccountId { id: account_id })
+        .await
+    {
+        Ok(_resp) => (
+            Json(serde_json::json!({
+                "success": "All sessions were revoked",
+            })),
+            http::StatusCode::OK,
+        ),
+        Err(status) => (
+            Json(serde_json::json!({
+                "error": status.message(),
+            })),
+            tonic_code_to_status_code(status.code()),
+        ),
+    }
+}
+
+#[actix_web::get("/accounts/{account_id}/scopes")]
+pub(super) async fn list_scopes(user: User, path: web::Path<String>) -> impl Responder {
+    let User::Authenticated { scopes , .. } = user else {
+        return (
+            Json(serde_json::json!({
+                "error": "You are not authenticated and can't access this api."
+            })),
+            http::StatusCode::UNAUTHORIZED,
+        );
+    };
+
+    if !scopes.contains(&Scope::Admin) {
+        return (
+            Json(serde_json::json!({
+                "error": "You do not have permission to access this api."
+            })),
+            http::StatusCode::UNAUTHORIZED,
+        );
+    }
+
+    let mut client = AUTH_CLIENT
+        .get()
+        .expect("AUTH_CLIENT used before it was initalized")
+        .clone();
+
+    match client
+        .list_scopes(AccountId {
+            id: path.into_inner(),
+        })
+        .await
+    {
+        Ok(resp) => (
+            Json(serde_json::json!(resp
+                .into_inner()
+                .scopes()
+                .map(|scope| scope.as_str_name())
+                .collect::<Vec<_>>())),
+            http::StatusCode::OK,
+        ),
+        Err(status) => (
+            Json(serde_json::json!({
+                "error": status.message(),
+            })),
+            tonic_code_to_status_code(status.code()),
+        ),
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub(super) struct GrantScopeParams {
+    scope: String,
+}
+
+#[actix_web::post("/accounts/{account_id}/scopes")]
+pub(super) async fn grant_scope(
+    user: User,
+    path: web::Path<String>,
+    params: Json<GrantScopeParams>,
+) -> impl Responder {
+    let User::Authenticated { scopes , .. } = user else {
+        return (
+            Json(serde_json::json!({
+                "error": "You are not authenticated and can't access this api."
+            })),
+            http::StatusCode::UNAUTHORIZED,
+        );
+    };
+
+    if !scopes.contains(&Scope::Admin) {
+        return (
+            Json(serde_json::json!({
+                "error": "You do not have permission to access this api."
+            })),
+            http::StatusCode::UNAUTHORIZED,
+        );
+    }
+
+    let GrantScopeParams { scope } = params.0;
+    let Some(scope) = Scope::from_str_name(&scope) else {
+        return (
+            Json(serde_json::json!({
+                "error": format!("Unknown scope: {scope}"),
+            })),
+            http::StatusCode::BAD_REQUEST,
+        );
+    };
+
+    let mut client = AUTH_CLIENT
+        .get()
+        .expect("AUTH_CLIENT used before it was initalized")
+        .clone();
+
+    match client
+        .grant_scope(ScopeGrant {
+            account_id: path.into_inner(),
+            scope: scope as i32,
+        })
+        .await
+    {
+        Ok(_resp) => (
+            Json(serde_json::json!({
+                "success": "The scope was granted",
+            })),
+            http::StatusCode::OK,
+        ),
+        Err(status) => (
+            Json(serde_json::json!({
+                "error": status.message(),
+            })),
+            tonic_code_to_status_code(status.code()),
+        ),
+    }
+}
+
+#[actix_web::delete("/accounts/{account_id}/scopes/{scope}")]
+pub(super) async fn revoke_scope(user: User, path: web::Path<(String, String)>) -> impl Responder {
+    let User::Authenticated { scopes , .. } = user else {
+        return (
+            Json(serde_json::json!({
+                "error": "You are not authenticated and can't access this api."
+            })),
+            http::StatusCode::UNAUTHORIZED,
+        );
+    };
+
+    if !scopes.contains(&Scope::Admin) {
+        return (
+            Json(serde_json::json!({
+                "error": "You do not have permission to access this api."
+            })),
+            http::StatusCode::UNAUTHORIZED,
+        );
+    }
+
+    let (account_id, scope) = path.into_inner();
+    let Some(scope) = Scope::from_str_name(&scope) else {
+        return (
+            Json(serde_json::json!({
+                "error": format!("Unknown scope: {scope}"),
+            })),
+            http::StatusCode::BAD_REQUEST,
+        );
+    };
+
+    let mut client = AUTH_CLIENT
+        .get()
+        .expect("AUTH_CLIENT used before it was initalized")
+        .clone();
+
+    match client
+        .revoke_scope(ScopeGrant {
+            account_id,
+            scope: scope as i32,
+        })
+        .await
+    {
+        Ok(_resp) => (
+            Json(serde_json::json!({
+                "success": "The scope was revoked",
+            })),
+            http::StatusCode::OK,
+        ),
+        Err(status) => (
+            Json(serde_json::json!({
+                "error": status.message(),
+            })),
+            tonic_code_to_status_code(status.code()),
+        ),
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub(super) struct EmailChangeParams {
+    email: String,
+}
+
+#[actix_web::post("/email/change")]
+pub(super) async fn request_email_change(
+    user: User,
+    params: Json<EmailChangeParams>,
+) -> impl Responder {
+    let User::Authenticated { account_id, session_id: Some(_), .. } = user else {
+        return (
+            Json(serde_json::json!({
+                "error": "You need to be logged in to access this api, api keys can't access it."
+            })),
+            http::StatusCode::UNAUTHORIZED,
+        );
+    };
+
+    let mut client = AUTH_CLIENT
+        .get()
+        .expect("AUTH_CLIENT used before it was initalized")
+        .clone();
+
+    match client
+        .request_email_change(EmailChange {
+            account_id,
+            new_email: params.0.email,
+        })
+        .await
+    {
+        Ok(_resp) => (
+            Json(serde_json::json!({
+                "success": "A confirmation code was sent to the new email",
+            })),
+            http::StatusCode::OK,
+        ),
+        Err(status) => (
+            Json(serde_json::json!({
+                "error": status.message(),
+            })),
+            tonic_code_to_status_code(status.code()),
+        ),
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub(super) struct EmailChangeCodeParams {
+    code: String,
+}
+
+#[actix_web::post("/email/confirm")]
+pub(super) async fn confirm_email_change(
+    user: User,
+    params: Json<EmailChangeCodeParams>,
+) -> impl Responder {
+    let User::Authenticated { account_id, session_id: Some(_), .. } = user else {
+        return (
+            Json(serde_json::json!({
+                "error": "You need to be logged in to access this api, api keys can't access it."
+            })),
+            http::StatusCode::UNAUTHORIZED,
+        );
+    };
+
+    let mut client = AUTH_CLIENT
+        .get()
+        .expect("AUTH_CLIENT used before it was initalized")
+        .clone();
+
+    match client
+        .confirm_email_change(EmailChangeCode {
+            account_id,
+            code: params.0.code,
+        })
+        .await
+    {
+        Ok(_resp) => (
+            Json(serde_json::json!({
+                "success": "The email was changed",
             })),
             http::StatusCode::OK,
         ),
@@ -1,6 +1,7 @@
 mod account;
 mod auth;
 mod storage;
+mod transaction;
 
 use std::time::Duration;
 
@@ -14,14 +15,20 @@ register_tonic_clients! {
     (AUTH_CLIENT, lunu::auth::auth_client::AuthClient<Channel>, lunu::Microservice::Auth, "auth"),
     (STORAGE_CLIENT, lunu::storage::storage_client::StorageClient<Channel>, lunu::Microservice::Storage, "storage"),
     (ACCOUNT_CLIENT, lunu::account::account_client::AccountClient<Channel>, lunu::Microservice::Account, "account"),
+    (TRANSACTION_CLIENT, lunu::transaction::transaction_client::TransactionClient<Channel>, lunu::Microservice::Transaction, "transaction"),
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     tracing_subscriber::fmt().init();
+    lunu::dotenvy::dotenv().ok();
+    // Failing on a missing jwt secret at startup instead of on the first request
+    lazy_static::initialize(&auth::JWT_DECODING_KEY);
 
     init_clients().await;
 
+    tokio::spawn(auth::sync_revocations());
+
     tokio::spawn(async {
         let mut client = AUTH_CLIENT
             .get()
@@ -44,9 +51,35 @@ async fn main() -> std::io::Result<()> {
                     .service(auth::create_email_login_intent)
                     .service(auth::login_to_email_login_intent)
                     .service(auth::create_new_pass_login_intent)
+                    .service(auth::check_new_pass_login_intent)
                     .service(auth::login_with_new_pass_login)
                     .service(auth::create_with_password)
-                    .service(auth::login_with_password),
+                    .service(auth::login_with_password)
+                    // Sessions
+                    .service(auth::refresh)
+                    .service(auth::logout)
+                    .service(auth::list_sessions)
+                    .service(auth::revoke_session)
+                    .service(auth::revoke_all_sessions)
+                    // Email change
+                    .service(auth::request_email_change)
+                    .service(auth::confirm_email_change)
+                    // Two factor authentication
+                    .service(auth::login_with_totp)
+                    .service(auth::enroll_totp)
+                    .service(auth::confirm_totp)
+                    .service(auth::disable_totp)
+                    // Api keys
+                    .service(auth::list_api_keys)
+                    .service(auth::create_api_key)
+                    .service(auth::revoke_api_key)
+                    // Blocking
+                    .service(auth::block_account)
+                    .service(auth::unblock_account)
+                    // Scopes
+                    .service(auth::list_scopes)
+                    .service(auth::grant_scope)
+                    .service(auth::revoke_scope),
             )
             .service(
                 web::scope("/api/v1/storage")
@@ -91,8 +124,22 @@ async fn main() -> std::io::Result<()> {
                     // Fees
                     .service(account::get_retailer_fees)
                     .service(account::set_retailer_fees)
+                    .service(account::delete_retailer_fee)
                     .service(account::get_partner_fees)
-                    .service(account::set_partner_fees),
+                    .service(account::set_partner_fees)
+                    .service(account::delete_partner_fee)
+                    // Providers
+                    .service(account::list_providers)
+                    .service(account::create_provider)
+                    .service(account::rename_provider)
+                    .service(account::delete_provider),
+            )
+            .service(
+                web::scope("/api/v1/transaction")
+                    .service(transaction::create_transaction)
+                    .service(transaction::list_transactions)
+                    .service(transaction::get_transaction)
+                    .service(transaction::update_status),
             )
     })
     .bind(("127.0.0.1", 8080))?
@@ -109,10 +156,10 @@ fn tonic_code_to_status_code(code: tonic::Code) -> http::StatusCode {
         | tonic::Code::DataLoss
         | tonic::Code::Internal
         | tonic::Code::Aborted
-        | tonic::Code::ResourceExhausted
         | tonic::Code::AlreadyExists
         | tonic::Code::Unknown => http::StatusCode::INTERNAL_SERVER_ERROR,
         tonic::Code::NotFound => http::StatusCode::NOT_FOUND,
+        tonic::Code::ResourceExhausted => http::StatusCode::TOO_MANY_REQUESTS,
         tonic::Code::PermissionDenied => http::StatusCode::FORBIDDEN,
         tonic::Code::FailedPrecondition => http::StatusCode::PRECONDITION_FAILED,
         tonic::Code::OutOfRange => http::StatusCode::RANGE_NOT_SATISFIABLE,
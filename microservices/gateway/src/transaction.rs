@@ -0,0 +1,241 @@
+use actix_web::{
+    http::StatusCode,
+    web::{self, Json},
+    Either, Responder,
+};
+use lunu::{
+    auth::Scope,
+    transaction::{SetStatus, TransactionDesc, TransactionFilter, TransactionId, TransactionStatus},
+};
+
+use crate::{tonic_code_to_status_code, User, TRANSACTION_CLIENT};
+
+#[actix_web::post("")]
+pub async fn create_transaction(user: User, params: Json<TransactionDesc>) -> impl Responder {
+    let User::Authenticated { retailer_id, scopes , .. } = user else {
+        return (
+            Json(serde_json::json!({
+                "error": "You are not authenticated and so you can't create a transaction."
+            })),
+            StatusCode::UNAUTHORIZED,
+        );
+    };
+
+    if !scopes.contains(&Scope::Admin)
+        && (retailer_id.is_none() || retailer_id != params.retailer_id)
+    {
+        return (
+            Json(serde_json::json!({
+                "error": "You do not have permission to create a transaction."
+            })),
+            StatusCode::UNAUTHORIZED,
+        );
+    }
+
+    let mut client = TRANSACTION_CLIENT
+        .get()
+        .expect("TRANSACTION_CLIENT used before it was initalized")
+        .clone();
+
+    match client.create_transaction(params.0).await {
+        Ok(id) => (
+            Json(serde_json::json!({
+                "id": id.into_inner().id,
+            })),
+            StatusCode::CREATED,
+        ),
+        Err(status) => (
+            Json(serde_json::json!({
+                "error": status.message(),
+            })),
+            tonic_code_to_status_code(status.code()),
+        ),
+    }
+}
+
+#[actix_web::get("/{transaction_id}")]
+pub async fn get_transaction(user: User, path: web::Path<String>) -> impl Responder {
+    let User::Authenticated { account_id, retailer_id, scopes , .. } = user else {
+        return (
+            Either::Right(Json(serde_json::json!({
+                "error": "You are not authenticated and can't access this api."
+            }))),
+            StatusCode::UNAUTHORIZED,
+        );
+    };
+
+    let mut client = TRANSACTION_CLIENT
+        .get()
+        .expect("TRANSACTION_CLIENT used before it was initalized")
+        .clone();
+
+    match client
+        .get_transaction(TransactionId {
+            id: path.into_inner(),
+        })
+        .await
+    {
+        Ok(resp) => {
+            let transaction = resp.into_inner();
+            if !scopes.contains(&Scope::Admin)
+                && (retailer_id.is_none() || retailer_id != transaction.retailer_id)
+                && account_id != transaction.source_account_wallet
+                && account_id != transaction.dest_account_wallet
+            {
+                return (
+                    Either::Right(Json(serde_json::json!({
+                        "error": "You do not have permission to access this api."
+                    }))),
+                    StatusCode::UNAUTHORIZED,
+                );
+            }
+
+            (Either::Left(Json(transaction)), StatusCode::OK)
+        }
+        Err(status) => (
+            Either::Right(Json(serde_json::json!({
+                "error": status.message(),
+            }))),
+            tonic_code_to_status_code(status.code()),
+        ),
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct ListTransactionsParams {
+    retailer_id: Option<String>,
+    account_id: Option<String>,
+}
+
+#[actix_web::get("")]
+pub async fn list_transactions(
+    user: User,
+    params: web::Query<ListTransactionsParams>,
+) -> impl Responder {
+    let User::Authenticated { account_id, retailer_id, scopes , .. } = user else {
+        return (
+            Either::Right(Json(serde_json::json!({
+                "error": "You are not authenticated and can't access this api."
+            }))),
+            StatusCode::UNAUTHORIZED,
+        );
+    };
+
+    let ListTransactionsParams {
+        retailer_id: in_retailer_id,
+        account_id: in_account_id,
+    } = params.into_inner();
+
+    // Non admins can only list the transactions of their own retailer or account
+    let filter = if scopes.contains(&Scope::Admin) {
+        TransactionFilter {
+            retailer_id: in_retailer_id,
+            account_id: in_account_id,
+        }
+    } else if in_retailer_id.is_some() {
+        if retailer_id != in_retailer_id {
+            return (
+                Either::Right(Json(serde_json::json!({
+                    "error": "You do not have permission to access this api."
+                }))),
+                StatusCode::UNAUTHORIZED,
+            );
+        }
+
+        TransactionFilter {
+            retailer_id,
+            account_id: None,
+        }
+    } else {
+        TransactionFilter {
+            retailer_id: None,
+            account_id: Some(account_id),
+        }
+    };
+
+    let mut client = TRANSACTION_CLIENT
+        .get()
+        .expect("TRANSACTION_CLIENT used before it was initalized")
+        .clone();
+
+    match client.list_transactions(filter).await {
+        Ok(resp) => (Either::Left(Json(resp.into_inner())), StatusCode::OK),
+        Err(status) => (
+            Either::Right(Json(serde_json::json!({
+                "error": status.message(),
+            }))),
+            tonic_code_to_status_code(status.code()),
+        ),
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct SetStatusParams {
+    status: TransactionStatus,
+}
+
+#[actix_web::post("/{transaction_id}/status")]
+pub async fn update_status(
+    user: User,
+    path: web::Path<String>,
+    params: Json<SetStatusParams>,
+) -> impl Responder {
+    let User::Authenticated { retailer_id, scopes , .. } = user else {
+        return (
+            Json(serde_json::json!({
+                "error": "You are not authenticated and can't access this api."
+            })),
+            StatusCode::UNAUTHORIZED,
+        );
+    };
+
+    let id = path.into_inner();
+    let mut client = TRANSACTION_CLIENT
+        .get()
+        .expect("TRANSACTION_CLIENT used before it was initalized")
+        .clone();
+
+    if !scopes.contains(&Scope::Admin) {
+        let transaction = match client.get_transaction(TransactionId { id: id.clone() }).await {
+            Ok(resp) => resp.into_inner(),
+            Err(status) => {
+                return (
+                    Json(serde_json::json!({
+                        "error": status.message(),
+                    })),
+                    tonic_code_to_status_code(status.code()),
+                )
+            }
+        };
+
+        if retailer_id.is_none() || retailer_id != transaction.retailer_id {
+            return (
+                Json(serde_json::json!({
+                    "error": "You do not have permission to access this api."
+                })),
+                StatusCode::UNAUTHORIZED,
+            );
+        }
+    }
+
+    match client
+        .update_status(SetStatus {
+            id,
+            status: params.0.status as i32,
+        })
+        .await
+    {
+        Ok(_) => (
+            Json(serde_json::json!({
+                "success": [],
+            })),
+            StatusCode::OK,
+        ),
+        Err(status) => (
+            Json(serde_json::json!({
+                "error": status.message(),
+            })),
+            tonic_code_to_status_code(status.code()),
+        ),
+    }
+}
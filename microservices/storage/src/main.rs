@@ -15,7 +15,7 @@ struct Storage {
 impl Storage {
     fn as_dir(&self, id: &FileId) -> PathBuf {
         let mut path = self.base_dir.clone();
-        path.push(id.account_id.to_string());
+        path.push(&id.account_id);
 
         path
     }
@@ -0,0 +1,367 @@
+use std::{env, str::FromStr};
+
+use lunu::{
+    account::HitLimit,
+    diesel::{
+        self, insert_into, update, BoolExpressionMethods, ExpressionMethods, OptionalExtension,
+        QueryDsl,
+    },
+    diesel_async::{
+        pooled_connection::{bb8::Pool, AsyncDieselConnectionManager},
+        scoped_futures::ScopedFutureExt,
+        AsyncConnection, AsyncPgConnection, RunQueryDsl,
+    },
+    dotenvy::dotenv,
+    limits::{self, LimitError},
+    models, schema,
+    transaction::{
+        transaction_server::TransactionServer, SetStatus, TransactionData, TransactionDesc,
+        TransactionFilter, TransactionId, TransactionStatus, Transactions,
+    },
+    Microservice, MICROSERVICE_ADDRS,
+};
+use time::OffsetDateTime;
+use tonic::transport::Server;
+use uuid::Uuid;
+
+struct Transaction {
+    pool: Pool<AsyncPgConnection>,
+}
+
+#[tonic::async_trait]
+impl lunu::transaction::transaction_server::Transaction for Transaction {
+    async fn create_transaction(
+        &self,
+        request: tonic::Request<TransactionDesc>,
+    ) -> Result<tonic::Response<TransactionId>, tonic::Status> {
+        let conn = &mut self
+            .pool
+            .get()
+            .await
+            .map_err(|_| TransactionError::PoolConnectionFailed)?;
+
+        let desc = request.into_inner();
+        let retailer_id = desc
+            .retailer_id
+            .map(|id| Uuid::from_str(&id))
+            .transpose()
+            .map_err(|_| TransactionError::MalformedRetailerId)?;
+        let source_account_wallet = Uuid::from_str(&desc.source_account_wallet)
+            .map_err(|_| TransactionError::MalformedAccountId)?;
+        let dest_account_wallet = Uuid::from_str(&desc.dest_account_wallet)
+            .map_err(|_| TransactionError::MalformedAccountId)?;
+        let (crypto_currency_type, crypto_amount) = desc
+            .crypto_amount
+            .ok_or(TransactionError::MissingAmount("crypto_amount"))?
+            .into();
+        let fiat = desc
+            .fiat_amount
+            .ok_or(TransactionError::MissingAmount("fiat_amount"))?;
+        let exchange_rate = desc
+            .exchange_rate
+            .ok_or(TransactionError::MissingAmount("exchange_rate"))?
+            .into();
+        let (payment_gateway_fee_currency, payment_gateway_fee) = desc
+            .payment_gateway_fee
+            .ok_or(TransactionError::MissingAmount("payment_gateway_fee"))?
+            .into();
+        let (exchange_spread_fee_currency, exchange_spread_fee) = desc
+            .exchange_spread_fee
+            .ok_or(TransactionError::MissingAmount("exchange_spread_fee"))?
+            .into();
+        let (partner_fee_currency, partner_fee) = desc
+            .partner_fee
+            .ok_or(TransactionError::MissingAmount("partner_fee"))?
+            .into();
+
+        let (fiat_type, fiat_amount) = fiat.clone().into();
+        let id = Uuid::new_v4();
+        let transaction = models::Transaction {
+            id,
+            retailer_id,
+            retailer_transaction_id: desc.retailer_transaction_id,
+            retailer_customer_id: desc.retailer_customer_id,
+            source_account_wallet,
+            dest_account_wallet,
+            kind: desc.kind,
+            timestamp: OffsetDateTime::now_utc(),
+            payment_method: desc.payment_method,
+            crypto_currency_type,
+            crypto_network: desc.crypto_network,
+            crypto_amount,
+            fiat_type,
+            fiat_amount,
+            exchange_rate,
+            dest_crypto_address: desc.dest_crypto_address,
+            transcation_hash: desc.transcation_hash,
+            payment_gateway_fee,
+            exchange_spread_fee,
+            partner_fee,
+            status: models::TransactionStatus::Created,
+            payment_gateway_fee_currency,
+            exchange_spread_fee_currency,
+            partner_fee_currency,
+        };
+
+        // The customer row is locked until the transaction is inserted, so concurrent
+        // transactions of a customer are checked against the limits one after the other
+        conn.transaction::<_, TransactionError, _>(move |conn| {
+            async move {
+                use schema::customers::dsl as c_dsl;
+
+                // Only transactions paid for by a customer count towards the limits
+                let customer_id = c_dsl::customers
+                    .filter(c_dsl::account_id.eq(source_account_wallet))
+                    .select(c_dsl::id)
+                    .for_update()
+                    .first::<Uuid>(conn)
+                    .await
+                    .optional()?;
+                if let Some(customer_id) = customer_id {
+                    let check = limits::check(conn, customer_id, retailer_id, fiat.into()).await?;
+
+                    if let Some(hit_limit) = check.hit_limit.filter(|_| !check.allowed) {
+                        return Err(TransactionError::LimitExceeded(hit_limit));
+                    }
+                }
+
+                use schema::transactions::dsl as t_dsl;
+
+                insert_into(t_dsl::transactions)
+                    .values(transaction)
+                    .execute(conn)
+                    .await?;
+
+                Ok(())
+            }
+            .scope_boxed()
+        })
+        .await?;
+
+        Ok(tonic::Response::new(TransactionId { id: id.to_string() }))
+    }
+
+    async fn get_transaction(
+        &self,
+        request: tonic::Request<TransactionId>,
+    ) -> Result<tonic::Response<TransactionData>, tonic::Status> {
+        let conn = &mut self
+            .pool
+            .get()
+            .await
+            .map_err(|_| TransactionError::PoolConnectionFailed)?;
+        let id = Uuid::from_str(&request.into_inner().id)
+            .map_err(|_| TransactionError::MalformedTransactionId)?;
+
+        use schema::transactions::dsl as t_dsl;
+
+        let transaction = t_dsl::transactions
+            .filter(t_dsl::id.eq(id))
+            .first::<models::Transaction>(conn)
+            .await
+            .optional()
+            .map_err(|e| TransactionError::QueryFailed(e.to_string()))?
+            .ok_or(TransactionError::TransactionNotFound)?;
+
+        Ok(tonic::Response::new(transaction.into()))
+    }
+
+    async fn list_transactions(
+        &self,
+        request: tonic::Request<TransactionFilter>,
+    ) -> Result<tonic::Response<Transactions>, tonic::Status> {
+        let conn = &mut self
+            .pool
+            .get()
+            .await
+            .map_err(|_| TransactionError::PoolConnectionFailed)?;
+        let TransactionFilter {
+            retailer_id,
+            account_id,
+        } = request.into_inner();
+
+        use schema::transactions::dsl as t_dsl;
+
+        let mut query = t_dsl::transactions.into_boxed();
+        if let Some(retailer_id) = retailer_id {
+            let retailer_id =
+                Uuid::from_str(&retailer_id).map_err(|_| TransactionError::MalformedRetailerId)?;
+            query = query.filter(t_dsl::retailer_id.eq(retailer_id));
+        }
+        if let Some(account_id) = account_id {
+            let account_id =
+                Uuid::from_str(&account_id).map_err(|_| TransactionError::MalformedAccountId)?;
+            query = query.filter(
+                t_dsl::source_account_wallet
+                    .eq(account_id)
+                    .or(t_dsl::dest_account_wallet.eq(account_id)),
+            );
+        }
+
+        let transactions = query
+            .order(t_dsl::timestamp.desc())
+            .load::<models::Transaction>(conn)
+            .await
+            .map_err(|e| TransactionError::QueryFailed(e.to_string()))?
+            .into_iter()
+            .map(|transaction| transaction.into())
+            .collect();
+
+        Ok(tonic::Response::new(Transactions { transactions }))
+    }
+
+    async fn update_status(
+        &self,
+        request: tonic::Request<SetStatus>,
+    ) -> Result<tonic::Response<()>, tonic::Status> {
+        let conn = &mut self
+            .pool
+            .get()
+            .await
+            .map_err(|_| TransactionError::PoolConnectionFailed)?;
+        let set_status = request.into_inner();
+        // `status()` would turn an unknown value into `Created`
+        let next = TransactionStatus::from_i32(set_status.status)
+            .map(models::TransactionStatus::from)
+            .ok_or(TransactionError::UnknownStatus(set_status.status))?;
+        let id =
+            Uuid::from_str(&set_status.id).map_err(|_| TransactionError::MalformedTransactionId)?;
+
+        use schema::transactions::dsl as t_dsl;
+
+        // Lock the row so two concurrent updates can't both pass the transition check
+        conn.transaction::<_, TransactionError, _>(|conn| {
+            async move {
+                let current = t_dsl::transactions
+                    .select(t_dsl::status)
+                    .filter(t_dsl::id.eq(id))
+                    .for_update()
+                    .first::<models::TransactionStatus>(conn)
+                    .await
+                    .optional()?
+                    .ok_or(TransactionError::TransactionNotFound)?;
+
+                if !current.can_transition_to(next) {
+                    return Err(TransactionError::InvalidStatusTransition(current, next));
+                }
+
+                update(t_dsl::transactions)
+                    .filter(t_dsl::id.eq(id))
+                    .set(t_dsl::status.eq(next))
+                    .execute(conn)
+                    .await?;
+
+                Ok(())
+            }
+            .scope_boxed()
+        })
+        .await?;
+
+        Ok(tonic::Response::new(()))
+    }
+}
+
+enum TransactionError {
+    MalformedTransactionId,
+    MalformedRetailerId,
+    MalformedAccountId,
+    QueryFailed(String),
+    PoolConnectionFailed,
+    MissingAmount(&'static str),
+    TransactionNotFound,
+    InvalidStatusTransition(models::TransactionStatus, models::TransactionStatus),
+    LimitExceeded(HitLimit),
+    LimitCurrencyMismatch(String, String),
+    CustomerNotFound,
+    UnknownStatus(i32),
+}
+
+impl From<diesel::result::Error> for TransactionError {
+    fn from(value: diesel::result::Error) -> Self {
+        TransactionError::QueryFailed(value.to_string())
+    }
+}
+
+impl From<LimitError> for TransactionError {
+    fn from(value: LimitError) -> Self {
+        match value {
+            LimitError::CustomerNotFound => TransactionError::CustomerNotFound,
+            LimitError::CurrencyMismatch(limit, amount) => {
+                TransactionError::LimitCurrencyMismatch(limit, amount)
+            }
+            LimitError::QueryFailed(s) => TransactionError::QueryFailed(s),
+        }
+    }
+}
+
+impl From<TransactionError> for tonic::Status {
+    fn from(value: TransactionError) -> Self {
+        match value {
+            TransactionError::MalformedTransactionId => {
+                tonic::Status::invalid_argument("Malformed transaction id")
+            }
+            TransactionError::MalformedRetailerId => {
+                tonic::Status::invalid_argument("Malformed retailer id")
+            }
+            TransactionError::MalformedAccountId => {
+                tonic::Status::invalid_argument("Malformed account id")
+            }
+            TransactionError::QueryFailed(s) => {
+                tonic::Status::internal(format!("Query Failed: {s}"))
+            }
+            TransactionError::PoolConnectionFailed => {
+                tonic::Status::internal("Failed to connect to the internal pool")
+            }
+            TransactionError::MissingAmount(field) => {
+                tonic::Status::invalid_argument(format!("Missing {field} from request"))
+            }
+            TransactionError::TransactionNotFound => {
+                tonic::Status::not_found("Transaction with the supplied id was not found")
+            }
+            TransactionError::InvalidStatusTransition(from, to) => {
+                tonic::Status::failed_precondition(format!(
+                    "Transaction can not move from status {from:?} to {to:?}"
+                ))
+            }
+            TransactionError::LimitExceeded(hit_limit) => {
+                tonic::Status::failed_precondition(format!(
+                    "Transaction exceeds the {:?} {:?} {:?}",
+                    hit_limit.period(),
+                    hit_limit.level(),
+                    hit_limit.source(),
+                ))
+            }
+            TransactionError::LimitCurrencyMismatch(limit, amount) => {
+                tonic::Status::invalid_argument(format!(
+                    "The limit is set in {limit} but the amount is in {amount}"
+                ))
+            }
+            TransactionError::CustomerNotFound => {
+                tonic::Status::invalid_argument("Customer with the supplied id was not found")
+            }
+            TransactionError::UnknownStatus(status) => {
+                tonic::Status::invalid_argument(format!("Unknown transaction status: {status}"))
+            }
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    dotenv().ok();
+
+    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+
+    let config = AsyncDieselConnectionManager::<AsyncPgConnection>::new(&database_url);
+    let transaction = Transaction {
+        pool: Pool::builder().build(config).await?,
+    };
+
+    let addr = MICROSERVICE_ADDRS[&Microservice::Transaction].parse()?;
+    Server::builder()
+        .add_service(TransactionServer::new(transaction))
+        .serve(addr)
+        .await?;
+
+    Ok(())
+}
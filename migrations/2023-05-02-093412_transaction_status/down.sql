@@ -0,0 +1,16 @@
+ALTER TABLE transactions ALTER COLUMN status DROP DEFAULT;
+ALTER TABLE transactions ALTER COLUMN status TYPE INTEGER USING (
+    CASE status
+        WHEN 'Created' THEN 0
+        WHEN 'AwaitingPayment' THEN 1
+        WHEN 'PaymentReceived' THEN 2
+        WHEN 'Exchanging' THEN 3
+        WHEN 'Sending' THEN 4
+        WHEN 'Completed' THEN 5
+        WHEN 'Failed' THEN 6
+        WHEN 'Refunded' THEN 7
+        WHEN 'Expired' THEN 8
+    END
+);
+
+DROP TYPE IF EXISTS TRANSACTION_STATUS;
\ No newline at end of file
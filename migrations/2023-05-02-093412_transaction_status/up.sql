@@ -0,0 +1,26 @@
+CREATE TYPE TRANSACTION_STATUS AS ENUM (
+    'Created',
+    'AwaitingPayment',
+    'PaymentReceived',
+    'Exchanging',
+    'Sending',
+    'Completed',
+    'Failed',
+    'Refunded',
+    'Expired'
+);
+
+ALTER TABLE transactions ALTER COLUMN status TYPE TRANSACTION_STATUS USING (
+    CASE status
+        WHEN 0 THEN 'Created'
+        WHEN 1 THEN 'AwaitingPayment'
+        WHEN 2 THEN 'PaymentReceived'
+        WHEN 3 THEN 'Exchanging'
+        WHEN 4 THEN 'Sending'
+        WHEN 5 THEN 'Completed'
+        WHEN 6 THEN 'Failed'
+        WHEN 7 THEN 'Refunded'
+        WHEN 8 THEN 'Expired'
+    END
+)::TRANSACTION_STATUS;
+ALTER TABLE transactions ALTER COLUMN status SET DEFAULT 'Created';
\ No newline at end of file
@@ -0,0 +1,13 @@
+ALTER TABLE transactions ALTER COLUMN dest_crypto_address TYPE INTEGER USING dest_crypto_address::INTEGER;
+ALTER TABLE transactions ALTER COLUMN transcation_hash TYPE INTEGER USING transcation_hash::INTEGER;
+
+ALTER TABLE transactions DROP COLUMN payment_gateway_fee_currency;
+ALTER TABLE transactions DROP COLUMN exchange_spread_fee_currency;
+ALTER TABLE transactions DROP COLUMN partner_fee_currency;
+
+ALTER TABLE transactions ALTER COLUMN crypto_amount TYPE MONEY USING crypto_amount::MONEY;
+ALTER TABLE transactions ALTER COLUMN fiat_amount TYPE MONEY USING fiat_amount::MONEY;
+ALTER TABLE transactions ALTER COLUMN exchange_rate TYPE MONEY USING exchange_rate::MONEY;
+ALTER TABLE transactions ALTER COLUMN payment_gateway_fee TYPE MONEY USING payment_gateway_fee::MONEY;
+ALTER TABLE transactions ALTER COLUMN exchange_spread_fee TYPE MONEY USING exchange_spread_fee::MONEY;
+ALTER TABLE transactions ALTER COLUMN partner_fee TYPE MONEY USING partner_fee::MONEY;
\ No newline at end of file
@@ -0,0 +1,21 @@
+ALTER TABLE transactions ALTER COLUMN crypto_amount TYPE NUMERIC USING crypto_amount::NUMERIC;
+ALTER TABLE transactions ALTER COLUMN fiat_amount TYPE NUMERIC USING fiat_amount::NUMERIC;
+ALTER TABLE transactions ALTER COLUMN exchange_rate TYPE NUMERIC USING exchange_rate::NUMERIC;
+ALTER TABLE transactions ALTER COLUMN payment_gateway_fee TYPE NUMERIC USING payment_gateway_fee::NUMERIC;
+ALTER TABLE transactions ALTER COLUMN exchange_spread_fee TYPE NUMERIC USING exchange_spread_fee::NUMERIC;
+ALTER TABLE transactions ALTER COLUMN partner_fee TYPE NUMERIC USING partner_fee::NUMERIC;
+
+-- The fees used to be MONEY in the locale currency, which has always been the fiat currency
+ALTER TABLE transactions ADD payment_gateway_fee_currency TEXT;
+ALTER TABLE transactions ADD exchange_spread_fee_currency TEXT;
+ALTER TABLE transactions ADD partner_fee_currency TEXT;
+UPDATE transactions SET
+    payment_gateway_fee_currency = fiat_type,
+    exchange_spread_fee_currency = fiat_type,
+    partner_fee_currency = fiat_type;
+ALTER TABLE transactions ALTER COLUMN payment_gateway_fee_currency SET NOT NULL;
+ALTER TABLE transactions ALTER COLUMN exchange_spread_fee_currency SET NOT NULL;
+ALTER TABLE transactions ALTER COLUMN partner_fee_currency SET NOT NULL;
+
+ALTER TABLE transactions ALTER COLUMN dest_crypto_address TYPE TEXT USING dest_crypto_address::TEXT;
+ALTER TABLE transactions ALTER COLUMN transcation_hash TYPE TEXT USING transcation_hash::TEXT;
\ No newline at end of file
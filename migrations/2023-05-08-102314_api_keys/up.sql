@@ -0,0 +1,22 @@
+CREATE TABLE api_keys(
+    id UUID PRIMARY KEY,
+    account_id UUID NOT NULL,
+    name TEXT NOT NULL,
+    hash TEXT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_used_at TIMESTAMP WITH TIME ZONE,
+    FOREIGN KEY (account_id)
+        REFERENCES accounts (id)
+            ON UPDATE CASCADE
+            ON DELETE CASCADE
+);
+
+CREATE TABLE api_key_scopes(
+    api_key_id UUID NOT NULL,
+    scope SCOPE NOT NULL,
+    PRIMARY KEY (api_key_id, scope),
+    FOREIGN KEY (api_key_id)
+        REFERENCES api_keys (id)
+            ON UPDATE CASCADE
+            ON DELETE CASCADE
+);
\ No newline at end of file
@@ -0,0 +1,5 @@
+ALTER TABLE sessions DROP COLUMN two_factor;
+
+DROP TABLE IF EXISTS totp_challenges;
+DROP TABLE IF EXISTS totp_recovery_codes;
+DROP TABLE IF EXISTS totp;
\ No newline at end of file
@@ -0,0 +1,33 @@
+CREATE TABLE totp(
+    account_id UUID PRIMARY KEY,
+    secret TEXT NOT NULL,
+    confirmed BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (account_id)
+        REFERENCES accounts (id)
+            ON UPDATE CASCADE
+            ON DELETE CASCADE
+);
+
+CREATE TABLE totp_recovery_codes(
+    id UUID PRIMARY KEY,
+    account_id UUID NOT NULL,
+    hash TEXT NOT NULL,
+    FOREIGN KEY (account_id)
+        REFERENCES accounts (id)
+            ON UPDATE CASCADE
+            ON DELETE CASCADE
+);
+
+CREATE TABLE totp_challenges(
+    token TEXT PRIMARY KEY,
+    account_id UUID NOT NULL,
+    password_login BOOLEAN NOT NULL DEFAULT FALSE,
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    FOREIGN KEY (account_id)
+        REFERENCES accounts (id)
+            ON UPDATE CASCADE
+            ON DELETE CASCADE
+);
+
+ALTER TABLE sessions ADD two_factor BOOLEAN NOT NULL DEFAULT FALSE;
\ No newline at end of file
@@ -0,0 +1,4 @@
+DROP TABLE IF EXISTS login_attempts;
+
+ALTER TABLE totp_challenges DROP COLUMN attempts;
+ALTER TABLE email_login_intents DROP COLUMN attempts;
\ No newline at end of file
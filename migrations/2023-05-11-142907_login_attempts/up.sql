@@ -0,0 +1,12 @@
+ALTER TABLE email_login_intents ADD attempts INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE totp_challenges ADD attempts INTEGER NOT NULL DEFAULT 0;
+
+CREATE TABLE login_attempts(
+    account_id UUID PRIMARY KEY,
+    failed_attempts INTEGER NOT NULL DEFAULT 0,
+    last_failed_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    FOREIGN KEY (account_id)
+        REFERENCES accounts (id)
+            ON UPDATE CASCADE
+            ON DELETE CASCADE
+);
\ No newline at end of file
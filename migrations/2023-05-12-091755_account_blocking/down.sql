@@ -0,0 +1,2 @@
+ALTER TABLE accounts DROP COLUMN blocked_at;
+ALTER TABLE accounts DROP COLUMN blocked_reason;
\ No newline at end of file
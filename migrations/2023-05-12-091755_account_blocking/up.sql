@@ -0,0 +1,2 @@
+ALTER TABLE accounts ADD blocked_reason TEXT;
+ALTER TABLE accounts ADD blocked_at TIMESTAMP WITH TIME ZONE;
\ No newline at end of file
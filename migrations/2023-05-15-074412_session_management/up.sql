@@ -0,0 +1,5 @@
+ALTER TABLE sessions ADD id UUID NOT NULL UNIQUE DEFAULT GEN_RANDOM_UUID();
+ALTER TABLE sessions ADD created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP;
+ALTER TABLE sessions ADD last_used_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP;
+ALTER TABLE sessions ADD user_agent TEXT;
+ALTER TABLE sessions ADD ip TEXT;
\ No newline at end of file
@@ -0,0 +1,4 @@
+ALTER TABLE password_login ADD COLUMN salt TEXT NOT NULL DEFAULT '';
+-- The salt is the fourth field of the PHC string: $argon2id$v=19$params$salt$hash
+UPDATE password_login SET salt = split_part(hash, '$', 5);
+ALTER TABLE password_login ALTER COLUMN salt DROP DEFAULT;
\ No newline at end of file
@@ -0,0 +1 @@
+ALTER TABLE password_login DROP COLUMN salt;
\ No newline at end of file
@@ -0,0 +1,11 @@
+CREATE TABLE email_change_intents(
+    account_id UUID PRIMARY KEY,
+    new_email TEXT NOT NULL,
+    code TEXT NOT NULL,
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    FOREIGN KEY (account_id)
+        REFERENCES accounts (id)
+            ON UPDATE CASCADE
+            ON DELETE CASCADE
+);
\ No newline at end of file
@@ -0,0 +1 @@
+ALTER TABLE accounts DROP COLUMN verified;
\ No newline at end of file
@@ -0,0 +1,3 @@
+ALTER TABLE accounts ADD verified BOOLEAN NOT NULL DEFAULT false;
+-- Accounts that already exist have been logged into before
+UPDATE accounts SET verified = true;
\ No newline at end of file
@@ -0,0 +1,18 @@
+CREATE TYPE EMAIL_STATUS AS ENUM ('Pending', 'Sent', 'Failed');
+
+CREATE TABLE email_outbox(
+    id UUID PRIMARY KEY DEFAULT GEN_RANDOM_UUID(),
+    email TEXT NOT NULL,
+    template TEXT NOT NULL,
+    sender TEXT NOT NULL,
+    recipients TEXT[] NOT NULL,
+    message BYTEA NOT NULL,
+    status EMAIL_STATUS NOT NULL DEFAULT 'Pending',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_error TEXT,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    sent_at TIMESTAMP WITH TIME ZONE
+);
+
+CREATE INDEX email_outbox_pending ON email_outbox (next_attempt_at) WHERE status = 'Pending';
\ No newline at end of file
@@ -0,0 +1,4 @@
+DROP TABLE IF EXISTS revoked_sessions;
+
+ALTER TABLE accounts DROP COLUMN tokens_revoked_at;
+ALTER TABLE accounts DROP COLUMN token_generation;
\ No newline at end of file
@@ -0,0 +1,8 @@
+-- Access tokens carry the generation they were issued in, bumping it revokes all of them
+ALTER TABLE accounts ADD token_generation INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE accounts ADD tokens_revoked_at TIMESTAMP WITH TIME ZONE;
+
+CREATE TABLE revoked_sessions(
+    session_id UUID PRIMARY KEY,
+    revoked_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
\ No newline at end of file
@@ -0,0 +1 @@
+DROP TABLE IF EXISTS retired_session_tokens;
\ No newline at end of file
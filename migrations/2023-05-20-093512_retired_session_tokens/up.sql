@@ -0,0 +1,11 @@
+-- The refresh tokens a session had before they were rotated, presenting one again means
+-- it was stolen
+CREATE TABLE retired_session_tokens(
+    token TEXT PRIMARY KEY,
+    session_id UUID NOT NULL,
+    retired_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (session_id)
+        REFERENCES sessions (id)
+            ON UPDATE CASCADE
+            ON DELETE CASCADE
+);
\ No newline at end of file
@@ -0,0 +1,2 @@
+DELETE FROM email_login_intents WHERE account_id IS NULL;
+ALTER TABLE email_login_intents ALTER COLUMN account_id SET NOT NULL;
\ No newline at end of file
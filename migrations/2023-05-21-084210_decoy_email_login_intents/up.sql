@@ -0,0 +1,3 @@
+-- Decoy intents have no account, they let a client make any number of wrong guesses
+-- without learning whether the email belongs to an account
+ALTER TABLE email_login_intents ALTER COLUMN account_id DROP NOT NULL;
\ No newline at end of file
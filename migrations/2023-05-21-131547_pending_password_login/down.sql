@@ -0,0 +1 @@
+ALTER TABLE email_login_intents DROP COLUMN password_hash;
\ No newline at end of file
@@ -0,0 +1,2 @@
+-- The password an account was signed up with, it is only set once the emailed code is used
+ALTER TABLE email_login_intents ADD password_hash TEXT;
\ No newline at end of file
@@ -0,0 +1,13 @@
+UPDATE email_outbox SET status = 'Pending' WHERE status = 'Sending';
+ALTER TABLE email_outbox DROP COLUMN lease_expires_at;
+
+-- A value can't be dropped from an enum, so the type is made again without it
+DROP INDEX email_outbox_pending;
+ALTER TYPE EMAIL_STATUS RENAME TO EMAIL_STATUS_OLD;
+CREATE TYPE EMAIL_STATUS AS ENUM ('Pending', 'Sent', 'Failed');
+ALTER TABLE email_outbox
+    ALTER COLUMN status DROP DEFAULT,
+    ALTER COLUMN status TYPE EMAIL_STATUS USING status::TEXT::EMAIL_STATUS,
+    ALTER COLUMN status SET DEFAULT 'Pending';
+DROP TYPE EMAIL_STATUS_OLD;
+CREATE INDEX email_outbox_pending ON email_outbox (next_attempt_at) WHERE status = 'Pending';
\ No newline at end of file
@@ -0,0 +1,4 @@
+-- An email is claimed by a worker before it is sent, a claim that outlives its lease
+-- belongs to a worker that stopped and the email is sent again
+ALTER TYPE EMAIL_STATUS ADD VALUE IF NOT EXISTS 'Sending';
+ALTER TABLE email_outbox ADD lease_expires_at TIMESTAMP WITH TIME ZONE;
\ No newline at end of file
@@ -0,0 +1,2 @@
+-- The last totp step a code was accepted for, a code is never accepted twice
+ALTER TABLE totp ADD last_step BIGINT;
\ No newline at end of file
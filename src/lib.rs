@@ -2,6 +2,8 @@
 pub mod models;
 #[cfg(feature = "db")]
 pub mod schema;
+#[cfg(all(feature = "account", feature = "db"))]
+pub mod limits;
 
 use std::collections::HashMap;
 
@@ -17,6 +19,7 @@ pub enum Microservice {
     Account,
     Storage,
     Email,
+    Transaction,
 }
 
 lazy_static::lazy_static! {
@@ -25,6 +28,7 @@ lazy_static::lazy_static! {
         (Microservice::Account, "[::1]:50052"),
         (Microservice::Storage, "[::1]:50053"),
         (Microservice::Email, "[::1]:50054"),
+        (Microservice::Transaction, "[::1]:50055"),
     ].into_iter().collect();
 }
 
@@ -62,6 +66,51 @@ macro_rules! register_tonic_clients {
 #[cfg(feature = "auth")]
 pub mod auth {
     tonic::include_proto!("auth");
+
+    /// The claims of the access tokens issued by the auth microservice.
+    #[derive(Debug, serde::Serialize, serde::Deserialize)]
+    pub struct AccessClaims {
+        /// The id of the account
+        pub sub: String,
+        /// The id of the session the token was issued for
+        pub sid: String,
+        pub scopes: Vec<String>,
+        pub customer_id: Option<String>,
+        pub retailer_id: Option<String>,
+        pub partner_id: Option<String>,
+        pub password_login: bool,
+        pub two_factor: bool,
+        /// The token generation of the account when the token was issued
+        pub gen: i32,
+        pub iat: i64,
+        pub exp: i64,
+    }
+
+    #[cfg(feature = "db")]
+    impl From<Scope> for super::models::ScopeKind {
+        fn from(val: Scope) -> super::models::ScopeKind {
+            match val {
+                Scope::Public => super::models::ScopeKind::Public,
+                Scope::Customer => super::models::ScopeKind::Customer,
+                Scope::Merchant => super::models::ScopeKind::Retailer,
+                Scope::Partner => super::models::ScopeKind::Partner,
+                Scope::Admin => super::models::ScopeKind::Admin,
+            }
+        }
+    }
+
+    #[cfg(feature = "db")]
+    impl From<super::models::ScopeKind> for Scope {
+        fn from(val: super::models::ScopeKind) -> Scope {
+            match val {
+                super::models::ScopeKind::Public => Scope::Public,
+                super::models::ScopeKind::Customer => Scope::Customer,
+                super::models::ScopeKind::Retailer => Scope::Merchant,
+                super::models::ScopeKind::Partner => Scope::Partner,
+                super::models::ScopeKind::Admin => Scope::Admin,
+            }
+        }
+    }
 }
 
 #[cfg(feature = "account")]
@@ -220,11 +269,11 @@ pub mod account {
         }
     }
 
-    impl Into<(String, BigDecimal)> for Money {
-        fn into(self) -> (String, BigDecimal) {
+    impl From<Money> for (String, BigDecimal) {
+        fn from(val: Money) -> (String, BigDecimal) {
             (
-                self.currency_code,
-                BigDecimal::new(BigInt::from_signed_bytes_le(&self.digits), self.exponent),
+                val.currency_code,
+                BigDecimal::new(BigInt::from_signed_bytes_le(&val.digits), val.exponent),
             )
         }
     }
@@ -248,9 +297,9 @@ pub mod account {
         }
     }
 
-    impl Into<BigDecimal> for Fee {
-        fn into(self) -> BigDecimal {
-            BigDecimal::new(BigInt::from_signed_bytes_le(&self.digits), self.exponent)
+    impl From<Fee> for BigDecimal {
+        fn from(val: Fee) -> BigDecimal {
+            BigDecimal::new(BigInt::from_signed_bytes_le(&val.digits), val.exponent)
         }
     }
 }
@@ -263,4 +312,94 @@ pub mod storage {
 #[cfg(feature = "email")]
 pub mod email {
     tonic::include_proto!("email");
+
+    #[cfg(feature = "db")]
+    impl From<super::models::EmailStatus> for DeliveryState {
+        fn from(val: super::models::EmailStatus) -> DeliveryState {
+            match val {
+                super::models::EmailStatus::Pending => DeliveryState::Pending,
+                super::models::EmailStatus::Sent => DeliveryState::Sent,
+                super::models::EmailStatus::Failed => DeliveryState::Failed,
+                super::models::EmailStatus::Sending => DeliveryState::Sending,
+            }
+        }
+    }
+}
+
+#[cfg(feature = "transaction")]
+pub mod transaction {
+    tonic::include_proto!("transaction");
+
+    #[cfg(feature = "db")]
+    impl From<super::models::Transaction> for TransactionData {
+        fn from(val: super::models::Transaction) -> Self {
+            TransactionData {
+                id: val.id.to_string(),
+                retailer_id: val.retailer_id.map(|id| id.to_string()),
+                retailer_transaction_id: val.retailer_transaction_id,
+                retailer_customer_id: val.retailer_customer_id,
+                source_account_wallet: val.source_account_wallet.to_string(),
+                dest_account_wallet: val.dest_account_wallet.to_string(),
+                kind: val.kind,
+                timestamp: val.timestamp.to_string(),
+                payment_method: val.payment_method,
+                crypto_network: val.crypto_network,
+                crypto_amount: Some((val.crypto_currency_type, val.crypto_amount).into()),
+                fiat_amount: Some((val.fiat_type, val.fiat_amount).into()),
+                exchange_rate: Some(val.exchange_rate.into()),
+                dest_crypto_address: val.dest_crypto_address,
+                transcation_hash: val.transcation_hash,
+                payment_gateway_fee: Some(
+                    (val.payment_gateway_fee_currency, val.payment_gateway_fee).into(),
+                ),
+                exchange_spread_fee: Some(
+                    (val.exchange_spread_fee_currency, val.exchange_spread_fee).into(),
+                ),
+                partner_fee: Some((val.partner_fee_currency, val.partner_fee).into()),
+                status: TransactionStatus::from(val.status) as i32,
+            }
+        }
+    }
+
+    #[cfg(feature = "db")]
+    impl From<TransactionStatus> for super::models::TransactionStatus {
+        fn from(val: TransactionStatus) -> super::models::TransactionStatus {
+            match val {
+                TransactionStatus::Created => super::models::TransactionStatus::Created,
+                TransactionStatus::AwaitingPayment => {
+                    super::models::TransactionStatus::AwaitingPayment
+                }
+                TransactionStatus::PaymentReceived => {
+                    super::models::TransactionStatus::PaymentReceived
+                }
+                TransactionStatus::Exchanging => super::models::TransactionStatus::Exchanging,
+                TransactionStatus::Sending => super::models::TransactionStatus::Sending,
+                TransactionStatus::Completed => super::models::TransactionStatus::Completed,
+                TransactionStatus::Failed => super::models::TransactionStatus::Failed,
+                TransactionStatus::Refunded => super::models::TransactionStatus::Refunded,
+                TransactionStatus::Expired => super::models::TransactionStatus::Expired,
+            }
+        }
+    }
+
+    #[cfg(feature = "db")]
+    impl From<super::models::TransactionStatus> for TransactionStatus {
+        fn from(val: super::models::TransactionStatus) -> TransactionStatus {
+            match val {
+                super::models::TransactionStatus::Created => TransactionStatus::Created,
+                super::models::TransactionStatus::AwaitingPayment => {
+                    TransactionStatus::AwaitingPayment
+                }
+                super::models::TransactionStatus::PaymentReceived => {
+                    TransactionStatus::PaymentReceived
+                }
+                super::models::TransactionStatus::Exchanging => TransactionStatus::Exchanging,
+                super::models::TransactionStatus::Sending => TransactionStatus::Sending,
+                super::models::TransactionStatus::Completed => TransactionStatus::Completed,
+                super::models::TransactionStatus::Failed => TransactionStatus::Failed,
+                super::models::TransactionStatus::Refunded => TransactionStatus::Refunded,
+                super::models::TransactionStatus::Expired => TransactionStatus::Expired,
+            }
+        }
+    }
 }
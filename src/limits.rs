@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+
+use bigdecimal::BigDecimal;
+use diesel::{BoolExpressionMethods, ExpressionMethods, QueryDsl};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use time::{Duration, OffsetDateTime};
+use uuid::Uuid;
+
+use crate::{
+    account::{HitLimit, LimitCheck, LimitLevel, LimitPeriod, LimitSource},
+    models, schema,
+};
+
+type LimitMap = HashMap<(LimitPeriod, LimitLevel), (String, BigDecimal)>;
+
+pub enum LimitError {
+    CustomerNotFound,
+    CurrencyMismatch(String, String),
+    QueryFailed(String),
+}
+
+/// Checks if the customer can transact `amount` without going over any of its limits.
+///
+/// Every period is checked against the limit for the customer's kyc level and the
+/// overall limit. The limit for each of them is taken from the customer limits if
+/// set, then the retailer limits and finally the global limits.
+///
+/// Running it in the transaction that inserts the new transaction, with the customer row
+/// locked, keeps concurrent transactions of the customer from passing the check together.
+pub async fn check(
+    conn: &mut AsyncPgConnection,
+    customer_id: Uuid,
+    retailer_id: Option<Uuid>,
+    (currency, amount): (String, BigDecimal),
+) -> Result<LimitCheck, LimitError> {
+    use schema::customers::dsl as c_dsl;
+
+    let (kyc_level, account_id) = c_dsl::customers
+        .filter(c_dsl::id.eq(customer_id))
+        .select((c_dsl::kyc_level, c_dsl::account_id))
+        .load::<(models::KycLevel, Option<Uuid>)>(conn)
+        .await
+        .map_err(|e| LimitError::QueryFailed(e.to_string()))?
+        .pop()
+        .ok_or(LimitError::CustomerNotFound)?;
+    let kyc_level = match kyc_level {
+        models::KycLevel::Level0 => LimitLevel::LimitKycLevel0,
+        models::KycLevel::Level1 => LimitLevel::LimitKycLevel1,
+        models::KycLevel::Level2 => LimitLevel::LimitKycLevel2,
+        models::KycLevel::Level3 => LimitLevel::LimitKycLevel3,
+    };
+
+    use schema::customer_limits::dsl as cl_dsl;
+
+    let customer_limits: LimitMap = cl_dsl::customer_limits
+        .select((
+            cl_dsl::period,
+            cl_dsl::level,
+            cl_dsl::amount,
+            cl_dsl::currency,
+        ))
+        .filter(cl_dsl::customer_id.eq(customer_id))
+        .load::<(models::LimitPeriod, models::LimitLevel, BigDecimal, String)>(conn)
+        .await
+        .map_err(|e| LimitError::QueryFailed(e.to_string()))?
+        .into_iter()
+        .map(|(period, level, amount, currency)| {
+            ((period.into(), level.into()), (currency, amount))
+        })
+        .collect();
+
+    let retailer_limits: LimitMap = if let Some(retailer_id) = retailer_id {
+        use schema::retailer_limits::dsl as rl_dsl;
+
+        rl_dsl::retailer_limits
+            .select((
+                rl_dsl::period,
+                rl_dsl::level,
+                rl_dsl::amount,
+                rl_dsl::currency,
+            ))
+            .filter(rl_dsl::retailer_id.eq(retailer_id))
+            .load::<(models::LimitPeriod, models::LimitLevel, BigDecimal, String)>(conn)
+            .await
+            .map_err(|e| LimitError::QueryFailed(e.to_string()))?
+            .into_iter()
+            .map(|(period, level, amount, currency)| {
+                ((period.into(), level.into()), (currency, amount))
+            })
+            .collect()
+    } else {
+        HashMap::new()
+    };
+
+    use schema::global_limits::dsl as gl_dsl;
+
+    let global_limits: LimitMap = gl_dsl::global_limits
+        .load::<(models::LimitPeriod, models::LimitLevel, BigDecimal, String)>(conn)
+        .await
+        .map_err(|e| LimitError::QueryFailed(e.to_string()))?
+        .into_iter()
+        .map(|(period, level, amount, currency)| {
+            ((period.into(), level.into()), (currency, amount))
+        })
+        .collect();
+
+    use schema::transactions::dsl as t_dsl;
+
+    let now = OffsetDateTime::now_utc();
+    for (period, window) in [
+        (LimitPeriod::Daily, Duration::days(1)),
+        (LimitPeriod::Weekly, Duration::weeks(1)),
+        (LimitPeriod::Monthly, Duration::days(30)),
+    ] {
+        let limits = [kyc_level, LimitLevel::Overall]
+            .into_iter()
+            .filter_map(|level| {
+                let key = (period, level);
+                if let Some(limit) = customer_limits.get(&key) {
+                    Some((level, LimitSource::CustomerLimit, limit))
+                } else if let Some(limit) = retailer_limits.get(&key) {
+                    Some((level, LimitSource::RetailerLimit, limit))
+                } else {
+                    global_limits
+                        .get(&key)
+                        .map(|limit| (level, LimitSource::GlobalLimit, limit))
+                }
+            })
+            .collect::<Vec<_>>();
+        if limits.is_empty() {
+            continue;
+        }
+
+        // A customer without an account can't have any transactions yet
+        let spent = match account_id {
+            Some(account_id) => t_dsl::transactions
+                .select(t_dsl::fiat_amount)
+                .filter(
+                    t_dsl::source_account_wallet
+                        .eq(account_id)
+                        .and(t_dsl::fiat_type.eq(&currency))
+                        .and(t_dsl::timestamp.gt(now - window))
+                        .and(t_dsl::status.ne_all([
+                            models::TransactionStatus::Failed,
+                            models::TransactionStatus::Refunded,
+                            models::TransactionStatus::Expired,
+                        ])),
+                )
+                .load::<BigDecimal>(conn)
+                .await
+                .map_err(|e| LimitError::QueryFailed(e.to_string()))?
+                .into_iter()
+                .sum(),
+            None => BigDecimal::default(),
+        };
+
+        for (level, source, (limit_currency, limit)) in limits {
+            if *limit_currency != currency {
+                return Err(LimitError::CurrencyMismatch(
+                    limit_currency.clone(),
+                    currency,
+                ));
+            }
+
+            if &spent + &amount > *limit {
+                return Ok(LimitCheck {
+                    allowed: false,
+                    hit_limit: Some(HitLimit {
+                        period: period as i32,
+                        level: level as i32,
+                        source: source as i32,
+                        limit: Some((limit_currency.clone(), limit.clone()).into()),
+                        spent: Some((currency, spent).into()),
+                    }),
+                });
+            }
+        }
+    }
+
+    Ok(LimitCheck {
+        allowed: true,
+        hit_limit: None,
+    })
+}
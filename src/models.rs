@@ -176,7 +176,7 @@ impl deserialize::FromSql<crate::schema::sql_types::LimitPeriod, Pg> for LimitPe
     }
 }
 
-#[derive(Debug, AsExpression, FromSqlRow)]
+#[derive(Debug, AsExpression, FromSqlRow, Clone, Copy)]
 #[diesel(sql_type = crate::schema::sql_types::ProfileIndex)]
 pub enum ProfileIndex {
     Zero,
@@ -219,26 +219,150 @@ impl TryFrom<usize> for ProfileIndex {
     }
 }
 
+#[derive(Debug, AsExpression, FromSqlRow, Clone, Copy, PartialEq, Eq)]
+#[diesel(sql_type = crate::schema::sql_types::TransactionStatus)]
+pub enum TransactionStatus {
+    Created = 0,
+    AwaitingPayment = 1,
+    PaymentReceived = 2,
+    Exchanging = 3,
+    Sending = 4,
+    Completed = 5,
+    Failed = 6,
+    Refunded = 7,
+    Expired = 8,
+}
+
+impl TransactionStatus {
+    /// Returns true if a transaction in this status is allowed to move to `next`.
+    pub fn can_transition_to(self, next: TransactionStatus) -> bool {
+        use TransactionStatus::*;
+
+        matches!(
+            (self, next),
+            (Created, AwaitingPayment | Failed | Expired)
+                | (AwaitingPayment, PaymentReceived | Failed | Expired)
+                | (PaymentReceived, Exchanging | Failed | Refunded)
+                | (Exchanging, Sending | Failed | Refunded)
+                | (Sending, Completed | Failed)
+                | (Completed, Refunded)
+                | (Failed, Refunded)
+        )
+    }
+}
+
+impl serialize::ToSql<crate::schema::sql_types::TransactionStatus, Pg> for TransactionStatus {
+    fn to_sql<'b>(&'b self, out: &mut serialize::Output<'b, '_, Pg>) -> serialize::Result {
+        match *self {
+            TransactionStatus::Created => out.write_all(b"Created")?,
+            TransactionStatus::AwaitingPayment => out.write_all(b"AwaitingPayment")?,
+            TransactionStatus::PaymentReceived => out.write_all(b"PaymentReceived")?,
+            TransactionStatus::Exchanging => out.write_all(b"Exchanging")?,
+            TransactionStatus::Sending => out.write_all(b"Sending")?,
+            TransactionStatus::Completed => out.write_all(b"Completed")?,
+            TransactionStatus::Failed => out.write_all(b"Failed")?,
+            TransactionStatus::Refunded => out.write_all(b"Refunded")?,
+            TransactionStatus::Expired => out.write_all(b"Expired")?,
+        }
+        Ok(serialize::IsNull::No)
+    }
+}
+
+impl deserialize::FromSql<crate::schema::sql_types::TransactionStatus, Pg> for TransactionStatus {
+    fn from_sql(bytes: PgValue) -> deserialize::Result<Self> {
+        match bytes.as_bytes() {
+            b"Created" => Ok(TransactionStatus::Created),
+            b"AwaitingPayment" => Ok(TransactionStatus::AwaitingPayment),
+            b"PaymentReceived" => Ok(TransactionStatus::PaymentReceived),
+            b"Exchanging" => Ok(TransactionStatus::Exchanging),
+            b"Sending" => Ok(TransactionStatus::Sending),
+            b"Completed" => Ok(TransactionStatus::Completed),
+            b"Failed" => Ok(TransactionStatus::Failed),
+            b"Refunded" => Ok(TransactionStatus::Refunded),
+            b"Expired" => Ok(TransactionStatus::Expired),
+            _ => Err("Unrecognized enum variant".into()),
+        }
+    }
+}
+
+#[derive(Debug, AsExpression, FromSqlRow, Clone, Copy, PartialEq, Eq)]
+#[diesel(sql_type = crate::schema::sql_types::EmailStatus)]
+pub enum EmailStatus {
+    Pending = 0,
+    Sent = 1,
+    Failed = 2,
+    Sending = 3,
+}
+
+impl serialize::ToSql<crate::schema::sql_types::EmailStatus, Pg> for EmailStatus {
+    fn to_sql<'b>(&'b self, out: &mut serialize::Output<'b, '_, Pg>) -> serialize::Result {
+        match *self {
+            EmailStatus::Pending => out.write_all(b"Pending")?,
+            EmailStatus::Sent => out.write_all(b"Sent")?,
+            EmailStatus::Failed => out.write_all(b"Failed")?,
+            EmailStatus::Sending => out.write_all(b"Sending")?,
+        }
+        Ok(serialize::IsNull::No)
+    }
+}
+
+impl deserialize::FromSql<crate::schema::sql_types::EmailStatus, Pg> for EmailStatus {
+    fn from_sql(bytes: PgValue) -> deserialize::Result<Self> {
+        match bytes.as_bytes() {
+            b"Pending" => Ok(EmailStatus::Pending),
+            b"Sent" => Ok(EmailStatus::Sent),
+            b"Failed" => Ok(EmailStatus::Failed),
+            b"Sending" => Ok(EmailStatus::Sending),
+            _ => Err("Unrecognized enum variant".into()),
+        }
+    }
+}
+
 #[derive(Queryable, Insertable)]
 #[diesel(table_name = schema::accounts)]
 pub struct Account<'s> {
     pub id: Uuid,
     pub email: &'s str,
+    pub verified: bool,
 }
 
-#[derive(Queryable)]
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = schema::scopes)]
 pub struct Scope {
     pub account_id: Uuid,
     pub scope: ScopeKind,
 }
 
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = schema::email_change_intents)]
+pub struct EmailChangeIntent<'s> {
+    pub account_id: Uuid,
+    pub new_email: &'s str,
+    pub code: &'s str,
+    pub expires_at: OffsetDateTime,
+    pub attempts: i32,
+}
+
 #[derive(Queryable, Insertable)]
 #[diesel(table_name = schema::email_login_intents)]
 pub struct EmailLoginIntent<'s> {
     pub id: Uuid,
-    pub account_id: Uuid,
+    pub account_id: Option<Uuid>,
     pub pass_key: &'s str,
     pub expires_at: OffsetDateTime,
+    pub attempts: i32,
+    pub password_hash: Option<&'s str>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = schema::email_outbox)]
+pub struct NewOutboxEmail<'s> {
+    pub id: Uuid,
+    pub email: &'s str,
+    pub template: &'s str,
+    pub sender: &'s str,
+    pub recipients: Vec<&'s str>,
+    pub message: &'s [u8],
 }
 
 #[derive(Queryable, Insertable)]
@@ -254,10 +378,27 @@ pub struct NewPassLoginIntent<'s> {
 pub struct PasswordLogin<'s> {
     pub account_id: Uuid,
     pub hash: &'s str,
-    pub salt: &'s str,
     pub created_at: OffsetDateTime,
 }
 
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = schema::api_keys)]
+pub struct ApiKey<'s> {
+    pub id: Uuid,
+    pub account_id: Uuid,
+    pub name: &'s str,
+    pub hash: &'s str,
+    pub created_at: OffsetDateTime,
+    pub last_used_at: Option<OffsetDateTime>,
+}
+
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = schema::api_key_scopes)]
+pub struct ApiKeyScope {
+    pub api_key_id: Uuid,
+    pub scope: ScopeKind,
+}
+
 #[derive(Queryable, Insertable)]
 #[diesel(table_name = schema::sessions)]
 pub struct Session<'s> {
@@ -265,6 +406,39 @@ pub struct Session<'s> {
     pub account_id: Uuid,
     pub password_login: bool,
     pub expires_at: OffsetDateTime,
+    pub two_factor: bool,
+    pub id: Uuid,
+    pub created_at: OffsetDateTime,
+    pub last_used_at: OffsetDateTime,
+    pub user_agent: Option<&'s str>,
+    pub ip: Option<&'s str>,
+}
+
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = schema::totp)]
+pub struct Totp<'s> {
+    pub account_id: Uuid,
+    pub secret: &'s str,
+    pub confirmed: bool,
+    pub created_at: OffsetDateTime,
+}
+
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = schema::totp_recovery_codes)]
+pub struct TotpRecoveryCode<'s> {
+    pub id: Uuid,
+    pub account_id: Uuid,
+    pub hash: &'s str,
+}
+
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = schema::totp_challenges)]
+pub struct TotpChallenge<'s> {
+    pub token: &'s str,
+    pub account_id: Uuid,
+    pub password_login: bool,
+    pub expires_at: OffsetDateTime,
+    pub attempts: i32,
 }
 
 #[derive(Queryable, Insertable)]
@@ -318,3 +492,32 @@ pub struct GlobalLimit<'rl> {
     pub amount: BigDecimal,
     pub currency: &'rl str,
 }
+
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = schema::transactions)]
+pub struct Transaction {
+    pub id: Uuid,
+    pub retailer_id: Option<Uuid>,
+    pub retailer_transaction_id: Option<String>,
+    pub retailer_customer_id: Option<String>,
+    pub source_account_wallet: Uuid,
+    pub dest_account_wallet: Uuid,
+    pub kind: String,
+    pub timestamp: OffsetDateTime,
+    pub payment_method: String,
+    pub crypto_currency_type: String,
+    pub crypto_network: String,
+    pub crypto_amount: BigDecimal,
+    pub fiat_type: String,
+    pub fiat_amount: BigDecimal,
+    pub exchange_rate: BigDecimal,
+    pub dest_crypto_address: String,
+    pub transcation_hash: String,
+    pub payment_gateway_fee: BigDecimal,
+    pub exchange_spread_fee: BigDecimal,
+    pub partner_fee: BigDecimal,
+    pub status: TransactionStatus,
+    pub payment_gateway_fee_currency: String,
+    pub exchange_spread_fee_currency: String,
+    pub partner_fee_currency: String,
+}
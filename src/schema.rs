@@ -1,29 +1,37 @@
 // @generated automatically by Diesel CLI.
 
 pub mod sql_types {
-    #[derive(diesel::sql_types::SqlType)]
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
     #[diesel(postgres_type(name = "approval"))]
     pub struct Approval;
 
-    #[derive(diesel::sql_types::SqlType)]
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "email_status"))]
+    pub struct EmailStatus;
+
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
     #[diesel(postgres_type(name = "kyc_level"))]
     pub struct KycLevel;
 
-    #[derive(diesel::sql_types::SqlType)]
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
     #[diesel(postgres_type(name = "limit_level"))]
     pub struct LimitLevel;
 
-    #[derive(diesel::sql_types::SqlType)]
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
     #[diesel(postgres_type(name = "limit_period"))]
     pub struct LimitPeriod;
 
-    #[derive(diesel::sql_types::SqlType)]
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
     #[diesel(postgres_type(name = "profile_index"))]
     pub struct ProfileIndex;
 
-    #[derive(diesel::sql_types::SqlType)]
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
     #[diesel(postgres_type(name = "scope"))]
     pub struct Scope;
+
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "transaction_status"))]
+    pub struct TransactionStatus;
 }
 
 diesel::table! {
@@ -32,6 +40,32 @@ diesel::table! {
         email -> Text,
         created_at -> Timestamptz,
         blocked -> Bool,
+        blocked_reason -> Nullable<Text>,
+        blocked_at -> Nullable<Timestamptz>,
+        verified -> Bool,
+        token_generation -> Int4,
+        tokens_revoked_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::Scope;
+
+    api_key_scopes (api_key_id, scope) {
+        api_key_id -> Uuid,
+        scope -> Scope,
+    }
+}
+
+diesel::table! {
+    api_keys (id) {
+        id -> Uuid,
+        account_id -> Uuid,
+        name -> Text,
+        hash -> Text,
+        created_at -> Timestamptz,
+        last_used_at -> Nullable<Timestamptz>,
     }
 }
 
@@ -115,12 +149,45 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    email_change_intents (account_id) {
+        account_id -> Uuid,
+        new_email -> Text,
+        code -> Text,
+        expires_at -> Timestamptz,
+        attempts -> Int4,
+    }
+}
+
 diesel::table! {
     email_login_intents (id) {
         id -> Uuid,
-        account_id -> Uuid,
+        account_id -> Nullable<Uuid>,
         pass_key -> Text,
         expires_at -> Timestamptz,
+        attempts -> Int4,
+        password_hash -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::EmailStatus;
+
+    email_outbox (id) {
+        id -> Uuid,
+        email -> Text,
+        template -> Text,
+        sender -> Text,
+        recipients -> Array<Nullable<Text>>,
+        message -> Bytea,
+        status -> EmailStatus,
+        attempts -> Int4,
+        next_attempt_at -> Timestamptz,
+        last_error -> Nullable<Text>,
+        created_at -> Timestamptz,
+        sent_at -> Nullable<Timestamptz>,
+        lease_expires_at -> Nullable<Timestamptz>,
     }
 }
 
@@ -180,6 +247,14 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    login_attempts (account_id) {
+        account_id -> Uuid,
+        failed_attempts -> Int4,
+        last_failed_at -> Timestamptz,
+    }
+}
+
 diesel::table! {
     new_pass_login_intents (id) {
         id -> Text,
@@ -214,7 +289,6 @@ diesel::table! {
     password_login (account_id) {
         account_id -> Uuid,
         hash -> Text,
-        salt -> Text,
         created_at -> Timestamptz,
     }
 }
@@ -332,16 +406,68 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    retired_session_tokens (token) {
+        token -> Text,
+        session_id -> Uuid,
+        retired_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    revoked_sessions (session_id) {
+        session_id -> Uuid,
+        revoked_at -> Timestamptz,
+    }
+}
+
 diesel::table! {
     sessions (token) {
         token -> Text,
         account_id -> Uuid,
         password_login -> Bool,
         expires_at -> Timestamptz,
+        two_factor -> Bool,
+        id -> Uuid,
+        created_at -> Timestamptz,
+        last_used_at -> Timestamptz,
+        user_agent -> Nullable<Text>,
+        ip -> Nullable<Text>,
     }
 }
 
 diesel::table! {
+    totp (account_id) {
+        account_id -> Uuid,
+        secret -> Text,
+        confirmed -> Bool,
+        created_at -> Timestamptz,
+        last_step -> Nullable<Int8>,
+    }
+}
+
+diesel::table! {
+    totp_challenges (token) {
+        token -> Text,
+        account_id -> Uuid,
+        password_login -> Bool,
+        expires_at -> Timestamptz,
+        attempts -> Int4,
+    }
+}
+
+diesel::table! {
+    totp_recovery_codes (id) {
+        id -> Uuid,
+        account_id -> Uuid,
+        hash -> Text,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::TransactionStatus;
+
     transactions (id) {
         id -> Uuid,
         retailer_id -> Nullable<Uuid>,
@@ -354,19 +480,24 @@ diesel::table! {
         payment_method -> Text,
         crypto_currency_type -> Text,
         crypto_network -> Text,
-        crypto_amount -> Money,
+        crypto_amount -> Numeric,
         fiat_type -> Text,
-        fiat_amount -> Money,
-        exchange_rate -> Money,
-        dest_crypto_address -> Int4,
-        transcation_hash -> Int4,
-        payment_gateway_fee -> Money,
-        exchange_spread_fee -> Money,
-        partner_fee -> Money,
-        status -> Int4,
+        fiat_amount -> Numeric,
+        exchange_rate -> Numeric,
+        dest_crypto_address -> Text,
+        transcation_hash -> Text,
+        payment_gateway_fee -> Numeric,
+        exchange_spread_fee -> Numeric,
+        partner_fee -> Numeric,
+        status -> TransactionStatus,
+        payment_gateway_fee_currency -> Text,
+        exchange_spread_fee_currency -> Text,
+        partner_fee_currency -> Text,
     }
 }
 
+diesel::joinable!(api_key_scopes -> api_keys (api_key_id));
+diesel::joinable!(api_keys -> accounts (account_id));
 diesel::joinable!(customer_custody_provider_routing -> custody_providers (selected));
 diesel::joinable!(customer_custody_provider_routing -> customers (customer_id));
 diesel::joinable!(customer_exchange_provider_routing -> customers (customer_id));
@@ -375,10 +506,12 @@ diesel::joinable!(customer_limits -> customers (customer_id));
 diesel::joinable!(customer_payment_gateway_routing -> customers (customer_id));
 diesel::joinable!(customer_payment_gateway_routing -> payment_gateways (selected));
 diesel::joinable!(customers -> accounts (account_id));
+diesel::joinable!(email_change_intents -> accounts (account_id));
 diesel::joinable!(email_login_intents -> accounts (account_id));
 diesel::joinable!(global_custody_provider_routing -> custody_providers (selected));
 diesel::joinable!(global_exchange_provider_routing -> exchange_providers (selected));
 diesel::joinable!(global_payment_gateway_routing -> payment_gateways (selected));
+diesel::joinable!(login_attempts -> accounts (account_id));
 diesel::joinable!(new_pass_login_intents -> accounts (account_id));
 diesel::joinable!(partner_fees -> partners (partner_id));
 diesel::joinable!(partner_fees -> payment_methods (payment_method_id));
@@ -398,22 +531,30 @@ diesel::joinable!(retailer_payment_gateway_routing -> retailers (retailer_id));
 diesel::joinable!(retailers -> accounts (account_id));
 diesel::joinable!(scopes -> accounts (account_id));
 diesel::joinable!(sessions -> accounts (account_id));
+diesel::joinable!(totp -> accounts (account_id));
+diesel::joinable!(totp_challenges -> accounts (account_id));
+diesel::joinable!(totp_recovery_codes -> accounts (account_id));
 diesel::joinable!(transactions -> retailers (retailer_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
     accounts,
+    api_key_scopes,
+    api_keys,
     custody_providers,
     customer_custody_provider_routing,
     customer_exchange_provider_routing,
     customer_limits,
     customer_payment_gateway_routing,
     customers,
+    email_change_intents,
     email_login_intents,
+    email_outbox,
     exchange_providers,
     global_custody_provider_routing,
     global_exchange_provider_routing,
     global_limits,
     global_payment_gateway_routing,
+    login_attempts,
     new_pass_login_intents,
     partner_fees,
     partners,
@@ -427,7 +568,12 @@ diesel::allow_tables_to_appear_in_same_query!(
     retailer_partners,
     retailer_payment_gateway_routing,
     retailers,
+    retired_session_tokens,
+    revoked_sessions,
     scopes,
     sessions,
+    totp,
+    totp_challenges,
+    totp_recovery_codes,
     transactions,
 );